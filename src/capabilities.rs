@@ -0,0 +1,184 @@
+use super::*;
+use std::env;
+use std::f64::consts;
+use std::fs;
+use std::io::Write;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Longest string in bytes `string.repeat` builds, so a script can't make
+/// the host run out of memory.
+pub const MAX_REPEAT_LEN: usize = 1 << 24;
+
+/// Group of externals a script can be granted, so untrusted scripts only
+/// get the access they need.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    /// The `math` module, without side effects.
+    Math,
+    /// The `string` module, without side effects.
+    String,
+    /// `fs.read` and `fs.exists`.
+    FsRead,
+    /// `fs.write`, `fs.append` and `fs.remove`.
+    FsWrite,
+    /// The `clock` module.
+    Clock,
+    /// `env` and `hasEnv`, reading environment variables.
+    Env,
+}
+
+pub const CAPABILITIES: [Capability; 6] = [
+    Capability::Math,
+    Capability::String,
+    Capability::FsRead,
+    Capability::FsWrite,
+    Capability::Clock,
+    Capability::Env,
+];
+
+/// Groups granted when `--allow` isn't given, the ones without side effects
+/// or access to the host.
+pub const DEFAULT_CAPABILITIES: [Capability; 2] = [Capability::Math, Capability::String];
+
+impl Capability {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Capability::Math => "math",
+            Capability::String => "string",
+            Capability::FsRead => "fs-read",
+            Capability::FsWrite => "fs-write",
+            Capability::Clock => "clock",
+            Capability::Env => "env",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Capability> {
+        CAPABILITIES.iter().cloned().find(|c| c.name() == name)
+    }
+
+    /// Adds the externals of the group to `externals`.
+    pub fn register(&self, externals: &mut Externals) {
+        match self {
+            Capability::Math => {
+                externals
+                    .module("math")
+                    .add_constant("PI", consts::PI)
+                    .add_constant("E", consts::E)
+                    .register("sin", f64::sin)
+                    .register("cos", f64::cos)
+                    .register("sqrt", f64::sqrt)
+                    .register("abs", f64::abs)
+                    .register("floor", f64::floor)
+                    .register("pow", f64::powf)
+                    .register("min", f64::min)
+                    .register("max", f64::max);
+            }
+            Capability::String => {
+                externals
+                    .module("string")
+                    .register("len", |s: String| -> f64 { s.chars().count() as f64 })
+                    .register("upper", |s: String| -> String { s.to_uppercase() })
+                    .register("lower", |s: String| -> String { s.to_lowercase() })
+                    .register("trim", |s: String| -> String { s.trim().to_string() })
+                    .register("contains", |s: String, part: String| -> bool {
+                        s.contains(&part)
+                    })
+                    .register("concat", |a: String, b: String| -> String { a + &b })
+                    .register(
+                        "repeat",
+                        |s: String, n: f64| -> Result<String, ExternalError> {
+                            if n < 0.0 || n.fract() != 0.0 {
+                                return Err(format!("cannot repeat a string {} times", n).into());
+                            }
+                            match s.len().checked_mul(n as usize) {
+                                Some(len) if len <= MAX_REPEAT_LEN => Ok(s.repeat(n as usize)),
+                                _ => Err(format!(
+                                    "repeating a string {} times makes it longer than {} bytes",
+                                    n, MAX_REPEAT_LEN
+                                )
+                                .into()),
+                            }
+                        },
+                    );
+            }
+            Capability::FsRead => {
+                externals
+                    .module("fs")
+                    .register("read", |path: String| -> Result<String, ExternalError> {
+                        fs::read_to_string(&path)
+                            .map_err(|error| format!("could not read {}: {}", path, error).into())
+                    })
+                    .register("exists", |path: String| -> bool {
+                        fs::metadata(path).is_ok()
+                    });
+            }
+            Capability::FsWrite => {
+                externals
+                    .module("fs")
+                    .register(
+                        "write",
+                        |path: String, contents: String| -> Result<(), ExternalError> {
+                            fs::write(&path, contents).map_err(|error| {
+                                format!("could not write {}: {}", path, error).into()
+                            })
+                        },
+                    )
+                    .register(
+                        "append",
+                        |path: String, contents: String| -> Result<(), ExternalError> {
+                            fs::OpenOptions::new()
+                                .append(true)
+                                .create(true)
+                                .open(&path)
+                                .and_then(|mut file| file.write_all(contents.as_bytes()))
+                                .map_err(|error| {
+                                    format!("could not write {}: {}", path, error).into()
+                                })
+                        },
+                    )
+                    .register("remove", |path: String| -> Result<(), ExternalError> {
+                        fs::remove_file(&path)
+                            .map_err(|error| format!("could not remove {}: {}", path, error).into())
+                    });
+            }
+            Capability::Clock => {
+                let start = Instant::now();
+                externals
+                    .module("clock")
+                    .register("now", || -> f64 {
+                        let now = SystemTime::now().duration_since(UNIX_EPOCH);
+                        now.map(|now| now.as_secs_f64()).unwrap_or(0.0)
+                    })
+                    .register("elapsed", move || -> f64 { start.elapsed().as_secs_f64() });
+            }
+            Capability::Env => {
+                // scripts have no optional values, unset variables read as
+                // empty and hasEnv tells them apart
+                externals.register("env", |name: String| -> String {
+                    env::var(name).unwrap_or_default()
+                });
+                externals.register("hasEnv", |name: String| -> bool { env::var(name).is_ok() });
+            }
+        }
+    }
+}
+
+impl Externals {
+    /// Externals with the groups in `granted`. The externals of the other
+    /// groups are known to the type checker, which rejects scripts using
+    /// them.
+    pub fn with_capabilities(granted: &[Capability]) -> Externals {
+        let mut externals = Externals::new();
+        for capability in CAPABILITIES.iter() {
+            if granted.contains(capability) {
+                capability.register(&mut externals);
+            }
+        }
+        for capability in CAPABILITIES.iter() {
+            if !granted.contains(capability) {
+                externals.deny(*capability);
+            }
+        }
+        externals
+    }
+}
@@ -18,8 +18,11 @@ pub enum OpCode {
     Pop { width: u8 },
     Not,
     Equal { width: u8 },
+    EqualF64,
     GreaterF64,
+    GreaterEqualF64,
     LesserF64,
+    LesserEqualF64,
     PrintF64,
     PrintBool,
     PrintString,
@@ -39,14 +42,42 @@ pub enum OpCode {
     Heapify { width: u8 },
     Closure { chunk_i: ChunkAdr, capture_len: u8 },
     FromHeap { stack_i: StackAdr },
+    Assert { assertion_i: DataAdr },
+    AssertEqF64 { assertion_i: DataAdr },
+    AssertEqBool { assertion_i: DataAdr },
+    AssertEqString { assertion_i: DataAdr },
+    AssertEq { width: u8, assertion_i: DataAdr },
 }
 
 pub type CodeAdr = u16;
 pub type DataAdr = u16;
 
+/// Message and location reported when an assertion fails.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assertion {
+    pub message: String,
+    pub span: Span,
+}
+
+/// Name and signature of a top level function, for calls from the host.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptFunction {
+    pub name: String,
+    pub args_t: Vec<AstType>,
+    pub ret_t: AstType,
+}
+
+impl ScriptFunction {
+    pub fn ast_type(&self) -> AstType {
+        AstType::Function(self.args_t.clone(), Box::new(self.ret_t.clone()))
+    }
+}
+
+#[derive(Clone)]
 pub struct Data {
     floats: Vec<f64>,
     strings: Vec<String>,
+    assertions: Vec<Assertion>,
 }
 
 impl Data {
@@ -54,19 +85,31 @@ impl Data {
         Data {
             floats: Vec::new(),
             strings: Vec::new(),
+            assertions: Vec::new(),
         }
     }
 }
 
+#[derive(Clone)]
 pub struct Chunk {
     code: Vec<OpCode>,
     data: Data,
+    /// Location of each call, in the order of the code, for the call trace
+    /// of runtime errors.
+    call_spans: Vec<(CodeAdr, Span)>,
+    /// Frame slots holding objects at each op that can fail, in the order of
+    /// the code, for releasing them when it does.
+    live_objects: Vec<(CodeAdr, Vec<StackAdr>)>,
+    function: Option<ScriptFunction>,
 }
 impl Chunk {
     pub fn new() -> Chunk {
         Chunk {
             code: Vec::new(),
             data: Data::new(),
+            call_spans: Vec::new(),
+            live_objects: Vec::new(),
+            function: None,
         }
     }
     pub fn len_code(&self) -> CodeAdr {
@@ -113,4 +156,41 @@ impl Chunk {
     pub fn get_const_string(&self, i: u16) -> String {
         self.data.strings[i as usize].clone()
     }
+    pub fn add_assertion(&mut self, assertion: Assertion) -> DataAdr {
+        self.data.assertions.push(assertion);
+        self.data.assertions.len() as DataAdr - 1
+    }
+    pub fn get_assertion(&self, i: DataAdr) -> &Assertion {
+        &self.data.assertions[i as usize]
+    }
+    /// Marks the chunk as the code of a top level function.
+    pub fn set_function(&mut self, function: ScriptFunction) {
+        self.function = Some(function);
+    }
+    pub fn function(&self) -> Option<&ScriptFunction> {
+        self.function.as_ref()
+    }
+    pub fn push_call(&mut self, op: OpCode, span: Span) -> CodeAdr {
+        let ip = self.push_op(op);
+        self.call_spans.push((ip, span));
+        ip
+    }
+    pub fn get_call_span(&self, ip: CodeAdr) -> Option<Span> {
+        self.call_spans
+            .binary_search_by_key(&ip, |(call_ip, _)| *call_ip)
+            .ok()
+            .map(|i| self.call_spans[i].1)
+    }
+    pub fn add_live_objects(&mut self, ip: CodeAdr, slots: Vec<StackAdr>) {
+        self.live_objects.push((ip, slots));
+    }
+    pub fn get_live_objects(&self, ip: CodeAdr) -> &[StackAdr] {
+        match self
+            .live_objects
+            .binary_search_by_key(&ip, |(object_ip, _)| *object_ip)
+        {
+            Ok(i) => &self.live_objects[i].1,
+            Err(_) => &[],
+        }
+    }
 }
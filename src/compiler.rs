@@ -9,42 +9,117 @@ struct LocalVariable {
     offset: StackAdr,
     t: AstType,
 }
+/// A value kept on the stack while the rest of an expression is evaluated.
+#[derive(Debug, Clone, Copy)]
+struct Temporary {
+    width: StackAdr,
+    is_obj: bool,
+}
 #[derive(Debug, Clone)]
 enum GlobalVariable {
     Function(ChunkAdr),
     External(ExternalAdr),
     EnumVariant(u8, usize),
+    Constant(ExternalConstant),
 }
 enum Variable {
     Local(LocalVariable),
     Global(GlobalVariable),
 }
+/// A test block compiled into its own entry chunk.
+#[derive(Debug, Clone)]
+pub struct CompiledTest {
+    pub name: String,
+    pub entry: ChunkAdr,
+}
 pub struct Compiler<'a> {
     variables: Vec<LocalVariable>,
     globals: HashMap<String, GlobalVariable>,
     externals: Option<&'a Externals>,
     current_scope_depth: u16,
+    temporaries: Vec<Temporary>,
     chunks: Vec<Chunk>,
+    chunks_offset: ChunkAdr,
     current_chunk: ChunkAdr,
     is_root: bool,
 }
 
 impl<'a> Compiler<'a> {
-    pub fn compile(ast: &Ast, externals: Option<&'a Externals>) -> Vec<Chunk> {
-        let mut compiler = Compiler {
+    pub fn new(externals: Option<&'a Externals>) -> Compiler<'a> {
+        Compiler {
             variables: Vec::new(),
             globals: HashMap::new(),
             externals,
             current_scope_depth: 0,
-            chunks: vec![Chunk::new()],
+            temporaries: Vec::new(),
+            chunks: Vec::new(),
+            chunks_offset: 0,
             current_chunk: 0,
             is_root: true,
-        };
-        compiler.codegen(ast);
-        compiler.chunks
+        }
+    }
+    pub fn compile(ast: &Ast, externals: Option<&'a Externals>) -> Vec<Chunk> {
+        let mut compiler = Compiler::new(externals);
+        compiler.compile_entry(ast);
+        compiler.take_chunks()
+    }
+    /// Compiles `ast` into a new entry chunk and returns its address. Globals
+    /// and root locals from earlier calls are kept, so chunks compiled this
+    /// way can be run one after another on the same vm.
+    pub fn compile_entry(&mut self, ast: &Ast) -> ChunkAdr {
+        self.chunks.push(Chunk::new());
+        self.current_chunk = self.next_chunk_adr() - 1;
+        self.codegen(ast);
+        self.current_chunk
+    }
+    /// Compiles the program and then every top level test block into its own
+    /// entry chunk. Tests see the functions and enums of the program but not
+    /// its variables.
+    pub fn compile_tests(
+        ast: &Ast,
+        externals: Option<&'a Externals>,
+    ) -> (Vec<Chunk>, Vec<CompiledTest>) {
+        let mut compiler = Compiler::new(externals);
+        compiler.compile_entry(ast);
+        let mut tests = vec![];
+        if let Ast::Program(stmts) = ast {
+            for stmt in stmts.iter() {
+                if let Ast::Test { name, body, .. } = stmt {
+                    compiler.chunks.push(Chunk::new());
+                    compiler.current_chunk = compiler.next_chunk_adr() - 1;
+                    let root_variables = mem::take(&mut compiler.variables);
+                    compiler.codegen(body);
+                    compiler.push_return(&None, &AstType::Nil);
+                    compiler.variables = root_variables;
+                    tests.push(CompiledTest {
+                        name: name.clone(),
+                        entry: compiler.current_chunk,
+                    });
+                }
+            }
+        }
+        (compiler.take_chunks(), tests)
+    }
+    /// Number of root variables, used to forget the variables of an entry
+    /// whose run failed.
+    pub fn variable_count(&self) -> usize {
+        self.variables.len()
+    }
+    pub fn truncate_variables(&mut self, count: usize) {
+        self.variables.truncate(count);
+    }
+    /// Hands over the chunks compiled since the last call, chunk addresses
+    /// continue from where the previous batch ended.
+    pub fn take_chunks(&mut self) -> Vec<Chunk> {
+        self.chunks_offset = self.next_chunk_adr();
+        mem::take(&mut self.chunks)
+    }
+    fn next_chunk_adr(&self) -> ChunkAdr {
+        self.chunks_offset + self.chunks.len() as ChunkAdr
     }
     fn chunk(&mut self) -> &mut Chunk {
-        &mut self.chunks[self.current_chunk as usize]
+        let i = self.current_chunk - self.chunks_offset;
+        &mut self.chunks[i as usize]
     }
     fn declare_variable(&mut self, name: &String, t: AstType) {
         self.variables.push(LocalVariable {
@@ -74,11 +149,40 @@ impl<'a> Compiler<'a> {
         if global.is_some() {
             return global;
         }
+        let constant = self
+            .externals
+            .and_then(|ext| ext.lookup_constant(name))
+            .map(|constant| Variable::Global(GlobalVariable::Constant(constant.clone())));
+        if constant.is_some() {
+            return constant;
+        }
         self.externals
             .map(|ext| ext.lookup_function(name))
             .flatten()
             .map(|t| Variable::Global(GlobalVariable::External(t)))
     }
+    /// Inlines the value of an external constant.
+    fn push_constant(&mut self, constant: &ExternalConstant) {
+        match constant {
+            ExternalConstant::Float(n) => {
+                let i = self.chunk().add_const_f64(*n);
+                self.chunk().push_op(OpCode::ConstantF64 { data_i: i });
+            }
+            ExternalConstant::Bool(true) => {
+                self.chunk().push_op(OpCode::True);
+            }
+            ExternalConstant::Bool(false) => {
+                self.chunk().push_op(OpCode::False);
+            }
+            ExternalConstant::String(s) => {
+                let i = self.chunk().add_const_string(s);
+                self.chunk().push_op(OpCode::ConstantString { data_i: i });
+            }
+            ExternalConstant::Tag { variant, .. } => {
+                self.chunk().push_op(OpCode::PushU8 { data: *variant });
+            }
+        }
+    }
     fn pop_type(&mut self, t: &AstType) {
         match t {
             AstType::Bool
@@ -90,14 +194,17 @@ impl<'a> Compiler<'a> {
                     width: t.width() as u8,
                 });
             }
-            AstType::Closure(..) | AstType::HeapAllocated(_) | AstType::String => {
+            AstType::Closure(..)
+            | AstType::HeapAllocated(_)
+            | AstType::String
+            | AstType::Foreign(_) => {
                 self.chunk().push_op(OpCode::DecreaseRC);
                 self.chunk().push_op(OpCode::Pop {
                     width: t.width() as u8,
                 });
             }
             AstType::Nil => {}
-            AstType::Unresolved(..) => todo!(),
+            AstType::Unresolved(..) | AstType::Unknown => todo!(),
         };
     }
     fn push_return(&mut self, expr: &Option<Box<Ast>>, t: &AstType) {
@@ -131,6 +238,122 @@ impl<'a> Compiler<'a> {
             self.pop_type(&t);
         }
     }
+    /// Type of the value `ast` leaves on the stack. Enum variants are left
+    /// `Unresolved`, see `temporary`.
+    fn value_type(&self, ast: &Ast) -> AstType {
+        match ast {
+            Ast::Float(..) | Ast::Negate(..) => AstType::Float,
+            Ast::Bool(..)
+            | Ast::Not(..)
+            | Ast::Equal(..)
+            | Ast::NotEqual(..)
+            | Ast::Greater(..)
+            | Ast::GreaterEqual(..)
+            | Ast::Lesser(..)
+            | Ast::LesserEqual(..)
+            | Ast::And(..)
+            | Ast::Or(..) => AstType::Bool,
+            Ast::String(..) => AstType::String,
+            Ast::Variable { t, .. } | Ast::Assign { t, .. } => match t.as_ref().unwrap() {
+                AstType::HeapAllocated(t) => (**t).clone(),
+                t => t.clone(),
+            },
+            Ast::Multiply(_, _, t, _)
+            | Ast::Divide(_, _, t, _)
+            | Ast::Add(_, _, t, _)
+            | Ast::Sub(_, _, t, _) => t.clone().unwrap(),
+            Ast::Function {
+                args,
+                captured,
+                ret_t,
+                ..
+            } => {
+                let args = args.iter().map(|(_, t, _)| t.clone()).collect();
+                if captured.is_empty() {
+                    AstType::Function(args, Box::new(ret_t.clone()))
+                } else {
+                    AstType::Closure(args, Box::new(ret_t.clone()))
+                }
+            }
+            Ast::Call { ident, .. } => match self.value_type(ident) {
+                AstType::Function(_, ret_t)
+                | AstType::Closure(_, ret_t)
+                | AstType::ExternalFunction(_, ret_t) => *ret_t,
+                t => t,
+            },
+            _ => panic!("{:?} is not an expression", ast),
+        }
+    }
+    fn temporary(&self, ast: &Ast) -> Temporary {
+        let padding = |ident: &Ast| match ident {
+            Ast::Variable { name, .. } => match self.globals.get(name) {
+                Some(GlobalVariable::EnumVariant(_, padding)) => *padding as StackAdr,
+                _ => panic!(),
+            },
+            _ => panic!(),
+        };
+        let tag_width = u8::width() as StackAdr;
+        match ast {
+            Ast::Call {
+                ident,
+                args_width,
+                call_t: Some(CallType::Enum),
+                ..
+            } => Temporary {
+                width: tag_width + args_width.unwrap() as StackAdr + padding(ident),
+                is_obj: false,
+            },
+            Ast::Variable {
+                t: Some(AstType::Unresolved(_)),
+                ..
+            } => Temporary {
+                width: tag_width + padding(ast),
+                is_obj: false,
+            },
+            _ => {
+                let t = self.value_type(ast);
+                Temporary {
+                    width: t.width() as StackAdr,
+                    is_obj: t.is_obj(),
+                }
+            }
+        }
+    }
+    /// Pushes both operands of a binary operator of type `t`.
+    fn operands(&mut self, l: &Ast, r: &Ast, t: &Option<AstType>) {
+        self.codegen(l);
+        let t = t.as_ref().unwrap();
+        self.temporaries.push(Temporary {
+            width: t.width() as StackAdr,
+            is_obj: t.is_obj(),
+        });
+        self.codegen(r);
+        self.temporaries.pop();
+    }
+    /// Records the objects on the frame when the op at `ip` runs, which the
+    /// vm releases if the op fails.
+    fn mark_live_objects(&mut self, ip: CodeAdr) {
+        let mut slots: Vec<StackAdr> = self
+            .variables
+            .iter()
+            .filter(|v| v.t.is_obj())
+            .map(|v| v.offset)
+            .collect();
+        let mut offset = self
+            .variables
+            .last()
+            .map(|v| v.offset + v.t.width() as StackAdr)
+            .unwrap_or(0);
+        for temporary in self.temporaries.iter() {
+            if temporary.is_obj {
+                slots.push(offset);
+            }
+            offset += temporary.width;
+        }
+        if !slots.is_empty() {
+            self.chunk().add_live_objects(ip, slots);
+        }
+    }
     fn codegen(&mut self, ast: &Ast) {
         match ast {
             Ast::Program(ps) => {
@@ -161,26 +384,73 @@ impl<'a> Compiler<'a> {
                     | AstType::HeapAllocated(_)
                     | AstType::Function(..)
                     | AstType::EnumVariant { .. }
+                    | AstType::Foreign(_)
                     | AstType::Nil => todo!(),
                     AstType::String => {
                         self.chunk().push_op(OpCode::PrintString);
                     }
-                    AstType::Unresolved(..) => todo!(),
+                    AstType::Unresolved(..) | AstType::Unknown => todo!(),
                 };
             }
             Ast::Return { expr, t, .. } => {
                 self.push_return(expr, t.as_ref().unwrap());
             }
+            Ast::Test { .. } | Ast::Import { .. } => {}
+            Ast::Assert { condition, pos } => {
+                self.codegen(condition);
+                let assertion_i = self.chunk().add_assertion(Assertion {
+                    message: format!("assertion failed: {}", format_expression(condition)),
+                    span: *pos,
+                });
+                let ip = self.chunk().push_op(OpCode::Assert { assertion_i });
+                self.mark_live_objects(ip);
+            }
+            Ast::AssertEq { lhs, rhs, t, pos } => {
+                self.operands(lhs, rhs, t);
+                let assertion_i = self.chunk().add_assertion(Assertion {
+                    message: format!(
+                        "assertion failed: {} == {}",
+                        format_expression(lhs),
+                        format_expression(rhs)
+                    ),
+                    span: *pos,
+                });
+                let ip = match t.as_ref().unwrap() {
+                    AstType::Float => self.chunk().push_op(OpCode::AssertEqF64 { assertion_i }),
+                    AstType::Bool => self.chunk().push_op(OpCode::AssertEqBool { assertion_i }),
+                    AstType::String => self.chunk().push_op(OpCode::AssertEqString { assertion_i }),
+                    t @ AstType::EnumVariant { .. } => self.chunk().push_op(OpCode::AssertEq {
+                        width: t.width() as u8,
+                        assertion_i,
+                    }),
+                    _ => panic!(),
+                };
+                self.mark_live_objects(ip);
+            }
             Ast::Declaration { name, expr, t, .. } => {
                 self.codegen(expr);
                 self.declare_variable(name, t.clone().unwrap());
             }
-            Ast::FuncDeclaration { name, func, .. } => {
-                self.globals.insert(
-                    name.clone(),
-                    GlobalVariable::Function(self.chunks.len() as ChunkAdr),
-                );
+            Ast::FuncDeclaration {
+                name,
+                func,
+                args_t,
+                ret_t,
+                ..
+            } => {
+                let chunk_i = self.next_chunk_adr();
+                self.globals
+                    .insert(name.clone(), GlobalVariable::Function(chunk_i));
                 self.codegen(func);
+                if self.is_root && self.current_scope_depth == 0 {
+                    self.chunks[(chunk_i - self.chunks_offset) as usize].set_function(
+                        ScriptFunction {
+                            name: name.clone(),
+                            args_t: args_t.clone(),
+                            ret_t: ret_t.clone(),
+                        },
+                    );
+                }
                 self.chunk().push_op(OpCode::Pop {
                     width: ChunkAdr::width() as u8,
                 });
@@ -220,7 +490,7 @@ impl<'a> Compiler<'a> {
                                 };
                                 false
                             }
-                            AstType::Closure(..) | AstType::String => {
+                            AstType::Closure(..) | AstType::String | AstType::Foreign(_) => {
                                 self.chunk().push_op(OpCode::Variable {
                                     stack_i: v.offset,
                                     width: HeapAdr::width() as u8,
@@ -228,7 +498,7 @@ impl<'a> Compiler<'a> {
                                 true
                             }
                             AstType::ExternalFunction(..) | AstType::Nil => panic!(),
-                            AstType::Unresolved(..) => todo!(),
+                            AstType::Unresolved(..) | AstType::Unknown => todo!(),
                         };
                         if is_rc {
                             self.chunk().push_op(OpCode::IncreaseRC);
@@ -248,6 +518,9 @@ impl<'a> Compiler<'a> {
                         }
                         self.chunk().push_op(OpCode::PushU8 { data: variant });
                     }
+                    Variable::Global(GlobalVariable::Constant(constant)) => {
+                        self.push_constant(&constant);
+                    }
                 }
             }
             Ast::Assign {
@@ -284,11 +557,11 @@ impl<'a> Compiler<'a> {
                                         .push_op(OpCode::AssignObj { stack_i: v.offset })
                                 }
                             }
-                            AstType::Closure(..) | AstType::String => self
+                            AstType::Closure(..) | AstType::String | AstType::Foreign(_) => self
                                 .chunk()
                                 .push_op(OpCode::AssignObj { stack_i: v.offset }),
                             AstType::ExternalFunction(..) | AstType::Nil => panic!(),
-                            AstType::Unresolved(..) => todo!(),
+                            AstType::Unresolved(..) | AstType::Unknown => todo!(),
                         };
                     }
                     _ => panic!(),
@@ -359,11 +632,12 @@ impl<'a> Compiler<'a> {
             } => {
                 let prev_chunk = self.current_chunk;
                 self.chunks.push(Chunk::new());
-                self.current_chunk = self.chunks.len() as ChunkAdr - 1;
+                self.current_chunk = self.next_chunk_adr() - 1;
 
                 let old_variables = mem::replace(&mut self.variables, vec![]);
                 let old_depth = mem::replace(&mut self.current_scope_depth, 0);
                 let old_is_root = mem::replace(&mut self.is_root, false);
+                let old_temporaries = mem::take(&mut self.temporaries);
 
                 for arg in args.iter() {
                     self.declare_variable(&arg.0, arg.1.clone());
@@ -383,6 +657,7 @@ impl<'a> Compiler<'a> {
                 mem::replace(&mut self.variables, old_variables);
                 mem::replace(&mut self.current_scope_depth, old_depth);
                 mem::replace(&mut self.is_root, old_is_root);
+                self.temporaries = old_temporaries;
 
                 let c = mem::replace(&mut self.current_chunk, prev_chunk);
 
@@ -390,9 +665,17 @@ impl<'a> Compiler<'a> {
                     self.chunk().push_op(OpCode::Function { chunk_i: c });
                 } else {
                     for var in captured.iter() {
+                        // the captured variable may itself be on the heap
+                        let t = match self.resolve_variable(&var.0) {
+                            Some(Variable::Local(local)) => local.t,
+                            _ => unreachable!(
+                                "the type checker only lets closures capture locals, {} isn't one",
+                                var.0
+                            ),
+                        };
                         self.codegen(&Ast::Variable {
                             name: var.0.clone(),
-                            t: var.1.clone(),
+                            t: Some(t),
                             pos: *pos,
                         });
                         match var.1.as_ref().unwrap() {
@@ -417,22 +700,31 @@ impl<'a> Compiler<'a> {
                 args,
                 args_width,
                 call_t,
-                ..
+                pos,
             } => {
+                let depth = self.temporaries.len();
                 for arg in args.iter() {
                     self.codegen(arg);
+                    let temporary = self.temporary(arg);
+                    self.temporaries.push(temporary);
                 }
 
                 self.codegen(ident);
+                self.temporaries.truncate(depth);
 
                 let args_width = args_width.unwrap();
 
-                match call_t.as_ref().unwrap() {
-                    CallType::Function => self.chunk().push_op(OpCode::Call { args_width }),
-                    CallType::Closure => self.chunk().push_op(OpCode::CallClosure { args_width }),
-                    CallType::External => self.chunk().push_op(OpCode::CallExternal { args_width }),
-                    CallType::Enum => 0,
+                let ip = match call_t.as_ref().unwrap() {
+                    CallType::Function => self.chunk().push_call(OpCode::Call { args_width }, *pos),
+                    CallType::Closure => self
+                        .chunk()
+                        .push_call(OpCode::CallClosure { args_width }, *pos),
+                    CallType::External => self
+                        .chunk()
+                        .push_call(OpCode::CallExternal { args_width }, *pos),
+                    CallType::Enum => return,
                 };
+                self.mark_live_objects(ip);
             }
             Ast::Float(n, _) => {
                 let i = self.chunk().add_const_f64(*n);
@@ -456,31 +748,28 @@ impl<'a> Compiler<'a> {
                 self.codegen(n);
                 self.chunk().push_op(OpCode::Not);
             }
-            Ast::Multiply(l, r, _, _) => {
-                self.codegen(l);
-                self.codegen(r);
+            Ast::Multiply(l, r, t, _) => {
+                self.operands(l, r, t);
                 self.chunk().push_op(OpCode::MultiplyF64);
             }
-            Ast::Divide(l, r, _, _) => {
-                self.codegen(l);
-                self.codegen(r);
+            Ast::Divide(l, r, t, _) => {
+                self.operands(l, r, t);
                 self.chunk().push_op(OpCode::DivideF64);
             }
-            Ast::Add(l, r, _, _) => {
-                self.codegen(l);
-                self.codegen(r);
+            Ast::Add(l, r, t, _) => {
+                self.operands(l, r, t);
                 self.chunk().push_op(OpCode::AddF64);
             }
-            Ast::Sub(l, r, _, _) => {
-                self.codegen(l);
-                self.codegen(r);
+            Ast::Sub(l, r, t, _) => {
+                self.operands(l, r, t);
                 self.chunk().push_op(OpCode::SubF64);
             }
             Ast::Equal(l, r, t, _) => {
-                self.codegen(l);
-                self.codegen(r);
+                self.operands(l, r, t);
                 match t.as_ref().unwrap() {
-                    t @ AstType::EnumVariant { .. } | t @ AstType::Bool | t @ AstType::Float => {
+                    // floats can't be compared bytewise, NaN != NaN and 0 == -0
+                    AstType::Float => self.chunk().push_op(OpCode::EqualF64),
+                    t @ AstType::EnumVariant { .. } | t @ AstType::Bool => {
                         self.chunk().push_op(OpCode::Equal {
                             width: t.width() as u8,
                         })
@@ -489,10 +778,10 @@ impl<'a> Compiler<'a> {
                 };
             }
             Ast::NotEqual(l, r, t, _) => {
-                self.codegen(l);
-                self.codegen(r);
+                self.operands(l, r, t);
                 match t.as_ref().unwrap() {
-                    t @ AstType::EnumVariant { .. } | t @ AstType::Bool | t @ AstType::Float => {
+                    AstType::Float => self.chunk().push_op(OpCode::EqualF64),
+                    t @ AstType::EnumVariant { .. } | t @ AstType::Bool => {
                         self.chunk().push_op(OpCode::Equal {
                             width: t.width() as u8,
                         })
@@ -502,38 +791,32 @@ impl<'a> Compiler<'a> {
                 self.chunk().push_op(OpCode::Not);
             }
             Ast::Greater(l, r, t, _) => {
-                self.codegen(l);
-                self.codegen(r);
+                self.operands(l, r, t);
                 match t.as_ref().unwrap() {
                     AstType::Float => self.chunk().push_op(OpCode::GreaterF64),
                     _ => panic!(),
                 };
             }
             Ast::GreaterEqual(l, r, t, _) => {
-                self.codegen(l);
-                self.codegen(r);
+                self.operands(l, r, t);
                 match t.as_ref().unwrap() {
-                    AstType::Float => self.chunk().push_op(OpCode::LesserF64),
+                    AstType::Float => self.chunk().push_op(OpCode::GreaterEqualF64),
                     _ => panic!(),
                 };
-                self.chunk().push_op(OpCode::Not);
             }
             Ast::Lesser(l, r, t, _) => {
-                self.codegen(l);
-                self.codegen(r);
+                self.operands(l, r, t);
                 match t.as_ref().unwrap() {
                     AstType::Float => self.chunk().push_op(OpCode::LesserF64),
                     _ => panic!(),
                 };
             }
             Ast::LesserEqual(l, r, t, _) => {
-                self.codegen(l);
-                self.codegen(r);
+                self.operands(l, r, t);
                 match t.as_ref().unwrap() {
-                    AstType::Float => self.chunk().push_op(OpCode::GreaterF64),
+                    AstType::Float => self.chunk().push_op(OpCode::LesserEqualF64),
                     _ => panic!(),
                 };
-                self.chunk().push_op(OpCode::Not);
             }
             Ast::And(l, r, _) => {
                 self.codegen(l);
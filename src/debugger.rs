@@ -2,51 +2,330 @@
 
 use super::*;
 use std::fmt;
+use std::io::{stderr, Write};
 
-fn print_errors(source: &String, errors: Vec<(usize, String)>) {
-    let lines_map = source.chars().fold(vec![0], |mut acc, c| {
-        acc.push(
-            acc.last().unwrap()
-                + match c {
-                    '\n' => 1,
-                    _ => 0,
-                },
-        );
-        acc
-    });
-    let lines: Vec<&str> = source.lines().collect();
-    let errors: Vec<(String, usize)> = errors
-        .into_iter()
-        .map(|err| {
-            let line = lines_map[err.0];
-            (err.1, line)
-        })
+/// A single error or warning, ready to be rendered against the source it
+/// came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub code: ErrorCode,
+    pub message: String,
+    pub span: Span,
+    /// Secondary spans, like where a variable was declared.
+    pub labels: Vec<(String, Span)>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn severity(&self) -> &'static str {
+        if self.code.is_warning() {
+            "warning"
+        } else {
+            "error"
+        }
+    }
+}
+
+/// Maps character positions (as used by `Token` and `Span`) to lines and
+/// columns.
+pub struct SourceLines {
+    starts: Vec<usize>,
+    lines: Vec<String>,
+    byte_offsets: Vec<usize>,
+}
+
+impl SourceLines {
+    pub fn new(source: &str) -> SourceLines {
+        let mut starts = vec![0];
+        for (i, c) in source.chars().enumerate() {
+            if c == '\n' {
+                starts.push(i + 1);
+            }
+        }
+        let lines = source
+            .split('\n')
+            .map(|line| line.trim_end_matches('\r').to_string())
+            .collect();
+        let byte_offsets = source
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(source.len()))
+            .collect();
+        SourceLines {
+            starts,
+            lines,
+            byte_offsets,
+        }
+    }
+
+    /// Zero based line and column of `pos`.
+    pub fn location(&self, pos: usize) -> (usize, usize) {
+        let line = self.starts.partition_point(|start| *start <= pos) - 1;
+        (line, pos - self.starts[line])
+    }
+
+    /// Position of the zero based `line` and `col`, clamped to the source.
+    pub fn offset(&self, line: usize, col: usize) -> usize {
+        let last = self.byte_offsets.len() - 1;
+        match self.starts.get(line) {
+            Some(start) => (start + col.min(self.lines[line].chars().count())).min(last),
+            None => last,
+        }
+    }
+
+    pub fn line(&self, line: usize) -> &str {
+        &self.lines[line]
+    }
+
+    pub fn byte_offset(&self, pos: usize) -> usize {
+        let last = *self.byte_offsets.last().unwrap();
+        self.byte_offsets.get(pos).cloned().unwrap_or(last)
+    }
+}
+
+fn render_underline(
+    lines: &SourceLines,
+    span: Span,
+    marker: char,
+    label: Option<&String>,
+    gutter: usize,
+    out: &mut dyn Write,
+) {
+    let (line, col) = lines.location(span.start);
+    let (end_line, end_col) = lines.location(span.end);
+    let text = lines.line(line);
+    let end_col = if end_line == line {
+        end_col
+    } else {
+        text.chars().count()
+    };
+    let indent: String = text
+        .chars()
+        .chain(std::iter::repeat(' '))
+        .take(col)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
         .collect();
-    eprintln!("{} errors!\n", errors.len());
-    for (msg, line) in errors.iter() {
-        eprintln!(
-            "...\n{: >3} | {}\n...",
+    let underline: String =
+        std::iter::repeat_n(marker, end_col.saturating_sub(col).max(1)).collect();
+    let label = label.map(|l| format!(" {}", l)).unwrap_or_default();
+    writeln!(
+        out,
+        "{: >w$} | {}{}{}",
+        "",
+        indent,
+        underline,
+        label,
+        w = gutter
+    )
+    .unwrap();
+}
+
+pub fn render_diagnostic(
+    name: &str,
+    lines: &SourceLines,
+    diagnostic: &Diagnostic,
+    out: &mut dyn Write,
+) {
+    let (line, col) = lines.location(diagnostic.span.start);
+    let mut markers = vec![(diagnostic.span, '^', None)];
+    for (label, span) in diagnostic.labels.iter() {
+        markers.push((*span, '-', Some(label)));
+    }
+    markers.sort_by_key(|(span, _, _)| lines.location(span.start));
+    let gutter = markers
+        .iter()
+        .map(|(span, _, _)| (lines.location(span.start).0 + 1).to_string().len())
+        .max()
+        .unwrap_or(1);
+
+    writeln!(
+        out,
+        "{}[{}]: {}",
+        diagnostic.severity(),
+        diagnostic.code.code(),
+        diagnostic.message
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "{: >w$}--> {}:{}:{}",
+        "",
+        name,
+        line + 1,
+        col + 1,
+        w = gutter
+    )
+    .unwrap();
+    writeln!(out, "{: >w$} |", "", w = gutter).unwrap();
+    let mut prev_line = None;
+    for (span, marker, label) in markers.into_iter() {
+        let line = lines.location(span.start).0;
+        if prev_line != Some(line) {
+            if prev_line.map(|prev| prev + 1 < line).unwrap_or(false) {
+                writeln!(out, "{: >w$}...", "", w = gutter).unwrap();
+            }
+            writeln!(out, "{: >w$} | {}", line + 1, lines.line(line), w = gutter).unwrap();
+            prev_line = Some(line);
+        }
+        render_underline(lines, span, marker, label, gutter, out);
+    }
+    if let Some(help) = &diagnostic.help {
+        writeln!(out, "{: >w$} = help: {}", "", help, w = gutter).unwrap();
+    }
+    writeln!(out).unwrap();
+}
+
+/// Renders an error raised while running, like a failed assertion, in the
+/// same layout as diagnostics.
+pub fn render_runtime_error(name: &str, source: &str, error: &RuntimeError, out: &mut dyn Write) {
+    let lines = SourceLines::new(source);
+    let (line, col) = lines.location(error.span.start);
+    let gutter = (line + 1).to_string().len();
+    writeln!(out, "error: {}", error.message).unwrap();
+    writeln!(
+        out,
+        "{: >w$}--> {}:{}:{}",
+        "",
+        name,
+        line + 1,
+        col + 1,
+        w = gutter
+    )
+    .unwrap();
+    writeln!(out, "{: >w$} |", "", w = gutter).unwrap();
+    writeln!(out, "{: >w$} | {}", line + 1, lines.line(line), w = gutter).unwrap();
+    render_underline(&lines, error.span, '^', None, gutter, out);
+    for call in error.trace.iter() {
+        let (line, col) = lines.location(call.start);
+        writeln!(
+            out,
+            "{: >w$} = note: called from {}:{}:{}",
+            "",
+            name,
             line + 1,
-            lines[(*line).min(lines.len() - 1)]
-        );
-        eprintln!("> {}", msg);
+            col + 1,
+            w = gutter
+        )
+        .unwrap();
+    }
+    writeln!(out).unwrap();
+}
+
+pub fn render_diagnostics(
+    name: &str,
+    source: &str,
+    diagnostics: &[Diagnostic],
+    out: &mut dyn Write,
+) {
+    let lines = SourceLines::new(source);
+    for diagnostic in diagnostics.iter() {
+        render_diagnostic(name, &lines, diagnostic, out);
     }
+    if diagnostics.len() > 1 {
+        let warnings = diagnostics.iter().filter(|d| d.code.is_warning()).count();
+        let errors = diagnostics.len() - warnings;
+        let counts: Vec<String> = [(errors, "error"), (warnings, "warning")]
+            .iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, kind)| {
+                format!("{} {}{}", count, kind, if *count == 1 { "" } else { "s" })
+            })
+            .collect();
+        writeln!(out, "{}", counts.join(", ")).unwrap();
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ErrorFormat {
+    Human,
+    /// One json object per diagnostic and line.
+    Json,
+}
+
+fn json_span(lines: &SourceLines, span: Span) -> String {
+    let (line, col) = lines.location(span.start);
+    let (end_line, end_col) = lines.location(span.end);
+    format!(
+        "\"start\":{},\"end\":{},\"line\":{},\"column\":{},\"end_line\":{},\"end_column\":{}",
+        lines.byte_offset(span.start),
+        lines.byte_offset(span.end),
+        line + 1,
+        col + 1,
+        end_line + 1,
+        end_col + 1
+    )
+}
+
+pub fn diagnostic_json(name: &str, lines: &SourceLines, diagnostic: &Diagnostic) -> String {
+    let labels: Vec<String> = diagnostic
+        .labels
+        .iter()
+        .map(|(label, span)| {
+            format!(
+                "{{\"message\":{},{}}}",
+                json_string(label),
+                json_span(lines, *span)
+            )
+        })
+        .collect();
+    format!(
+        "{{\"severity\":{},\"code\":{},\"message\":{},\"file\":{},{},\"labels\":[{}],\"help\":{}}}",
+        json_string(diagnostic.severity()),
+        json_string(diagnostic.code.code()),
+        json_string(&diagnostic.message),
+        json_string(name),
+        json_span(lines, diagnostic.span),
+        labels.join(","),
+        diagnostic
+            .help
+            .as_deref()
+            .map(json_string)
+            .unwrap_or_else(|| "null".to_string())
+    )
 }
 
-pub fn print_lexer_err(source: &String, error: LexerError) {
-    let error = match error {
-        LexerError::Parse(i) => (i, "could not parse character".to_string()),
-        LexerError::Unescaped(i) => (i, "unescaped string".to_string()),
+pub fn render_diagnostics_json(
+    name: &str,
+    source: &str,
+    diagnostics: &[Diagnostic],
+    out: &mut dyn Write,
+) {
+    let lines = SourceLines::new(source);
+    for diagnostic in diagnostics.iter() {
+        writeln!(out, "{}", diagnostic_json(name, &lines, diagnostic)).unwrap();
+    }
+}
+
+pub fn emit_diagnostics(format: ErrorFormat, name: &str, source: &str, diagnostics: &[Diagnostic]) {
+    match format {
+        ErrorFormat::Human => render_diagnostics(name, source, diagnostics, &mut stderr()),
+        ErrorFormat::Json => render_diagnostics_json(name, source, diagnostics, &mut stderr()),
+    }
+}
+
+pub fn lexer_diagnostics(error: LexerError) -> Vec<Diagnostic> {
+    let (code, message, i) = match error {
+        LexerError::Parse(i) => (ErrorCode::InvalidCharacter, "could not parse character", i),
+        LexerError::Unescaped(i) => (ErrorCode::UnterminatedString, "unescaped string", i),
     };
-    print_errors(source, vec![error]);
+    vec![Diagnostic {
+        code,
+        message: message.to_string(),
+        span: Span::new(i, i + 1),
+        labels: vec![],
+        help: None,
+    }]
 }
 
-fn flatmap_parser_error(error: ParserError, list: &mut Vec<(usize, String)>) {
+fn flatmap_parser_error(error: ParserError, list: &mut Vec<Diagnostic>) {
     match error {
-        ParserError::Unexpected(token, msg) => list.push((
-            token.start,
-            format!("on token {:?}: {}", token.t, msg.to_string()),
-        )),
+        ParserError::Unexpected(token, msg) => list.push(Diagnostic {
+            code: ErrorCode::UnexpectedToken,
+            message: format!("on token {:?}: {}", token.t, msg),
+            span: token.span(),
+            labels: vec![],
+            help: None,
+        }),
         ParserError::BlockErrors(errors) => {
             for error in errors.into_iter() {
                 flatmap_parser_error(error, list);
@@ -55,27 +334,63 @@ fn flatmap_parser_error(error: ParserError, list: &mut Vec<(usize, String)>) {
     }
 }
 
-pub fn print_parser_error(source: &String, error: ParserError) {
-    let mut errors = vec![];
-    flatmap_parser_error(error, &mut errors);
-    print_errors(source, errors);
+pub fn parser_diagnostics(error: ParserError) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    flatmap_parser_error(error, &mut diagnostics);
+    diagnostics
 }
 
-fn flatmap_type_error(error: TypeError, list: &mut Vec<(usize, String)>) {
+fn flatmap_type_error(error: TypeError, list: &mut Vec<Diagnostic>) {
     match error {
         TypeError::BlockErrors(errors) => {
             for error in errors.into_iter() {
                 flatmap_type_error(error, list);
             }
         }
-        TypeError::Error(msg, pos) => list.push((pos, msg)),
+        TypeError::Error(code, message, span) => list.push(Diagnostic {
+            code,
+            message,
+            span,
+            labels: vec![],
+            help: None,
+        }),
+        TypeError::Labeled(code, message, span, labels) => list.push(Diagnostic {
+            code,
+            message,
+            span,
+            labels,
+            help: None,
+        }),
+        TypeError::Help(error, help) => {
+            let start = list.len();
+            flatmap_type_error(*error, list);
+            for diagnostic in list[start..].iter_mut() {
+                diagnostic.help = Some(help.clone());
+            }
+        }
     }
 }
 
-pub fn print_type_error(source: &String, error: TypeError) {
-    let mut errors = vec![];
-    flatmap_type_error(error, &mut errors);
-    print_errors(source, errors);
+pub fn type_diagnostics(error: TypeError) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    flatmap_type_error(error, &mut diagnostics);
+    diagnostics
+}
+
+pub fn print_lexer_err(name: &str, source: &String, error: LexerError) {
+    render_diagnostics(name, source, &lexer_diagnostics(error), &mut stderr());
+}
+
+pub fn print_parser_error(name: &str, source: &String, error: ParserError) {
+    render_diagnostics(name, source, &parser_diagnostics(error), &mut stderr());
+}
+
+pub fn print_type_error(name: &str, source: &String, error: TypeError) {
+    render_diagnostics(name, source, &type_diagnostics(error), &mut stderr());
+}
+
+pub fn print_runtime_error(name: &str, source: &str, error: &RuntimeError) {
+    render_runtime_error(name, source, error, &mut stderr());
 }
 
 impl fmt::Display for OpCode {
@@ -84,14 +399,14 @@ impl fmt::Display for OpCode {
     }
 }
 
-pub fn disassemble_chunk(chunks: &Vec<Chunk>) {
+pub fn disassemble_chunk(chunks: &Vec<Chunk>, out: &mut dyn Write) {
     for (i, chunk) in chunks.iter().enumerate() {
-        eprintln!("{:*^64}", format!("BYTECODE CHUNK {}", i));
+        writeln!(out, "{:*^64}", format!("BYTECODE CHUNK {}", i)).unwrap();
         let mut ip = 0;
         while ip < chunk.len_code() {
-            eprintln!("{:0>6}\t{:?}", ip, chunk.get_op(ip));
+            writeln!(out, "{:0>6}\t{:?}", ip, chunk.get_op(ip)).unwrap();
             ip += 1;
         }
     }
-    eprintln!("{:*^64}", "");
+    writeln!(out, "{:*^64}", "").unwrap();
 }
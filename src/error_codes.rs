@@ -0,0 +1,409 @@
+/// Stable codes for every error and warning reported by the lexer, parser,
+/// type checker and formatter. Codes are never reused or renumbered, new
+/// errors get new codes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ErrorCode {
+    InvalidCharacter,
+    UnterminatedString,
+    UnexpectedToken,
+    UndefinedName,
+    NameInUse,
+    NotTopLevel,
+    AssignToGlobal,
+    MismatchedTypes,
+    InvalidOperand,
+    NonBoolCondition,
+    ReturnMismatch,
+    MissingReturn,
+    NotCallable,
+    ArgumentMismatch,
+    UnprintableType,
+    NilSwitch,
+    ReturnOutsideFunction,
+    CommentInExpression,
+    ExternalAsValue,
+    NotAllowed,
+    HostEnumMismatch,
+    InternalError,
+    CapturedGlobal,
+    AmbiguousName,
+
+    UnusedVariable,
+    UnreachableCode,
+    ShadowedVariable,
+    UnusedDeclaration,
+}
+
+pub const ERROR_CODES: [ErrorCode; 28] = [
+    ErrorCode::InvalidCharacter,
+    ErrorCode::UnterminatedString,
+    ErrorCode::UnexpectedToken,
+    ErrorCode::UndefinedName,
+    ErrorCode::NameInUse,
+    ErrorCode::NotTopLevel,
+    ErrorCode::AssignToGlobal,
+    ErrorCode::MismatchedTypes,
+    ErrorCode::InvalidOperand,
+    ErrorCode::NonBoolCondition,
+    ErrorCode::ReturnMismatch,
+    ErrorCode::MissingReturn,
+    ErrorCode::NotCallable,
+    ErrorCode::ArgumentMismatch,
+    ErrorCode::UnprintableType,
+    ErrorCode::NilSwitch,
+    ErrorCode::ReturnOutsideFunction,
+    ErrorCode::CommentInExpression,
+    ErrorCode::ExternalAsValue,
+    ErrorCode::NotAllowed,
+    ErrorCode::HostEnumMismatch,
+    ErrorCode::InternalError,
+    ErrorCode::CapturedGlobal,
+    ErrorCode::AmbiguousName,
+    ErrorCode::UnusedVariable,
+    ErrorCode::UnreachableCode,
+    ErrorCode::ShadowedVariable,
+    ErrorCode::UnusedDeclaration,
+];
+
+impl ErrorCode {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidCharacter => "E0001",
+            ErrorCode::UnterminatedString => "E0002",
+            ErrorCode::UnexpectedToken => "E0003",
+            ErrorCode::UndefinedName => "E0004",
+            ErrorCode::NameInUse => "E0005",
+            ErrorCode::NotTopLevel => "E0006",
+            ErrorCode::AssignToGlobal => "E0007",
+            ErrorCode::MismatchedTypes => "E0008",
+            ErrorCode::InvalidOperand => "E0009",
+            ErrorCode::NonBoolCondition => "E0010",
+            ErrorCode::ReturnMismatch => "E0011",
+            ErrorCode::MissingReturn => "E0012",
+            ErrorCode::NotCallable => "E0013",
+            ErrorCode::ArgumentMismatch => "E0014",
+            ErrorCode::UnprintableType => "E0015",
+            ErrorCode::NilSwitch => "E0016",
+            ErrorCode::ReturnOutsideFunction => "E0017",
+            ErrorCode::CommentInExpression => "E0018",
+            ErrorCode::ExternalAsValue => "E0019",
+            ErrorCode::NotAllowed => "E0020",
+            ErrorCode::HostEnumMismatch => "E0021",
+            ErrorCode::InternalError => "E0022",
+            ErrorCode::CapturedGlobal => "E0023",
+            ErrorCode::AmbiguousName => "E0024",
+            ErrorCode::UnusedVariable => "W0001",
+            ErrorCode::UnreachableCode => "W0002",
+            ErrorCode::ShadowedVariable => "W0003",
+            ErrorCode::UnusedDeclaration => "W0004",
+        }
+    }
+
+    pub fn is_warning(&self) -> bool {
+        matches!(
+            self,
+            ErrorCode::UnusedVariable
+                | ErrorCode::UnreachableCode
+                | ErrorCode::ShadowedVariable
+                | ErrorCode::UnusedDeclaration
+        )
+    }
+
+    pub fn from_code(code: &str) -> Option<ErrorCode> {
+        let code = code.to_uppercase();
+        ERROR_CODES.iter().cloned().find(|c| c.code() == code)
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidCharacter => "invalid character",
+            ErrorCode::UnterminatedString => "unterminated string",
+            ErrorCode::UnexpectedToken => "unexpected token",
+            ErrorCode::UndefinedName => "name is not defined",
+            ErrorCode::NameInUse => "name already in use",
+            ErrorCode::NotTopLevel => "declaration only allowed at the top level",
+            ErrorCode::AssignToGlobal => "assignment to a global",
+            ErrorCode::MismatchedTypes => "mismatched types",
+            ErrorCode::InvalidOperand => "operator used with an invalid type",
+            ErrorCode::NonBoolCondition => "condition is not a bool",
+            ErrorCode::ReturnMismatch => "return type doesn't match signature",
+            ErrorCode::MissingReturn => "function doesn't return on every path",
+            ErrorCode::NotCallable => "value is not callable",
+            ErrorCode::ArgumentMismatch => "arguments don't match parameters",
+            ErrorCode::UnprintableType => "type can't be printed",
+            ErrorCode::NilSwitch => "switch on nil",
+            ErrorCode::ReturnOutsideFunction => "return outside of a function",
+            ErrorCode::CommentInExpression => "comment inside an expression",
+            ErrorCode::ExternalAsValue => "external function used as a value",
+            ErrorCode::NotAllowed => "external not granted to the script",
+            ErrorCode::HostEnumMismatch => "enum doesn't match the host's",
+            ErrorCode::InternalError => "internal error",
+            ErrorCode::CapturedGlobal => "global captured by a closure",
+            ErrorCode::AmbiguousName => "name in more than one imported module",
+            ErrorCode::UnusedVariable => "unused variable",
+            ErrorCode::UnreachableCode => "unreachable code",
+            ErrorCode::ShadowedVariable => "variable shadows an outer variable",
+            ErrorCode::UnusedDeclaration => "unused function or enum",
+        }
+    }
+
+    /// Longer description of the error, with an example, shown by
+    /// `imglang explain`.
+    pub fn explanation(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidCharacter => {
+                "The lexer found a character that is not part of the language, or a
+number it could not parse.
+
+    var a = 1 @ 2;
+
+Remove the character, or put it inside a string."
+            }
+            ErrorCode::UnterminatedString => {
+                "A string was opened with '\"' but never closed, or ends with a '\\'
+escaping the closing quote.
+
+    var s = \"hello;
+
+Close the string with a matching '\"'."
+            }
+            ErrorCode::UnexpectedToken => {
+                "The parser found a token where it expected something else, like a
+missing ';' or ')'.
+
+    var a = ;
+
+The message says what was expected at the highlighted token."
+            }
+            ErrorCode::UndefinedName => {
+                "A variable, function or enum variant is used, but no declaration
+with that name is in scope.
+
+    print lenght;
+
+Check the spelling, or declare the name before it is used. Variables
+must be declared before use, functions and enums can be used anywhere
+at the top level."
+            }
+            ErrorCode::NameInUse => {
+                "An enum or enum variant is declared with a name that is already
+used by another enum or variant.
+
+    enum State = on | off;
+    enum Switch = on | broken;
+
+Enum variants share one namespace, rename one of them."
+            }
+            ErrorCode::NotTopLevel => {
+                "Named functions and enums can only be declared at the top level of
+a script, not inside blocks or functions.
+
+    fun outer() {
+      fun inner() {}
+    }
+
+Move the declaration to the top level, or use a closure assigned to a
+variable: `var inner = fun() {};`."
+            }
+            ErrorCode::AssignToGlobal => {
+                "Global names (top level functions) can't be assigned to.
+
+    fun f() {}
+    f = fun() {};
+
+Declare a variable instead: `var g = fun() {};`."
+            }
+            ErrorCode::MismatchedTypes => {
+                "Two types that must be the same are different, for example the
+operands of a binary operator, the value assigned to a variable, or a
+switch case and the switch value.
+
+    var a = 1;
+    a = \"one\";
+
+Values never change type, convert the value or use a new variable."
+            }
+            ErrorCode::InvalidOperand => {
+                "An operator is used with a type it is not defined for. Arithmetic
+and comparison need floats, '!', '&&' and '||' need bools, and '=='
+works on floats, bools and enums.
+
+    print \"a\" * \"b\";"
+            }
+            ErrorCode::NonBoolCondition => {
+                "The condition of an `if` or `while` is not a bool. There is no
+implicit conversion from other types.
+
+    var n = 3;
+    while (n) { n = n - 1; }
+
+Compare explicitly: `while (n > 0) { ... }`."
+            }
+            ErrorCode::ReturnMismatch => {
+                "A return statement returns a type that doesn't match the return
+type in the function signature.
+
+    fun half(x float) bool {
+      return x / 2;
+    }
+
+Change the signature, or the returned value."
+            }
+            ErrorCode::MissingReturn => {
+                "A function with a return type doesn't return on every path through
+its body.
+
+    fun sign(x float) float {
+      if (x < 0) {
+        return -1;
+      }
+    }
+
+Add a return at the end of the body, or an else branch that returns."
+            }
+            ErrorCode::NotCallable => {
+                "Something that is not a function, closure, external or enum variant
+is called.
+
+    var a = 1;
+    a();"
+            }
+            ErrorCode::ArgumentMismatch => {
+                "The arguments of a call don't match the parameters of the function,
+either in number or in type.
+
+    fun add(a float, b float) float { return a + b; }
+    add(1, true);"
+            }
+            ErrorCode::UnprintableType => {
+                "Only floats, bools and strings can be printed.
+
+    fun f() {}
+    print f;"
+            }
+            ErrorCode::NilSwitch => {
+                "The value of a switch statement has type nil, so there is nothing to
+match against.
+
+    fun f() {}
+    switch (f()) {
+      * { print 1; }
+    }"
+            }
+            ErrorCode::ReturnOutsideFunction => {
+                "`return` is used at the top level of a script, outside any function.
+
+    var a = 1;
+    return a;"
+            }
+            ErrorCode::CommentInExpression => {
+                "The formatter found a comment inside an expression, where it can't
+keep the comment on a line of its own without moving it.
+
+    var total = price + // before tax
+      tax;
+
+Move the comment before or after the statement. Comments between the
+arguments of a call or the parameters of a function are kept."
+            }
+            ErrorCode::ExternalAsValue => {
+                "An external function is stored, passed or returned like a value.
+Externals can only be called.
+
+    var root = math.sqrt;
+
+Call it from a script function and use that instead.
+
+    var root = fun(x float) float math.sqrt(x);"
+            }
+            ErrorCode::NotAllowed => {
+                "The script uses an external from a capability group it isn't
+granted, like writing files when only reading them is allowed.
+
+    fs.write(\"out.txt\", \"data\");
+
+Grant the group named in the message, with `--allow=fs-write` on the
+command line, or do without the external."
+            }
+            ErrorCode::HostEnumMismatch => {
+                "The host passes a Rust enum to and from the script as the script
+enum of the same name, but the script enum declares other variants, or
+declares them in another order, or wraps values in them.
+
+    enum Direction = up | left;
+
+Declare the variants the host has, in its order and without values. Their
+names are compared ignoring case.
+
+    enum Direction = left | right | up;"
+            }
+            ErrorCode::InternalError => {
+                "Checking the document crashed the language server. This is a bug in
+imglang, not in the script, so the document may have other errors that
+aren't shown. Please report it along with the document."
+            }
+            ErrorCode::CapturedGlobal => {
+                "A closure lists a function, enum variant or external among the
+variables it captures. Only local variables are captured, globals are
+visible from every function.
+
+    fun half(x float) float { return x / 2; }
+    var f = fun[half]() float half(3);
+
+Leave the global out of the list.
+
+    var f = fun() float half(3);"
+            }
+            ErrorCode::AmbiguousName => {
+                "An unqualified name is declared by more than one imported module, so
+it isn't clear which external is meant.
+
+    import math;
+    import img;
+    print max(1, 2);
+
+Qualify the name with its module.
+
+    print math.max(1, 2);"
+            }
+            ErrorCode::UnusedVariable => {
+                "A local variable or parameter is declared but its value is never
+read.
+
+    fun area(w float, h float) float {
+      return w * w;
+    }
+
+Remove it, or start the name with '_' if it is unused on purpose."
+            }
+            ErrorCode::UnreachableCode => {
+                "A statement comes after a return, or after an if/else where every
+branch returns, so it can never run.
+
+    fun f() float {
+      return 1;
+      print 2;
+    }"
+            }
+            ErrorCode::ShadowedVariable => {
+                "A variable is declared with the same name as a variable in an outer
+block, which is hidden until the inner block ends.
+
+    var x = 1;
+    {
+      var x = 2;
+      print x;
+    }
+
+This is allowed, but often a typo or a leftover. Rename one of them."
+            }
+            ErrorCode::UnusedDeclaration => {
+                "A top level function or enum is declared but never used.
+
+    fun helper() {}
+
+Remove it, or start the name with '_' if it is unused on purpose."
+            }
+        }
+    }
+}
@@ -1,17 +1,284 @@
 use super::*;
-use std::collections::HashMap;
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::mem;
+use std::rc::Rc;
 
 pub type ExternalAdr = u16;
 
+/// Strings and other objects are passed as `HeapAdr`s, each argument holds a
+/// reference that the external has to release, and a returned object hands
+/// its reference over to the script.
+pub struct ExternalContext<'a> {
+    pub stack: &'a mut Stack,
+    pub heap: &'a mut Heap,
+    externals: Option<&'a Externals>,
+    callbacks: &'a mut dyn Callbacks,
+    out: &'a mut dyn Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Callee {
+    Closure(HeapAdr),
+    Function(ChunkAdr),
+}
+
+pub trait Callbacks {
+    fn call(
+        &mut self,
+        callee: Callee,
+        args_t: &[AstType],
+        stack: &mut Stack,
+        heap: &mut Heap,
+        out: &mut dyn Write,
+    ) -> Result<(), RuntimeError>;
+}
+
+impl<'a> ExternalContext<'a> {
+    pub fn new(
+        stack: &'a mut Stack,
+        heap: &'a mut Heap,
+        externals: Option<&'a Externals>,
+        callbacks: &'a mut dyn Callbacks,
+        out: &'a mut dyn Write,
+    ) -> ExternalContext<'a> {
+        ExternalContext {
+            stack,
+            heap,
+            externals,
+            callbacks,
+            out,
+        }
+    }
+
+    /// The external still has to release the closure.
+    pub fn call_closure<Args: ScriptArgs, R: ExternalValue>(
+        &mut self,
+        adr: HeapAdr,
+        args: Args,
+    ) -> Result<R, RuntimeError> {
+        self.call(Callee::Closure(adr), args)
+    }
+
+    pub fn call_function<Args: ScriptArgs, R: ExternalValue>(
+        &mut self,
+        chunk_i: ChunkAdr,
+        args: Args,
+    ) -> Result<R, RuntimeError> {
+        self.call(Callee::Function(chunk_i), args)
+    }
+
+    fn call<Args: ScriptArgs, R: ExternalValue>(
+        &mut self,
+        callee: Callee,
+        args: Args,
+    ) -> Result<R, RuntimeError> {
+        args.push_to(self);
+        let args_t = Args::ast_types();
+        self.callbacks
+            .call(callee, &args_t, self.stack, self.heap, self.out)?;
+        Ok(R::pop_from(self))
+    }
+
+    pub fn pop_string(&mut self) -> String {
+        let adr: HeapAdr = self.stack.pop();
+        let string = self
+            .string(adr)
+            .expect("argument is not a string")
+            .to_string();
+        self.heap.decrease_rc(adr);
+        string
+    }
+
+    pub fn push_string(&mut self, string: String) {
+        let adr = self.heap.add_object(Obj::String(string));
+        self.stack.push(adr);
+    }
+
+    pub fn string(&self, adr: HeapAdr) -> Option<&str> {
+        self.heap.get_string_ref(adr).map(|s| s.as_str())
+    }
+
+    /// Panics when `T` isn't registered with `Externals::add_foreign_type`.
+    pub fn push_foreign<T: Any>(&mut self, value: T) {
+        let foreign_type = self
+            .externals
+            .and_then(|externals| externals.foreign_type_of::<T>())
+            .expect("foreign type isn't registered");
+        let foreign = Foreign::new(
+            foreign_type.name.clone(),
+            Box::new(value),
+            foreign_type.on_drop.clone(),
+        );
+        let adr = self.heap.add_object(Obj::Foreign(foreign));
+        self.stack.push(adr);
+    }
+
+    pub fn foreign<T: Any>(&self, adr: HeapAdr) -> Option<&T> {
+        self.heap
+            .get_foreign_ref(adr)
+            .and_then(|f| f.value().downcast_ref())
+    }
+
+    pub fn foreign_mut<T: Any>(&mut self, adr: HeapAdr) -> Option<&mut T> {
+        self.heap
+            .get_foreign_mut(adr)
+            .and_then(|f| f.value_mut().downcast_mut())
+    }
+
+    pub fn retain(&mut self, adr: HeapAdr) {
+        assert!(self.heap.is_live(adr), "retained a freed object");
+        self.heap.increase_rc(adr);
+    }
+
+    pub fn release(&mut self, adr: HeapAdr) {
+        assert!(self.heap.is_live(adr), "released a freed object");
+        self.heap.decrease_rc(adr);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExternalError {
+    Message(String),
+    Callback(RuntimeError),
+}
+
+impl ExternalError {
+    pub fn new(message: impl Into<String>) -> ExternalError {
+        ExternalError::Message(message.into())
+    }
+
+    pub fn at_call(self, call: RuntimeError) -> RuntimeError {
+        match self {
+            ExternalError::Message(message) => RuntimeError { message, ..call },
+            ExternalError::Callback(mut error) => {
+                error.trace.push(call.span);
+                error.trace.extend(call.trace);
+                error
+            }
+        }
+    }
+}
+
+impl From<String> for ExternalError {
+    fn from(message: String) -> ExternalError {
+        ExternalError::new(message)
+    }
+}
+
+impl From<&str> for ExternalError {
+    fn from(message: &str) -> ExternalError {
+        ExternalError::new(message)
+    }
+}
+
+impl From<RuntimeError> for ExternalError {
+    fn from(error: RuntimeError) -> ExternalError {
+        ExternalError::Callback(error)
+    }
+}
+
+/// On an error nothing has to be pushed.
+pub type ExternalDispatch = Box<dyn FnMut(&mut ExternalContext) -> Result<(), ExternalError>>;
+
 pub struct ExternalFunction {
     pub args_t: Vec<AstType>,
     pub ret_t: AstType,
-    pub dispatch: fn(&mut Stack),
+    pub dispatch: ExternalDispatch,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExternalConstant {
+    Float(f64),
+    Bool(bool),
+    String(String),
+    Tag {
+        enum_type: String,
+        variants: &'static [&'static str],
+        variant: u8,
+    },
+}
+
+impl ExternalConstant {
+    pub fn ast_type(&self) -> AstType {
+        match self {
+            ExternalConstant::Float(_) => AstType::Float,
+            ExternalConstant::Bool(_) => AstType::Bool,
+            ExternalConstant::String(_) => AstType::String,
+            ExternalConstant::Tag { enum_type, .. } => AstType::EnumVariant {
+                enum_type: enum_type.clone(),
+                max_size: 0,
+            },
+        }
+    }
+}
+
+impl From<f64> for ExternalConstant {
+    fn from(value: f64) -> ExternalConstant {
+        ExternalConstant::Float(value)
+    }
+}
+
+impl From<bool> for ExternalConstant {
+    fn from(value: bool) -> ExternalConstant {
+        ExternalConstant::Bool(value)
+    }
+}
+
+impl From<String> for ExternalConstant {
+    fn from(value: String) -> ExternalConstant {
+        ExternalConstant::String(value)
+    }
+}
+
+impl From<&str> for ExternalConstant {
+    fn from(value: &str) -> ExternalConstant {
+        ExternalConstant::String(value.to_string())
+    }
+}
+
+pub struct ForeignType {
+    pub name: String,
+    type_id: TypeId,
+    on_drop: Option<ForeignDrop>,
 }
 
 pub struct Externals {
-    functions: Vec<ExternalFunction>,
+    functions: Vec<RefCell<ExternalFunction>>,
     function_map: HashMap<String, ExternalAdr>,
+    foreign_types: Vec<ForeignType>,
+    constants: HashMap<String, ExternalConstant>,
+    enums: HashMap<String, &'static [&'static str]>,
+    modules: HashSet<String>,
+    denied: HashMap<String, Capability>,
+}
+
+pub struct ExternalModule<'e> {
+    externals: &'e mut Externals,
+    name: String,
+}
+
+impl<'e> ExternalModule<'e> {
+    pub fn add_function(&mut self, name: &str, func: ExternalFunction) -> &mut Self {
+        let name = format!("{}.{}", self.name, name);
+        self.externals.add_function(name, func);
+        self
+    }
+
+    pub fn register<Args, F: TypedExternal<Args>>(&mut self, name: &str, func: F) -> &mut Self {
+        let name = format!("{}.{}", self.name, name);
+        self.externals.add_enums(F::enums());
+        self.externals.add_function(name, func.into_external());
+        self
+    }
+
+    pub fn add_constant(&mut self, name: &str, value: impl Into<ExternalConstant>) -> &mut Self {
+        let name = format!("{}.{}", self.name, name);
+        self.externals.add_constant(&name, value);
+        self
+    }
 }
 
 impl Externals {
@@ -19,36 +286,159 @@ impl Externals {
         Externals {
             functions: Vec::new(),
             function_map: HashMap::new(),
+            foreign_types: Vec::new(),
+            constants: HashMap::new(),
+            enums: HashMap::new(),
+            modules: HashSet::new(),
+            denied: HashMap::new(),
+        }
+    }
+
+    pub fn module(&mut self, name: &str) -> ExternalModule<'_> {
+        self.modules.insert(name.to_string());
+        ExternalModule {
+            externals: self,
+            name: name.to_string(),
         }
     }
 
+    pub fn has_module(&self, name: &str) -> bool {
+        self.modules.contains(name)
+    }
+
+    pub fn deny(&mut self, capability: Capability) {
+        let mut denied = Externals::new();
+        capability.register(&mut denied);
+        for name in denied.names() {
+            if self.lookup_type(name).is_none() {
+                self.denied.insert(name.clone(), capability);
+            }
+        }
+        self.modules.extend(denied.modules);
+    }
+
+    pub fn lookup_denied(&self, name: &String) -> Option<Capability> {
+        self.denied.get(name).cloned()
+    }
+
+    pub fn add_foreign_type<T: Any>(&mut self, name: &str) {
+        self.foreign_types.push(ForeignType {
+            name: name.to_string(),
+            type_id: TypeId::of::<T>(),
+            on_drop: None,
+        });
+    }
+
+    pub fn add_foreign_type_with_drop<T: Any>(
+        &mut self,
+        name: &str,
+        on_drop: impl Fn(T) + 'static,
+    ) {
+        self.foreign_types.push(ForeignType {
+            name: name.to_string(),
+            type_id: TypeId::of::<T>(),
+            on_drop: Some(Rc::new(move |value: Box<dyn Any>| {
+                on_drop(*value.downcast().unwrap())
+            })),
+        });
+    }
+
+    pub fn lookup_foreign_type(&self, name: &String) -> Option<AstType> {
+        self.foreign_types
+            .iter()
+            .find(|t| &t.name == name)
+            .map(|t| AstType::Foreign(t.name.clone()))
+    }
+
+    pub fn foreign_type_of<T: Any>(&self) -> Option<&ForeignType> {
+        self.foreign_types
+            .iter()
+            .find(|t| t.type_id == TypeId::of::<T>())
+    }
+
     pub fn add_function(&mut self, name: String, func: ExternalFunction) {
         self.function_map
             .insert(name, self.functions.len() as ExternalAdr);
-        self.functions.push(func);
+        self.functions.push(RefCell::new(func));
     }
 
     pub fn lookup_function(&self, name: &String) -> Option<ExternalAdr> {
         self.function_map.get(name).map(|a| *a)
     }
 
+    pub fn add_constant(&mut self, name: &str, value: impl Into<ExternalConstant>) {
+        let value = value.into();
+        if let ExternalConstant::Tag {
+            enum_type,
+            variants,
+            ..
+        } = &value
+        {
+            self.enums.insert(enum_type.clone(), variants);
+        }
+        self.constants.insert(name.to_string(), value);
+    }
+
+    pub fn lookup_constant(&self, name: &String) -> Option<&ExternalConstant> {
+        self.constants.get(name)
+    }
+
+    fn add_enums(&mut self, enums: Vec<(AstType, &'static [&'static str])>) {
+        for (t, variants) in enums {
+            if let AstType::EnumVariant { enum_type, .. } = t {
+                self.enums.insert(enum_type, variants);
+            }
+        }
+    }
+
+    pub fn lookup_enum(&self, name: &String) -> Option<&'static [&'static str]> {
+        self.enums.get(name).cloned()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.function_map.keys().chain(self.constants.keys())
+    }
+
     pub fn lookup_type(&self, name: &String) -> Option<AstType> {
+        if let Some(constant) = self.constants.get(name) {
+            return Some(constant.ast_type());
+        }
         let i = match self.function_map.get(name) {
             Some(i) => *i,
             None => {
                 return None;
             }
         };
-        let func = &self.functions[i as usize];
+        let func = self.functions[i as usize].borrow();
         Some(AstType::ExternalFunction(
             func.args_t.clone(),
             Box::new(func.ret_t.clone()),
         ))
     }
 
-    pub fn dispatch(&self, adr: ExternalAdr, stack: &mut Stack) {
+    /// The host code is taken out of the function while it runs, so a
+    /// callback calling the same external again fails instead of re-entering
+    /// it.
+    pub fn dispatch(
+        &self,
+        adr: ExternalAdr,
+        context: &mut ExternalContext,
+    ) -> Result<(), ExternalError> {
         let func = self.functions.get(adr as usize).unwrap();
-        (func.dispatch)(stack);
+        let running: ExternalDispatch = Box::new(|_: &mut ExternalContext| {
+            Err(ExternalError::new(
+                "external called again from a callback while it's running",
+            ))
+        });
+        let mut dispatch = mem::replace(&mut func.borrow_mut().dispatch, running);
+        let result = dispatch(context);
+        func.borrow_mut().dispatch = dispatch;
+        result
+    }
+
+    pub fn register<Args, F: TypedExternal<Args>>(&mut self, name: &str, func: F) {
+        self.add_enums(F::enums());
+        self.add_function(name.to_string(), func.into_external());
     }
 }
 
@@ -56,12 +446,241 @@ pub trait AstTypeCaster {
     fn ast_type() -> AstType;
 }
 
-macro_rules! external_pop_args {
-    ($stack:ident, ($arg:ident, $t:ident), $(($rest_arg:ident, $rest_t:ident)),+) => {
-        external_pop_args!($stack, $(($rest_arg, $rest_t)),+);
-        let $arg: $t = $stack.pop();
+pub trait ExternalValue: AstTypeCaster + Sized {
+    fn pop_from(context: &mut ExternalContext) -> Self;
+    fn push_to(self, context: &mut ExternalContext);
+
+    fn variants() -> Option<&'static [&'static str]> {
+        None
+    }
+}
+
+impl AstTypeCaster for f64 {
+    fn ast_type() -> AstType {
+        AstType::Float
+    }
+}
+
+impl ExternalValue for f64 {
+    fn pop_from(context: &mut ExternalContext) -> f64 {
+        context.stack.pop()
+    }
+    fn push_to(self, context: &mut ExternalContext) {
+        context.stack.push(self);
+    }
+}
+
+impl AstTypeCaster for bool {
+    fn ast_type() -> AstType {
+        AstType::Bool
+    }
+}
+
+impl ExternalValue for bool {
+    fn pop_from(context: &mut ExternalContext) -> bool {
+        context.stack.pop()
+    }
+    fn push_to(self, context: &mut ExternalContext) {
+        context.stack.push(self);
+    }
+}
+
+impl AstTypeCaster for String {
+    fn ast_type() -> AstType {
+        AstType::String
+    }
+}
+
+impl ExternalValue for String {
+    fn pop_from(context: &mut ExternalContext) -> String {
+        context.pop_string()
+    }
+    fn push_to(self, context: &mut ExternalContext) {
+        context.push_string(self);
+    }
+}
+
+impl AstTypeCaster for () {
+    fn ast_type() -> AstType {
+        AstType::Nil
+    }
+}
+
+impl ExternalValue for () {
+    fn pop_from(_: &mut ExternalContext) {}
+    fn push_to(self, _: &mut ExternalContext) {}
+}
+
+/// Passes a Rust enum without fields to and from externals as the script
+/// enum of the same name, which the type checker makes declare the same
+/// variants in the same order, so they're passed by their index.
+///
+/// ```ignore
+/// external_enum!(Mode { Fast, Slow });
+/// ```
+#[macro_export]
+macro_rules! external_enum {
+    ($name:ident { $($variant:ident),+ $(,)? }) => {
+        impl $crate::AstTypeCaster for $name {
+            fn ast_type() -> $crate::AstType {
+                $crate::AstType::EnumVariant {
+                    enum_type: stringify!($name).to_string(),
+                    max_size: 0,
+                }
+            }
+        }
+
+        impl $crate::ExternalValue for $name {
+            #[allow(unused_assignments)]
+            fn pop_from(context: &mut $crate::ExternalContext) -> $name {
+                let variant: u8 = context.stack.pop();
+                let mut i = 0;
+                $(
+                    if i == variant {
+                        return $name::$variant;
+                    }
+                    i += 1;
+                )+
+                unreachable!(
+                    "the type checker makes enum {} declare the variants of the host",
+                    stringify!($name)
+                )
+            }
+            fn push_to(self, context: &mut $crate::ExternalContext) {
+                context.stack.push(self as u8);
+            }
+            fn variants() -> Option<&'static [&'static str]> {
+                Some(&[$(stringify!($variant)),+])
+            }
+        }
+
+        impl From<$name> for $crate::ExternalConstant {
+            fn from(value: $name) -> $crate::ExternalConstant {
+                $crate::ExternalConstant::Tag {
+                    enum_type: stringify!($name).to_string(),
+                    variants: &[$(stringify!($variant)),+],
+                    variant: value as u8,
+                }
+            }
+        }
     };
-    ($stack:ident, ($arg:ident, $t:ident)) => {
-        let $arg: $t = $stack.pop();
+}
+
+pub trait ExternalReturn {
+    fn ast_type() -> AstType;
+    fn push_to(self, context: &mut ExternalContext) -> Result<(), ExternalError>;
+    fn variants() -> Option<&'static [&'static str]>;
+}
+
+impl<T: ExternalValue> ExternalReturn for T {
+    fn ast_type() -> AstType {
+        T::ast_type()
+    }
+    fn variants() -> Option<&'static [&'static str]> {
+        T::variants()
+    }
+    fn push_to(self, context: &mut ExternalContext) -> Result<(), ExternalError> {
+        ExternalValue::push_to(self, context);
+        Ok(())
+    }
+}
+
+impl<T: ExternalValue> ExternalReturn for Result<T, ExternalError> {
+    fn ast_type() -> AstType {
+        T::ast_type()
+    }
+    fn variants() -> Option<&'static [&'static str]> {
+        T::variants()
+    }
+    fn push_to(self, context: &mut ExternalContext) -> Result<(), ExternalError> {
+        ExternalValue::push_to(self?, context);
+        Ok(())
+    }
+}
+
+pub trait TypedExternal<Args> {
+    fn into_external(self) -> ExternalFunction;
+    fn enums() -> Vec<(AstType, &'static [&'static str])>;
+}
+
+pub trait ScriptArgs {
+    fn ast_types() -> Vec<AstType>;
+    fn push_to(self, context: &mut ExternalContext);
+
+    fn width() -> StackAdr {
+        Self::ast_types()
+            .iter()
+            .map(|t| t.width() as StackAdr)
+            .sum()
+    }
+}
+
+macro_rules! script_args {
+    ($(($arg:ident, $t:ident)),*) => {
+        impl<$($t: ExternalValue),*> ScriptArgs for ($($t,)*) {
+            fn ast_types() -> Vec<AstType> {
+                vec![$(<$t as AstTypeCaster>::ast_type()),*]
+            }
+            #[allow(unused_variables)]
+            fn push_to(self, context: &mut ExternalContext) {
+                let ($($arg,)*) = self;
+                $(ExternalValue::push_to($arg, context);)*
+            }
+        }
     };
 }
+
+script_args!();
+script_args!((a, A));
+script_args!((a, A), (b, B));
+script_args!((a, A), (b, B), (c, C));
+script_args!((a, A), (b, B), (c, C), (d, D));
+script_args!((a, A), (b, B), (c, C), (d, D), (e, E));
+script_args!((a, A), (b, B), (c, C), (d, D), (e, E), (f, G));
+
+/// Pops the arguments last to first, the last one is on top of the stack.
+macro_rules! pop_args {
+    ($context:ident;) => {};
+    ($context:ident; ($arg:ident, $t:ident) $($rest:tt)*) => {
+        pop_args!($context; $($rest)*);
+        let $arg = $t::pop_from($context);
+    };
+}
+
+macro_rules! typed_external {
+    ($(($arg:ident, $t:ident)),*) => {
+        impl<F, R, $($t),*> TypedExternal<($($t,)*)> for F
+        where
+            F: FnMut($($t),*) -> R + 'static,
+            R: ExternalReturn,
+            $($t: ExternalValue,)*
+        {
+            fn into_external(mut self) -> ExternalFunction {
+                ExternalFunction {
+                    args_t: vec![$($t::ast_type()),*],
+                    ret_t: <R as ExternalReturn>::ast_type(),
+                    dispatch: Box::new(move |context: &mut ExternalContext| {
+                        pop_args!(context; $(($arg, $t))*);
+                        self($($arg),*).push_to(context)
+                    }),
+                }
+            }
+            fn enums() -> Vec<(AstType, &'static [&'static str])> {
+                let args = vec![$(($t::ast_type(), $t::variants())),*];
+                let ret = (<R as ExternalReturn>::ast_type(), <R as ExternalReturn>::variants());
+                args.into_iter()
+                    .chain(Some(ret))
+                    .filter_map(|(t, variants)| variants.map(|variants| (t, variants)))
+                    .collect()
+            }
+        }
+    };
+}
+
+typed_external!();
+typed_external!((a, A));
+typed_external!((a, A), (b, B));
+typed_external!((a, A), (b, B), (c, C));
+typed_external!((a, A), (b, B), (c, C), (d, D));
+typed_external!((a, A), (b, B), (c, C), (d, D), (e, E));
+typed_external!((a, A), (b, B), (c, C), (d, D), (e, E), (f, G));
@@ -0,0 +1,525 @@
+use super::*;
+
+/// Calls longer than this are split with one argument per line.
+pub const MAX_WIDTH: usize = 80;
+const INDENT: &str = "  ";
+
+#[derive(Debug)]
+pub enum FormatError {
+    Lexer(LexerError),
+    Parser(ParserError),
+    /// A comment inside an expression, which has no line of its own to keep
+    /// it on.
+    Comment(Span),
+}
+
+/// Formats `source`, keeping its comments and single blank lines between
+/// statements. Formatting formatted source returns it unchanged.
+pub fn format_source(source: &String) -> Result<String, FormatError> {
+    let mut lexer = Lexer::new(source).map_err(FormatError::Lexer)?;
+    let ast = parse(&mut lexer).map_err(FormatError::Parser)?;
+    let chars: Vec<char> = source.chars().collect();
+    let mut formatter = Formatter::new(&chars, lexer.comments());
+    if let Ast::Program(stmts) = &ast {
+        formatter.statements(stmts, chars.len());
+    }
+    match formatter.misplaced {
+        Some(span) => Err(FormatError::Comment(span)),
+        None => Ok(formatter.out),
+    }
+}
+
+/// Formats a single expression on one line, used to describe failed
+/// assertions.
+pub fn format_expression(ast: &Ast) -> String {
+    let mut formatter = Formatter::new(&[], &[]);
+    formatter.wrap = false;
+    formatter.expr(ast, PREC_ASSIGNMENT);
+    formatter.out
+}
+
+struct Formatter<'a> {
+    source: &'a [char],
+    comments: &'a [Comment],
+    next_comment: usize,
+    /// End of the last statement or comment written, to keep blank lines.
+    last_end: Option<usize>,
+    out: String,
+    indent: usize,
+    /// Cleared when measuring how wide a call is on a single line.
+    wrap: bool,
+    /// The first comment that would have moved out of its statement.
+    misplaced: Option<Span>,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(source: &'a [char], comments: &'a [Comment]) -> Formatter<'a> {
+        Formatter {
+            source,
+            comments,
+            next_comment: 0,
+            last_end: None,
+            out: String::new(),
+            indent: 0,
+            wrap: true,
+            misplaced: None,
+        }
+    }
+
+    fn column(&self) -> usize {
+        self.out.chars().rev().take_while(|c| *c != '\n').count()
+    }
+
+    fn write_indent(&mut self) {
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    /// Keeps one empty line where the source had at least one.
+    fn blank_line(&mut self, start: usize) {
+        let between = self.last_end.and_then(|end| self.source.get(end..start));
+        if between.is_some_and(|s| s.iter().filter(|c| **c == '\n').count() > 1) {
+            self.out.push('\n');
+        }
+    }
+
+    /// Writes the comments starting before `pos` on their own lines.
+    fn comments_before(&mut self, pos: usize) {
+        let comments = self.comments;
+        while let Some(comment) = comments
+            .get(self.next_comment)
+            .filter(|comment| comment.span.start < pos)
+        {
+            self.next_comment += 1;
+            self.blank_line(comment.span.start);
+            self.write_indent();
+            self.out.push_str(&comment.text);
+            self.out.push('\n');
+            self.last_end = Some(comment.span.end);
+        }
+    }
+
+    /// Writes a comment following the last statement or list item on the
+    /// same line.
+    fn trailing_comment(&mut self) {
+        let comment = match self.comments.get(self.next_comment) {
+            Some(comment) => comment,
+            None => return,
+        };
+        let end = self.last_end.unwrap_or(0);
+        let between = self.source.get(end..comment.span.start);
+        let same_line = |c: &char| (c.is_whitespace() && *c != '\n') || *c == ',';
+        if between.is_some_and(|s| s.iter().all(same_line)) {
+            self.next_comment += 1;
+            self.out.push_str("  ");
+            self.out.push_str(&comment.text);
+            self.last_end = Some(comment.span.end);
+        }
+    }
+
+    /// Whether a comment not written yet starts between `start` and `end`,
+    /// outside of `items`.
+    fn has_comments(&self, start: usize, end: usize, items: &[Span]) -> bool {
+        self.comments[self.next_comment..]
+            .iter()
+            .take_while(|comment| comment.span.start < end)
+            .any(|comment| {
+                comment.span.start > start
+                    && !items
+                        .iter()
+                        .any(|item| (item.start..item.end).contains(&comment.span.start))
+            })
+    }
+
+    /// Remembers the first comment left inside the statement ending at `end`.
+    fn check_comments_placed(&mut self, end: usize) {
+        if let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.span.start < end && self.misplaced.is_none() {
+                self.misplaced = Some(comment.span);
+            }
+        }
+    }
+
+    /// Writes a list in parentheses with one item per line, keeping the
+    /// comments between the items. `end` is the closing parenthesis.
+    fn wrapped_list<T>(
+        &mut self,
+        items: &[T],
+        end: usize,
+        span: impl Fn(&T) -> Span,
+        mut write: impl FnMut(&mut Self, &T),
+    ) {
+        self.out.push_str("(\n");
+        self.indent += 1;
+        self.last_end = None;
+        for item in items.iter() {
+            let span = span(item);
+            self.comments_before(span.start);
+            self.write_indent();
+            write(self, item);
+            self.out.push(',');
+            self.last_end = Some(span.end);
+            self.trailing_comment();
+            self.out.push('\n');
+        }
+        self.comments_before(end);
+        self.indent -= 1;
+        self.write_indent();
+        self.out.push(')');
+    }
+
+    /// Position of the first `c` from `pos` on that isn't in a comment.
+    fn find(&self, pos: usize, c: char) -> usize {
+        let mut i = pos;
+        while i < self.source.len() {
+            match self.comments.iter().find(|comment| comment.span.start == i) {
+                Some(comment) => i = comment.span.end,
+                None if self.source[i] == c => return i,
+                None => i += 1,
+            }
+        }
+        i
+    }
+
+    fn statements(&mut self, stmts: &[Ast], end: usize) {
+        for stmt in stmts.iter() {
+            let span = stmt.span();
+            self.comments_before(span.start);
+            self.blank_line(span.start);
+            self.write_indent();
+            self.statement(stmt);
+            self.check_comments_placed(span.end);
+            self.last_end = Some(span.end);
+            self.trailing_comment();
+            self.out.push('\n');
+        }
+        self.comments_before(end);
+    }
+
+    fn block(&mut self, cont: &[Ast], pos: Span) {
+        let has_comments = self
+            .comments
+            .get(self.next_comment)
+            .is_some_and(|comment| comment.span.start < pos.end);
+        if cont.is_empty() && !has_comments {
+            self.out.push_str("{}");
+            return;
+        }
+        self.out.push('{');
+        self.last_end = Some(pos.start + 1);
+        self.trailing_comment();
+        self.out.push('\n');
+        self.indent += 1;
+        self.last_end = None;
+        self.statements(cont, pos.end);
+        self.indent -= 1;
+        self.write_indent();
+        self.out.push('}');
+    }
+
+    fn statement(&mut self, ast: &Ast) {
+        match ast {
+            Ast::Block { cont, pos } => self.block(cont, *pos),
+            Ast::Print { expr, .. } => {
+                self.out.push_str("print ");
+                self.expr(expr, PREC_ASSIGNMENT);
+                self.out.push(';');
+            }
+            Ast::Return { expr, .. } => {
+                self.out.push_str("return");
+                if let Some(expr) = expr {
+                    self.out.push(' ');
+                    self.expr(expr, PREC_ASSIGNMENT);
+                }
+                self.out.push(';');
+            }
+            Ast::Declaration { name, expr, .. } => {
+                self.out.push_str(&format!("var {} = ", name));
+                self.expr(expr, PREC_ASSIGNMENT);
+                self.out.push(';');
+            }
+            Ast::FuncDeclaration { name, func, .. } => {
+                self.out.push_str(&format!("fun {}", name));
+                self.function(func);
+            }
+            Ast::EnumDeclaration { name, variants, .. } => {
+                let variants: Vec<String> = variants
+                    .iter()
+                    .map(|(variant, t)| match t {
+                        AstType::Nil => variant.clone(),
+                        t => format!("{} {}", variant, t),
+                    })
+                    .collect();
+                self.out
+                    .push_str(&format!("enum {} = {};", name, variants.join(" | ")));
+            }
+            Ast::Switch {
+                head,
+                cases,
+                default,
+                pos,
+            } => {
+                self.out.push_str("switch (");
+                self.expr(head, PREC_ASSIGNMENT);
+                self.out.push_str(") {\n");
+                self.indent += 1;
+                self.last_end = None;
+                let cases = cases.iter().map(|(case, body)| (Some(case), body));
+                for (case, body) in cases.chain(default.iter().map(|body| (None, &**body))) {
+                    let start = case.unwrap_or(body).span().start;
+                    self.comments_before(start);
+                    self.blank_line(start);
+                    self.write_indent();
+                    match case {
+                        Some(case) => self.expr(case, PREC_ASSIGNMENT),
+                        None => self.out.push('*'),
+                    }
+                    self.out.push(' ');
+                    self.statement(body);
+                    self.check_comments_placed(body.span().end);
+                    self.last_end = Some(body.span().end);
+                    self.trailing_comment();
+                    self.out.push('\n');
+                }
+                self.comments_before(pos.end);
+                self.indent -= 1;
+                self.write_indent();
+                self.out.push('}');
+            }
+            Ast::If {
+                condition,
+                body,
+                else_body,
+                ..
+            } => {
+                self.out.push_str("if (");
+                self.expr(condition, PREC_ASSIGNMENT);
+                self.out.push_str(") ");
+                self.check_comments_placed(body.span().start);
+                self.statement(body);
+                if let Some(else_body) = else_body {
+                    let else_start = self.find(body.span().end, 'e');
+                    if self.has_comments(body.span().end, else_start, &[]) {
+                        // comments before `else` stay between the branches
+                        self.last_end = Some(body.span().end);
+                        self.trailing_comment();
+                        self.out.push('\n');
+                        self.comments_before(else_start);
+                        self.write_indent();
+                        self.out.push_str("else ");
+                    } else {
+                        self.out.push_str(" else ");
+                    }
+                    self.check_comments_placed(else_body.span().start);
+                    self.statement(else_body);
+                }
+            }
+            Ast::While {
+                condition, body, ..
+            } => {
+                self.out.push_str("while (");
+                self.expr(condition, PREC_ASSIGNMENT);
+                self.out.push_str(") ");
+                self.check_comments_placed(body.span().start);
+                self.statement(body);
+            }
+            Ast::ExprStatement { expr, .. } => {
+                self.expr(expr, PREC_ASSIGNMENT);
+                self.out.push(';');
+            }
+            Ast::Import { module, .. } => {
+                self.out.push_str(&format!("import {};", module));
+            }
+            Ast::Test { name, body, .. } => {
+                self.out.push_str(&format!("test \"{}\" ", name));
+                self.statement(body);
+            }
+            Ast::Assert { condition, .. } => {
+                self.out.push_str("assert ");
+                self.expr(condition, PREC_ASSIGNMENT);
+                self.out.push(';');
+            }
+            Ast::AssertEq { lhs, rhs, .. } => {
+                self.out.push_str("assert_eq(");
+                self.expr(lhs, PREC_ASSIGNMENT);
+                self.out.push_str(", ");
+                self.expr(rhs, PREC_ASSIGNMENT);
+                self.out.push_str(");");
+            }
+            _ => self.expr(ast, PREC_ASSIGNMENT),
+        }
+    }
+
+    /// Writes the function after `fun` or the function name.
+    fn function(&mut self, ast: &Ast) {
+        let (body, args, captured, ret_t, pos) = match ast {
+            Ast::Function {
+                body,
+                args,
+                captured,
+                ret_t,
+                pos,
+            } => (body, args, captured, ret_t, pos),
+            _ => return self.expr(ast, PREC_ASSIGNMENT),
+        };
+        if !captured.is_empty() {
+            let captured: Vec<&str> = captured.iter().map(|(name, _)| name.as_str()).collect();
+            self.out.push_str(&format!("[{}]", captured.join(", ")));
+        }
+        let open = self.find(pos.start, '(');
+        let close = self.find(open, ')');
+        if self.wrap && self.has_comments(open, close, &[]) {
+            self.wrapped_list(
+                args,
+                close,
+                |arg| arg.2,
+                |formatter, (name, t, _)| formatter.out.push_str(&format!("{} {}", name, t)),
+            );
+        } else {
+            let args: Vec<String> = args
+                .iter()
+                .map(|(name, t, _)| format!("{} {}", name, t))
+                .collect();
+            self.out.push_str(&format!("({})", args.join(", ")));
+        }
+        if *ret_t != AstType::Nil {
+            self.out.push_str(&format!(" {}", ret_t));
+        }
+        self.out.push(' ');
+        self.check_comments_placed(body.span().start);
+        match expression_body(body) {
+            Some(expr) => self.expr(expr, PREC_ASSIGNMENT),
+            None => self.statement(body),
+        }
+    }
+
+    fn expr(&mut self, ast: &Ast, min_prec: u32) {
+        if precedence(ast) < min_prec {
+            self.out.push('(');
+            self.expr(ast, PREC_ASSIGNMENT);
+            self.out.push(')');
+            return;
+        }
+        let prec = precedence(ast);
+        let (lhs, op, rhs) = match ast {
+            Ast::Float(f, _) => return self.out.push_str(&f.to_string()),
+            Ast::Bool(b, _) => return self.out.push_str(&b.to_string()),
+            Ast::String(s, _) => return self.out.push_str(&format!("\"{}\"", s)),
+            Ast::Variable { name, .. } => return self.out.push_str(name),
+            Ast::Assign { name, expr, .. } => {
+                self.out.push_str(&format!("{} = ", name));
+                return self.expr(expr, PREC_ASSIGNMENT);
+            }
+            Ast::Negate(expr, _) | Ast::Not(expr, _) => {
+                self.out
+                    .push(if let Ast::Negate(..) = ast { '-' } else { '!' });
+                return self.expr(expr, PREC_UNARY);
+            }
+            Ast::Function { .. } => {
+                self.out.push_str("fun");
+                return self.function(ast);
+            }
+            Ast::Call {
+                ident, args, pos, ..
+            } if ident.span().start > pos.start => {
+                self.expr(&args[0], PREC_ASSIGNMENT);
+                self.out.push_str(" | ");
+                return self.expr(ident, PREC_ASSIGNMENT + 1);
+            }
+            Ast::Call {
+                ident, args, pos, ..
+            } => {
+                self.expr(ident, PREC_CALL);
+                return self.arguments(args, ident.span().end, pos.end);
+            }
+            Ast::Multiply(lhs, rhs, _, _) => (lhs, "*", rhs),
+            Ast::Divide(lhs, rhs, _, _) => (lhs, "/", rhs),
+            Ast::Add(lhs, rhs, _, _) => (lhs, "+", rhs),
+            Ast::Sub(lhs, rhs, _, _) => (lhs, "-", rhs),
+            Ast::Equal(lhs, rhs, _, _) => (lhs, "==", rhs),
+            Ast::NotEqual(lhs, rhs, _, _) => (lhs, "!=", rhs),
+            Ast::Greater(lhs, rhs, _, _) => (lhs, ">", rhs),
+            Ast::GreaterEqual(lhs, rhs, _, _) => (lhs, ">=", rhs),
+            Ast::Lesser(lhs, rhs, _, _) => (lhs, "<", rhs),
+            Ast::LesserEqual(lhs, rhs, _, _) => (lhs, "<=", rhs),
+            Ast::And(lhs, rhs, _) | Ast::Or(lhs, rhs, _) => {
+                // `and` and `or` group to the right
+                let op = if let Ast::And(..) = ast { "and" } else { "or" };
+                self.expr(lhs, prec + 1);
+                self.out.push_str(&format!(" {} ", op));
+                return self.expr(rhs, prec);
+            }
+            _ => return self.statement(ast),
+        };
+        self.expr(lhs, prec);
+        self.out.push_str(&format!(" {} ", op));
+        self.expr(rhs, prec + 1);
+    }
+
+    /// Writes the arguments of a call between `start` and `end`.
+    fn arguments(&mut self, args: &[Ast], start: usize, end: usize) {
+        if self.wrap {
+            let mut flat = Formatter::new(self.source, &[]);
+            flat.wrap = false;
+            flat.indent = self.indent;
+            flat.out = " ".repeat(self.column());
+            flat.arguments(args, start, end);
+            let width = flat.out.lines().next().map_or(0, |line| line.chars().count());
+            let spans: Vec<Span> = args.iter().map(|arg| arg.span()).collect();
+            let too_wide = !args.is_empty() && width + 1 > MAX_WIDTH;
+            if too_wide || self.has_comments(start, end, &spans) {
+                let end = end - 1;
+                return self.wrapped_list(
+                    args,
+                    end,
+                    |arg| arg.span(),
+                    |formatter, arg| formatter.expr(arg, PREC_ASSIGNMENT),
+                );
+            }
+        }
+        self.out.push('(');
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.expr(arg, PREC_ASSIGNMENT);
+        }
+        self.out.push(')');
+    }
+}
+
+/// The expression of a function written as `fun(a float) float a * 2`,
+/// which the parser turns into a block with a single return.
+fn expression_body(body: &Ast) -> Option<&Ast> {
+    match body {
+        Ast::Block { cont, pos } => match cont.as_slice() {
+            [Ast::Return {
+                expr: Some(expr),
+                pos: return_pos,
+                ..
+            }] if return_pos == pos => Some(expr),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn precedence(ast: &Ast) -> u32 {
+    match ast {
+        Ast::Assign { .. } => PREC_ASSIGNMENT,
+        Ast::Call { ident, pos, .. } if ident.span().start > pos.start => PREC_ASSIGNMENT,
+        Ast::Or(..) => PREC_OR,
+        Ast::And(..) => PREC_AND,
+        Ast::Equal(..) | Ast::NotEqual(..) => PREC_EQUALITY,
+        Ast::Greater(..) | Ast::GreaterEqual(..) | Ast::Lesser(..) | Ast::LesserEqual(..) => {
+            PREC_COMPARISON
+        }
+        Ast::Add(..) | Ast::Sub(..) => PREC_TERM,
+        Ast::Multiply(..) | Ast::Divide(..) => PREC_FACTOR,
+        Ast::Negate(..) | Ast::Not(..) => PREC_UNARY,
+        // an expression body would swallow anything written after it
+        Ast::Function { body, .. } if expression_body(body).is_some() => PREC_ASSIGNMENT,
+        _ => PREC_CALL,
+    }
+}
@@ -0,0 +1,491 @@
+use std::fmt;
+
+/// Types the generator knows how to produce values of. A subset of
+/// `AstType` without the types that only exist inside the type checker.
+#[derive(Debug, Clone, PartialEq)]
+enum GenType {
+    Nil,
+    Float,
+    Bool,
+    String,
+    Enum(usize),
+    Function(Vec<GenType>, Box<GenType>),
+    Closure(Vec<GenType>, Box<GenType>),
+}
+
+impl GenType {
+    fn can_capture(&self) -> bool {
+        matches!(self, GenType::Float | GenType::Bool | GenType::Enum(_))
+    }
+
+    fn can_compare(&self) -> bool {
+        matches!(self, GenType::Float | GenType::Bool | GenType::Enum(_))
+    }
+}
+
+impl fmt::Display for GenType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenType::Nil => write!(f, "nil"),
+            GenType::Float => write!(f, "float"),
+            GenType::Bool => write!(f, "bool"),
+            GenType::String => write!(f, "str"),
+            GenType::Enum(i) => write!(f, "E{}", i),
+            GenType::Function(args, ret) | GenType::Closure(args, ret) => {
+                let args: Vec<String> = args.iter().map(|t| t.to_string()).collect();
+                write!(f, "<{}", args.join(", "))?;
+                if **ret != GenType::Nil {
+                    write!(f, ";{}", ret)?;
+                }
+                write!(f, ">")?;
+                if let GenType::Closure(..) = self {
+                    write!(f, "*")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+struct Local {
+    name: String,
+    t: GenType,
+    /// Loop counters are read but never assigned, so loops always end.
+    assignable: bool,
+}
+
+/// Generates random programs that type check, from a seed. Loops are
+/// bounded and functions only call functions declared before them, so
+/// every program terminates.
+pub struct ProgramGenerator {
+    state: u64,
+    /// Variants of each enum, and whether they wrap a float.
+    enums: Vec<Vec<bool>>,
+    functions: Vec<(Vec<GenType>, GenType)>,
+    /// Block scopes of the function being generated.
+    scopes: Vec<Vec<Local>>,
+    next_name: usize,
+    depth: usize,
+    indent: usize,
+}
+
+const MAX_DEPTH: usize = 4;
+const MAX_LOOP: usize = 4;
+
+impl ProgramGenerator {
+    pub fn new(seed: u64) -> ProgramGenerator {
+        ProgramGenerator {
+            state: seed.wrapping_mul(0x9e3779b97f4a7c15) | 1,
+            enums: vec![],
+            functions: vec![],
+            scopes: vec![vec![]],
+            next_name: 0,
+            depth: 0,
+            indent: 0,
+        }
+    }
+
+    /// Enums and functions at the top level, followed by a block with the
+    /// main code, so no root variables are alive when the program ends.
+    pub fn program(&mut self) -> String {
+        let mut out = String::new();
+        for i in 0..self.below(3) {
+            let variants: Vec<bool> = (0..1 + self.below(3)).map(|_| self.chance(40)).collect();
+            let names: Vec<String> = variants
+                .iter()
+                .enumerate()
+                .map(|(v, wraps)| match wraps {
+                    true => format!("e{}v{} float", i, v),
+                    false => format!("e{}v{}", i, v),
+                })
+                .collect();
+            out.push_str(&format!("enum E{} = {};\n", i, names.join(" | ")));
+            self.enums.push(variants);
+        }
+        for i in 0..1 + self.below(4) {
+            out.push_str(&self.function(i));
+        }
+        out.push_str(&self.block());
+        out.push('\n');
+        out
+    }
+
+    fn next(&mut self) -> u64 {
+        // xorshift64*
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    fn pick<T: Clone>(&mut self, items: &[T]) -> Option<T> {
+        match items.len() {
+            0 => None,
+            n => Some(items[self.below(n)].clone()),
+        }
+    }
+
+    fn name(&mut self, prefix: &str) -> String {
+        self.next_name += 1;
+        format!("{}{}", prefix, self.next_name)
+    }
+
+    fn newline(&self) -> String {
+        format!("\n{}", "  ".repeat(self.indent))
+    }
+
+    fn locals(&self) -> impl Iterator<Item = &Local> {
+        self.scopes.iter().flatten()
+    }
+
+    fn declare(&mut self, name: String, t: GenType, assignable: bool) {
+        self.scopes.last_mut().unwrap().push(Local {
+            name,
+            t,
+            assignable,
+        });
+    }
+
+    fn value_type(&mut self) -> GenType {
+        match self.below(4 + self.enums.len().min(1)) {
+            0 | 1 => GenType::Float,
+            2 => GenType::Bool,
+            3 => GenType::String,
+            _ => GenType::Enum(self.below(self.enums.len())),
+        }
+    }
+
+    fn capture_type(&mut self) -> GenType {
+        match self.value_type() {
+            GenType::String => GenType::Float,
+            t => t,
+        }
+    }
+
+    /// Type of a new variable, closures only when there is something to
+    /// capture.
+    fn variable_type(&mut self) -> GenType {
+        if self.chance(10) {
+            if let Some((args, ret)) = self.pick(&self.functions.clone()) {
+                return GenType::Function(args, Box::new(ret));
+            }
+        }
+        let can_capture = self.locals().any(|l| l.t.can_capture());
+        if can_capture && self.depth < MAX_DEPTH && self.chance(15) {
+            let args = (0..self.below(3)).map(|_| self.value_type()).collect();
+            return GenType::Closure(args, Box::new(self.value_type()));
+        }
+        self.value_type()
+    }
+
+    fn function(&mut self, i: usize) -> String {
+        let mut args: Vec<GenType> = (0..self.below(4)).map(|_| self.value_type()).collect();
+        let ret = match self.below(6) {
+            0 => GenType::Nil,
+            1 => {
+                // returned closures capture the first argument
+                args.insert(0, self.capture_type());
+                let closure_args = (0..self.below(2)).map(|_| self.value_type()).collect();
+                GenType::Closure(closure_args, Box::new(self.value_type()))
+            }
+            _ => self.value_type(),
+        };
+        let names: Vec<String> = args.iter().map(|_| self.name("a")).collect();
+        let params: Vec<String> = names
+            .iter()
+            .zip(args.iter())
+            .map(|(name, t)| format!("{} {}", name, t))
+            .collect();
+        let mut header = format!("fun f{}({})", i, params.join(", "));
+        if ret != GenType::Nil {
+            header.push_str(&format!(" {}", ret));
+        }
+        let scope = names
+            .into_iter()
+            .zip(args.iter().cloned())
+            .map(|(name, t)| Local {
+                name,
+                t,
+                assignable: true,
+            })
+            .collect();
+        let body = self.body(scope, &ret);
+        self.functions.push((args, ret));
+        format!("{} {}\n\n", header, body)
+    }
+
+    /// Body of a function or closure with `scope` as its only variables,
+    /// ending with a return of `ret`.
+    fn body(&mut self, scope: Vec<Local>, ret: &GenType) -> String {
+        let outer = std::mem::replace(&mut self.scopes, vec![scope]);
+        self.indent += 1;
+        let mut out = String::from("{");
+        for _ in 0..self.below(3) {
+            out.push_str(&self.newline());
+            out.push_str(&self.statement());
+        }
+        if *ret != GenType::Nil {
+            out.push_str(&self.newline());
+            out.push_str(&format!("return {};", self.expression(ret)));
+        }
+        self.indent -= 1;
+        out.push_str(&self.newline());
+        out.push('}');
+        self.scopes = outer;
+        out
+    }
+
+    fn block(&mut self) -> String {
+        self.scopes.push(vec![]);
+        self.indent += 1;
+        let mut out = String::from("{");
+        let count = match self.depth {
+            0 => 4 + self.below(8),
+            _ => 1 + self.below(3),
+        };
+        for _ in 0..count {
+            out.push_str(&self.newline());
+            out.push_str(&self.statement());
+        }
+        self.indent -= 1;
+        out.push_str(&self.newline());
+        out.push('}');
+        self.scopes.pop();
+        out
+    }
+
+    fn statement(&mut self) -> String {
+        self.depth += 1;
+        let nested = self.depth < MAX_DEPTH;
+        let assignable: Vec<(String, GenType)> = self
+            .locals()
+            .filter(|l| l.assignable)
+            .map(|l| (l.name.clone(), l.t.clone()))
+            .collect();
+        let stmt = match self.below(100) {
+            0..=29 => {
+                let t = self.variable_type();
+                let name = self.name("v");
+                let stmt = format!("var {} = {};", name, self.expression(&t));
+                self.declare(name, t, true);
+                stmt
+            }
+            30..=44 if !assignable.is_empty() => {
+                let (name, t) = self.pick(&assignable).unwrap();
+                format!("{} = {};", name, self.expression(&t))
+            }
+            45..=64 => {
+                let t = match self.below(3) {
+                    0 => GenType::Float,
+                    1 => GenType::Bool,
+                    _ => GenType::String,
+                };
+                format!("print {};", self.expression(&t))
+            }
+            65..=74 if nested => {
+                let condition = self.expression(&GenType::Bool);
+                let body = self.block();
+                match self.chance(50) {
+                    true => format!("if ({}) {} else {}", condition, body, self.block()),
+                    false => format!("if ({}) {}", condition, body),
+                }
+            }
+            75..=82 if nested => {
+                let counter = self.name("i");
+                let limit = 1 + self.below(MAX_LOOP);
+                self.declare(counter.clone(), GenType::Float, false);
+                let body = self.block();
+                // the increment goes first, so the loop ends even if the
+                // body is cut short
+                let body = body.replacen(
+                    '{',
+                    &format!("{{{}  {} = {} + 1;", self.newline(), counter, counter),
+                    1,
+                );
+                format!(
+                    "var {} = 0;{}while ({} < {}) {}",
+                    counter,
+                    self.newline(),
+                    counter,
+                    limit,
+                    body
+                )
+            }
+            83..=92 => match self.call(None) {
+                Some(call) => format!("{};", call),
+                None => format!("print {};", self.expression(&GenType::Float)),
+            },
+            93..=99 if nested => self.block(),
+            _ => format!("print {};", self.expression(&GenType::Bool)),
+        };
+        self.depth -= 1;
+        stmt
+    }
+
+    /// A call of a function or closure returning `ret`, any return type
+    /// when `None`.
+    fn call(&mut self, ret: Option<&GenType>) -> Option<String> {
+        let mut callees: Vec<(String, Vec<GenType>)> = vec![];
+        for (i, (args, t)) in self.functions.iter().enumerate() {
+            if ret.is_none_or(|ret| ret == t) {
+                callees.push((format!("f{}", i), args.clone()));
+            }
+        }
+        for local in self.locals() {
+            if let GenType::Function(args, t) | GenType::Closure(args, t) = &local.t {
+                if ret.is_none_or(|ret| ret == &**t) {
+                    callees.push((local.name.clone(), args.clone()));
+                }
+            }
+        }
+        let (callee, args) = self.pick(&callees)?;
+        let args: Vec<String> = args.iter().map(|t| self.expression(t)).collect();
+        Some(format!("{}({})", callee, args.join(", ")))
+    }
+
+    fn variable(&mut self, t: &GenType) -> Option<String> {
+        let names: Vec<String> = self
+            .locals()
+            .filter(|l| &l.t == t)
+            .map(|l| l.name.clone())
+            .collect();
+        self.pick(&names)
+    }
+
+    fn expression(&mut self, t: &GenType) -> String {
+        self.depth += 1;
+        let leaf = self.depth >= MAX_DEPTH || self.chance(35);
+        let expr = self.expression_of(t, leaf);
+        self.depth -= 1;
+        expr
+    }
+
+    fn expression_of(&mut self, t: &GenType, leaf: bool) -> String {
+        if self.chance(30) {
+            if let Some(variable) = self.variable(t) {
+                return variable;
+            }
+        }
+        if !leaf && self.chance(20) {
+            if let Some(call) = self.call(Some(t)) {
+                return call;
+            }
+        }
+        match t {
+            GenType::Float if leaf => match self.chance(70) {
+                true => format!("{}", self.below(20)),
+                false => format!("{}.5", self.below(10)),
+            },
+            GenType::Float => match self.below(5) {
+                0 => format!("-{}", self.expression(t)),
+                n => {
+                    let op = ["+", "-", "*", "/"][n - 1];
+                    format!("({} {} {})", self.expression(t), op, self.expression(t))
+                }
+            },
+            GenType::Bool if leaf => self.pick(&["true", "false"]).unwrap().to_string(),
+            GenType::Bool => match self.below(5) {
+                0 => format!("!{}", self.expression(t)),
+                1 => {
+                    let op = self.pick(&["and", "or"]).unwrap();
+                    format!("({} {} {})", self.expression(t), op, self.expression(t))
+                }
+                2 => {
+                    let compared = loop {
+                        let compared = self.value_type();
+                        if compared.can_compare() {
+                            break compared;
+                        }
+                    };
+                    let op = self.pick(&["==", "!="]).unwrap();
+                    let l = self.expression(&compared);
+                    let r = self.expression(&compared);
+                    format!("({} {} {})", l, op, r)
+                }
+                _ => {
+                    let op = self.pick(&["<", "<=", ">", ">="]).unwrap();
+                    let l = self.expression(&GenType::Float);
+                    let r = self.expression(&GenType::Float);
+                    format!("({} {} {})", l, op, r)
+                }
+            },
+            GenType::String => format!("\"s{}\"", self.below(10)),
+            GenType::Enum(i) => {
+                let variant = self.below(self.enums[*i].len());
+                match self.enums[*i][variant] {
+                    true => format!("e{}v{}({})", i, variant, self.expression(&GenType::Float)),
+                    false => format!("e{}v{}()", i, variant),
+                }
+            }
+            GenType::Closure(args, ret) => self.closure(args, ret),
+            GenType::Function(args, ret) => {
+                let matching: Vec<String> = (0..self.functions.len())
+                    .filter(|i| self.functions[*i] == (args.clone(), (**ret).clone()))
+                    .map(|i| format!("f{}", i))
+                    .collect();
+                self.pick(&matching).unwrap()
+            }
+            GenType::Nil => panic!("no value of type nil"),
+        }
+    }
+
+    fn closure(&mut self, args: &[GenType], ret: &GenType) -> String {
+        let capturable: Vec<(String, GenType)> = self
+            .locals()
+            .filter(|l| l.t.can_capture())
+            .map(|l| (l.name.clone(), l.t.clone()))
+            .collect();
+        let mut captured: Vec<(String, GenType)> = vec![];
+        while captured.is_empty() || (captured.len() < capturable.len() && self.chance(40)) {
+            let local = self.pick(&capturable).unwrap();
+            if !captured.contains(&local) {
+                captured.push(local);
+            }
+        }
+        let names: Vec<String> = args.iter().map(|_| self.name("a")).collect();
+        let params: Vec<String> = names
+            .iter()
+            .zip(args.iter())
+            .map(|(name, t)| format!("{} {}", name, t))
+            .collect();
+        let captured_names: Vec<String> = captured.iter().map(|(name, _)| name.clone()).collect();
+        let mut scope: Vec<Local> = names
+            .into_iter()
+            .zip(args.iter().cloned())
+            .map(|(name, t)| Local {
+                name,
+                t,
+                assignable: true,
+            })
+            .collect();
+        for (name, t) in captured.into_iter() {
+            scope.push(Local {
+                name,
+                t,
+                assignable: true,
+            });
+        }
+        let body = self.body(scope, ret);
+        format!(
+            "fun[{}]({}) {} {}",
+            captured_names.join(", "),
+            params.join(", "),
+            ret,
+            body
+        )
+    }
+}
+
+/// Generates the program for `seed`, the same seed always gives the same
+/// program.
+pub fn generate_program(seed: u64) -> String {
+    ProgramGenerator::new(seed).program()
+}
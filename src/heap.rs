@@ -1,5 +1,8 @@
 use super::*;
+use std::any::Any;
+use std::fmt;
 use std::mem;
+use std::rc::Rc;
 
 pub type HeapAdr = u32;
 
@@ -9,6 +12,49 @@ pub struct Closure {
     pub captured: Vec<HeapAdr>,
 }
 
+/// Called with the value of a foreign object when it's freed.
+pub type ForeignDrop = Rc<dyn Fn(Box<dyn Any>)>;
+
+/// A host value scripts pass around without seeing inside, see
+/// `Externals::add_foreign_type`.
+pub struct Foreign {
+    pub type_name: String,
+    value: Option<Box<dyn Any>>,
+    on_drop: Option<ForeignDrop>,
+}
+
+impl Foreign {
+    pub fn new(type_name: String, value: Box<dyn Any>, on_drop: Option<ForeignDrop>) -> Foreign {
+        Foreign {
+            type_name,
+            value: Some(value),
+            on_drop,
+        }
+    }
+
+    pub fn value(&self) -> &dyn Any {
+        self.value.as_deref().unwrap()
+    }
+
+    pub fn value_mut(&mut self) -> &mut dyn Any {
+        self.value.as_deref_mut().unwrap()
+    }
+}
+
+impl fmt::Debug for Foreign {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Foreign({})", self.type_name)
+    }
+}
+
+impl Drop for Foreign {
+    fn drop(&mut self) {
+        if let (Some(on_drop), Some(value)) = (&self.on_drop, self.value.take()) {
+            on_drop(value);
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Obj {
     Heapified(Box<[u8]>),
@@ -17,6 +63,8 @@ pub enum Obj {
 
     Closure(Closure),
 
+    Foreign(Foreign),
+
     Free,
 }
 
@@ -33,7 +81,6 @@ impl Heap {
         }
     }
 
-    #[allow(dead_code)]
     pub fn count_objects(&self) -> HeapAdr {
         self.objects
             .iter()
@@ -96,6 +143,12 @@ impl Heap {
         }
     }
 
+    /// Removes the object at `i` however many references it has.
+    pub fn take_object(&mut self, i: HeapAdr) -> Obj {
+        self.gaps.push(i);
+        mem::replace(&mut self.objects[i as usize], (0, Obj::Free)).1
+    }
+
     pub fn get_object_ref(&self, i: HeapAdr) -> Option<&Obj> {
         self.objects.get(i as usize).map(|obj| &obj.1)
     }
@@ -104,26 +157,50 @@ impl Heap {
         self.objects.get(i as usize).map(|obj| &obj.1)
     }
 
+    /// Whether `i` is an object that hasn't been freed.
+    pub fn is_live(&self, i: HeapAdr) -> bool {
+        match self.get_object_ref(i) {
+            Some(Obj::Free) | None => false,
+            Some(_) => true,
+        }
+    }
+
     pub fn get_string_ref(&self, i: HeapAdr) -> Option<&String> {
-        self.get_object_ref(i).map(|obj| match obj {
-            Obj::String(s) => s,
-            _ => todo!(),
+        self.get_object_ref(i).and_then(|obj| match obj {
+            Obj::String(s) => Some(s),
+            _ => None,
         })
     }
 
     pub fn get_closure_ref(&self, i: HeapAdr) -> Option<&Closure> {
-        self.get_object_ref(i).map(|obj| match obj {
-            Obj::Closure(c) => c,
-            _ => todo!(),
+        self.get_object_ref(i).and_then(|obj| match obj {
+            Obj::Closure(c) => Some(c),
+            _ => None,
         })
     }
 
+    pub fn get_foreign_ref(&self, i: HeapAdr) -> Option<&Foreign> {
+        self.get_object_ref(i).and_then(|obj| match obj {
+            Obj::Foreign(f) => Some(f),
+            _ => None,
+        })
+    }
+
+    pub fn get_foreign_mut(&mut self, i: HeapAdr) -> Option<&mut Foreign> {
+        self.objects
+            .get_mut(i as usize)
+            .and_then(|obj| match &mut obj.1 {
+                Obj::Foreign(f) => Some(f),
+                _ => None,
+            })
+    }
+
     pub fn get_value(&mut self, i: HeapAdr) -> Option<&mut Box<[u8]>> {
         self.objects
             .get_mut(i as usize)
-            .map(|obj| match &mut obj.1 {
-                Obj::Heapified(v) => v,
-                _ => todo!(),
+            .and_then(|obj| match &mut obj.1 {
+                Obj::Heapified(v) => Some(v),
+                _ => None,
             })
     }
 }
@@ -0,0 +1,590 @@
+use super::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Write;
+use std::mem;
+use std::rc::Rc;
+
+/// Value of an expression in the tree-walking interpreter.
+#[derive(Debug, Clone)]
+pub enum Value<'a> {
+    Nil,
+    Float(f64),
+    Bool(bool),
+    String(Rc<String>),
+    /// A named function or a function without captured variables.
+    Function(&'a Ast),
+    Closure(Rc<ClosureValue<'a>>),
+    External(ExternalAdr),
+    /// Constructor of the enum variant with this index, before it is called.
+    Variant(u8),
+    Enum(u8, Option<Box<Value<'a>>>),
+    /// Empty only while it's lent to an external.
+    Foreign(Rc<RefCell<Option<Foreign>>>),
+}
+
+/// Captured variables are copied when the closure is created and shared by
+/// every call of it, like the heapified values of the vm.
+#[derive(Debug)]
+pub struct ClosureValue<'a> {
+    function: &'a Ast,
+    captured: Vec<Rc<RefCell<Value<'a>>>>,
+}
+
+enum Flow<'a> {
+    Next,
+    Return(Value<'a>),
+}
+
+/// Runs a type checked `Ast` directly, without compiling it. Slow, but simple
+/// enough to serve as a reference for the compiler and vm.
+pub struct Interpreter<'a> {
+    globals: HashMap<&'a str, Value<'a>>,
+    externals: Option<&'a Externals>,
+    variables: Vec<(&'a str, Rc<RefCell<Value<'a>>>)>,
+    /// Locations of the function calls being run, for the call trace of
+    /// runtime errors.
+    calls: Vec<Span>,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(externals: Option<&'a Externals>) -> Interpreter<'a> {
+        Interpreter {
+            globals: HashMap::new(),
+            externals,
+            variables: Vec::new(),
+            calls: Vec::new(),
+        }
+    }
+
+    /// Runs a program, printing to `out` like the vm does. Test blocks are
+    /// skipped.
+    pub fn run(&mut self, ast: &'a Ast, out: &mut dyn Write) -> Result<(), RuntimeError> {
+        let stmts = match ast {
+            Ast::Program(stmts) => stmts,
+            _ => panic!(),
+        };
+        // globals are known before the program runs, as they are for the vm
+        for stmt in stmts.iter() {
+            match stmt {
+                Ast::FuncDeclaration { name, func, .. } => {
+                    self.globals.insert(name, Value::Function(func));
+                }
+                Ast::EnumDeclaration { variants, .. } => {
+                    for (i, (variant, _)) in variants.iter().enumerate() {
+                        self.globals.insert(variant, Value::Variant(i as u8));
+                    }
+                }
+                _ => {}
+            }
+        }
+        for stmt in stmts.iter() {
+            self.statement(stmt, out)?;
+        }
+        Ok(())
+    }
+
+    fn resolve(&self, name: &str) -> Value<'a> {
+        if let Some((_, cell)) = self.variables.iter().rev().find(|(n, _)| *n == name) {
+            return cell.borrow().clone();
+        }
+        if let Some(value) = self.globals.get(name) {
+            return value.clone();
+        }
+        let externals = self.externals.unwrap();
+        match externals.lookup_constant(&name.to_string()) {
+            Some(ExternalConstant::Float(n)) => Value::Float(*n),
+            Some(ExternalConstant::Bool(b)) => Value::Bool(*b),
+            Some(ExternalConstant::String(s)) => Value::String(Rc::new(s.clone())),
+            Some(ExternalConstant::Tag { variant, .. }) => Value::Enum(*variant, None),
+            None => Value::External(externals.lookup_function(&name.to_string()).unwrap()),
+        }
+    }
+
+    fn declare(&mut self, name: &'a str, value: Value<'a>) {
+        self.variables.push((name, Rc::new(RefCell::new(value))));
+    }
+
+    fn statements(
+        &mut self,
+        stmts: &'a [Ast],
+        out: &mut dyn Write,
+    ) -> Result<Flow<'a>, RuntimeError> {
+        let scope = self.variables.len();
+        for stmt in stmts.iter() {
+            let flow = self.statement(stmt, out);
+            if !matches!(flow, Ok(Flow::Next)) {
+                self.variables.truncate(scope);
+                return flow;
+            }
+        }
+        self.variables.truncate(scope);
+        Ok(Flow::Next)
+    }
+
+    fn statement(&mut self, ast: &'a Ast, out: &mut dyn Write) -> Result<Flow<'a>, RuntimeError> {
+        match ast {
+            Ast::Program(stmts) | Ast::Block { cont: stmts, .. } => {
+                return self.statements(stmts, out);
+            }
+            Ast::Print { expr, .. } => match self.expression(expr, out)? {
+                Value::Float(n) => writeln!(out, "{:?}", n).unwrap(),
+                Value::Bool(b) => writeln!(out, "{:?}", b).unwrap(),
+                Value::String(s) => writeln!(out, "{}", s).unwrap(),
+                _ => panic!(),
+            },
+            Ast::Return { expr, .. } => {
+                let value = match expr {
+                    Some(expr) => self.expression(expr, out)?,
+                    None => Value::Nil,
+                };
+                return Ok(Flow::Return(value));
+            }
+            Ast::Declaration { name, expr, .. } => {
+                let value = self.expression(expr, out)?;
+                self.declare(name, value);
+            }
+            Ast::FuncDeclaration { .. }
+            | Ast::EnumDeclaration { .. }
+            | Ast::Test { .. }
+            | Ast::Import { .. } => {}
+            Ast::Switch {
+                head,
+                cases,
+                default,
+                ..
+            } => {
+                let value = self.expression(head, out)?;
+                for (case, body) in cases.iter() {
+                    if equal(&value, &self.expression(case, out)?) {
+                        return self.statement(body, out);
+                    }
+                }
+                if let Some(default) = default {
+                    return self.statement(default, out);
+                }
+            }
+            Ast::If {
+                condition,
+                body,
+                else_body,
+                ..
+            } => {
+                if self.condition(condition, out)? {
+                    return self.statement(body, out);
+                } else if let Some(else_body) = else_body {
+                    return self.statement(else_body, out);
+                }
+            }
+            Ast::While {
+                condition, body, ..
+            } => {
+                while self.condition(condition, out)? {
+                    if let Flow::Return(value) = self.statement(body, out)? {
+                        return Ok(Flow::Return(value));
+                    }
+                }
+            }
+            Ast::ExprStatement { expr, .. } => {
+                self.expression(expr, out)?;
+            }
+            Ast::Assert { condition, pos } => {
+                if !self.condition(condition, out)? {
+                    let message = format!("assertion failed: {}", format_expression(condition));
+                    return Err(self.fail(message, *pos));
+                }
+            }
+            Ast::AssertEq { lhs, rhs, pos, .. } => {
+                let l = self.expression(lhs, out)?;
+                let r = self.expression(rhs, out)?;
+                if !equal(&l, &r) {
+                    let mut message = format!(
+                        "assertion failed: {} == {}",
+                        format_expression(lhs),
+                        format_expression(rhs)
+                    );
+                    match (l, r) {
+                        (Value::Float(l), Value::Float(r)) => {
+                            message.push_str(&format!("\n  left: {:?}\n right: {:?}", l, r))
+                        }
+                        (Value::Bool(l), Value::Bool(r)) => {
+                            message.push_str(&format!("\n  left: {:?}\n right: {:?}", l, r))
+                        }
+                        (Value::String(l), Value::String(r)) => {
+                            message.push_str(&format!("\n  left: {:?}\n right: {:?}", l, r))
+                        }
+                        _ => {}
+                    }
+                    return Err(self.fail(message, *pos));
+                }
+            }
+            _ => panic!("{:?} is not a statement", ast),
+        }
+        Ok(Flow::Next)
+    }
+
+    fn condition(&mut self, ast: &'a Ast, out: &mut dyn Write) -> Result<bool, RuntimeError> {
+        match self.expression(ast, out)? {
+            Value::Bool(b) => Ok(b),
+            _ => panic!(),
+        }
+    }
+
+    fn float(&mut self, ast: &'a Ast, out: &mut dyn Write) -> Result<f64, RuntimeError> {
+        match self.expression(ast, out)? {
+            Value::Float(n) => Ok(n),
+            _ => panic!(),
+        }
+    }
+
+    fn expression(&mut self, ast: &'a Ast, out: &mut dyn Write) -> Result<Value<'a>, RuntimeError> {
+        Ok(match ast {
+            Ast::Variable { name, .. } => self.resolve(name),
+            Ast::Assign { name, expr, .. } => {
+                let value = self.expression(expr, out)?;
+                let (_, cell) = self
+                    .variables
+                    .iter()
+                    .rev()
+                    .find(|(n, _)| n == name)
+                    .unwrap();
+                *cell.borrow_mut() = value.clone();
+                value
+            }
+            Ast::Function { captured, .. } if captured.is_empty() => Value::Function(ast),
+            Ast::Function { captured, .. } => Value::Closure(Rc::new(ClosureValue {
+                function: ast,
+                captured: captured
+                    .iter()
+                    .map(|(name, _)| Rc::new(RefCell::new(self.resolve(name))))
+                    .collect(),
+            })),
+            Ast::Call {
+                ident, args, pos, ..
+            } => {
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args.iter() {
+                    values.push(self.expression(arg, out)?);
+                }
+                match self.expression(ident, out)? {
+                    Value::Function(function) => {
+                        self.calls.push(*pos);
+                        let value = self.call(function, &[], values, out)?;
+                        self.calls.pop();
+                        value
+                    }
+                    Value::Closure(closure) => {
+                        self.calls.push(*pos);
+                        let value = self.call(closure.function, &closure.captured, values, out)?;
+                        self.calls.pop();
+                        value
+                    }
+                    Value::External(adr) => self.call_external(ident, adr, values, *pos, out)?,
+                    Value::Variant(variant) => Value::Enum(variant, values.pop().map(Box::new)),
+                    _ => panic!(),
+                }
+            }
+            Ast::Float(n, _) => Value::Float(*n),
+            Ast::Bool(b, _) => Value::Bool(*b),
+            Ast::String(s, _) => Value::String(Rc::new(s.clone())),
+            Ast::Negate(n, _) => Value::Float(-self.float(n, out)?),
+            Ast::Not(b, _) => Value::Bool(!self.condition(b, out)?),
+            Ast::Multiply(l, r, _, _) => Value::Float(self.float(l, out)? * self.float(r, out)?),
+            Ast::Divide(l, r, _, _) => Value::Float(self.float(l, out)? / self.float(r, out)?),
+            Ast::Add(l, r, _, _) => Value::Float(self.float(l, out)? + self.float(r, out)?),
+            Ast::Sub(l, r, _, _) => Value::Float(self.float(l, out)? - self.float(r, out)?),
+            Ast::Equal(l, r, _, _) => {
+                let l = self.expression(l, out)?;
+                Value::Bool(equal(&l, &self.expression(r, out)?))
+            }
+            Ast::NotEqual(l, r, _, _) => {
+                let l = self.expression(l, out)?;
+                Value::Bool(!equal(&l, &self.expression(r, out)?))
+            }
+            Ast::Greater(l, r, _, _) => Value::Bool(self.float(l, out)? > self.float(r, out)?),
+            Ast::GreaterEqual(l, r, _, _) => {
+                Value::Bool(self.float(l, out)? >= self.float(r, out)?)
+            }
+            Ast::Lesser(l, r, _, _) => Value::Bool(self.float(l, out)? < self.float(r, out)?),
+            Ast::LesserEqual(l, r, _, _) => Value::Bool(self.float(l, out)? <= self.float(r, out)?),
+            Ast::And(l, r, _) => Value::Bool(self.condition(l, out)? && self.condition(r, out)?),
+            Ast::Or(l, r, _) => Value::Bool(self.condition(l, out)? || self.condition(r, out)?),
+            _ => panic!("{:?} is not an expression", ast),
+        })
+    }
+
+    fn call(
+        &mut self,
+        function: &'a Ast,
+        captured: &[Rc<RefCell<Value<'a>>>],
+        args: Vec<Value<'a>>,
+        out: &mut dyn Write,
+    ) -> Result<Value<'a>, RuntimeError> {
+        let (body, arg_names, captured_names) = match function {
+            Ast::Function {
+                body,
+                args,
+                captured,
+                ..
+            } => (body, args, captured),
+            _ => panic!(),
+        };
+        let mut frame = Vec::with_capacity(args.len() + captured.len());
+        for ((name, _, _), value) in arg_names.iter().zip(args) {
+            frame.push((name.as_str(), Rc::new(RefCell::new(value))));
+        }
+        for ((name, _), cell) in captured_names.iter().zip(captured) {
+            frame.push((name.as_str(), cell.clone()));
+        }
+        let caller = mem::replace(&mut self.variables, frame);
+        let flow = self.statement(body, out);
+        self.variables = caller;
+        Ok(match flow? {
+            Flow::Return(value) => value,
+            Flow::Next => Value::Nil,
+        })
+    }
+
+    /// Calls an external with a scratch stack and heap, converting the
+    /// arguments and result to the vm representation.
+    fn call_external(
+        &mut self,
+        ident: &Ast,
+        adr: ExternalAdr,
+        args: Vec<Value<'a>>,
+        pos: Span,
+        out: &mut dyn Write,
+    ) -> Result<Value<'a>, RuntimeError> {
+        let ret_t = match ident {
+            Ast::Variable {
+                t: Some(AstType::ExternalFunction(_, ret_t)),
+                ..
+            } => ret_t,
+            _ => panic!(),
+        };
+        let mut stack = Stack::new();
+        let mut heap = Heap::new();
+        let mut lent = Lent {
+            foreign: vec![],
+            callees: vec![],
+        };
+        for arg in args.into_iter() {
+            lent.push(arg, &mut stack, &mut heap);
+        }
+        let externals = self.externals.unwrap();
+        let mut callbacks = InterpreterCallbacks {
+            interpreter: self,
+            lent,
+        };
+        let result = externals.dispatch(
+            adr,
+            &mut ExternalContext::new(&mut stack, &mut heap, Some(externals), &mut callbacks, out),
+        );
+        let lent = callbacks.lent;
+        let value = match result {
+            Err(error) => Err(error.at_call(self.fail(String::new(), pos))),
+            Ok(()) => Ok(lent.pop(ret_t, &mut stack, &mut heap)),
+        };
+        for (adr, cell) in lent.foreign.into_iter() {
+            match heap.take_object(adr) {
+                Obj::Foreign(foreign) => *cell.borrow_mut() = Some(foreign),
+                _ => panic!(),
+            }
+        }
+        value
+    }
+
+    fn fail(&self, message: String, span: Span) -> RuntimeError {
+        RuntimeError {
+            message,
+            span,
+            trace: self.calls.iter().rev().cloned().collect(),
+        }
+    }
+}
+
+/// Values lent to an external for a call, while they're on its scratch stack
+/// and heap.
+struct Lent<'a> {
+    /// Foreign objects are moved to the scratch heap for the call and back
+    /// after it, with a reference kept so the external can't free them.
+    foreign: Vec<(HeapAdr, Rc<RefCell<Option<Foreign>>>)>,
+    /// Script functions and closures, which are passed by their index here
+    /// in place of a chunk address.
+    callees: Vec<Value<'a>>,
+}
+
+impl<'a> Lent<'a> {
+    fn push(&mut self, value: Value<'a>, stack: &mut Stack, heap: &mut Heap) {
+        match value {
+            Value::Nil => {}
+            Value::Float(n) => {
+                stack.push(n);
+            }
+            Value::Bool(b) => {
+                stack.push(b);
+            }
+            Value::String(s) => {
+                let adr = heap.add_object(Obj::String((*s).clone()));
+                stack.push(adr);
+            }
+            Value::Enum(variant, None) => {
+                stack.push(variant);
+            }
+            Value::Function(_) => {
+                stack.push(self.callees.len() as ChunkAdr);
+                self.callees.push(value);
+            }
+            Value::Closure(_) => {
+                let adr = heap.add_object(Obj::Closure(Closure {
+                    function: self.callees.len() as ChunkAdr,
+                    captured: vec![],
+                }));
+                stack.push(adr);
+                self.callees.push(value);
+            }
+            Value::Foreign(cell) => {
+                let adr = match self
+                    .foreign
+                    .iter()
+                    .find(|(_, lent)| Rc::ptr_eq(lent, &cell))
+                {
+                    Some((adr, _)) => *adr,
+                    None => {
+                        let foreign = cell.borrow_mut().take().unwrap();
+                        let adr = heap.add_object(Obj::Foreign(foreign));
+                        self.foreign.push((adr, cell));
+                        adr
+                    }
+                };
+                heap.increase_rc(adr);
+                stack.push(adr);
+            }
+            Value::Enum(_, Some(_)) | Value::External(_) | Value::Variant(_) => {
+                panic!("{:?} can't be passed to or from an external", value)
+            }
+        }
+    }
+
+    fn pop(&self, t: &AstType, stack: &mut Stack, heap: &mut Heap) -> Value<'a> {
+        match t {
+            AstType::Nil => Value::Nil,
+            AstType::Float => Value::Float(stack.pop()),
+            AstType::Bool => Value::Bool(stack.pop()),
+            AstType::String => {
+                let adr: HeapAdr = stack.pop();
+                Value::String(Rc::new(heap.get_string_ref(adr).unwrap().clone()))
+            }
+            AstType::EnumVariant { max_size: 0, .. } => Value::Enum(stack.pop(), None),
+            AstType::Function(..) => {
+                let i: ChunkAdr = stack.pop();
+                self.callees[i as usize].clone()
+            }
+            AstType::Closure(..) => {
+                let adr: HeapAdr = stack.pop();
+                let i = heap.get_closure_ref(adr).unwrap().function;
+                self.callees[i as usize].clone()
+            }
+            AstType::Foreign(_) => {
+                let adr: HeapAdr = stack.pop();
+                match self.foreign.iter().find(|(lent, _)| *lent == adr) {
+                    Some((_, cell)) => Value::Foreign(cell.clone()),
+                    None => match heap.take_object(adr) {
+                        Obj::Foreign(foreign) => {
+                            Value::Foreign(Rc::new(RefCell::new(Some(foreign))))
+                        }
+                        _ => panic!(),
+                    },
+                }
+            }
+            _ => panic!("values of type {} aren't passed to or from externals", t),
+        }
+    }
+}
+
+/// Runs the callbacks of an external on the interpreter that called it.
+struct InterpreterCallbacks<'i, 'a> {
+    interpreter: &'i mut Interpreter<'a>,
+    lent: Lent<'a>,
+}
+
+impl<'i, 'a> Callbacks for InterpreterCallbacks<'i, 'a> {
+    fn call(
+        &mut self,
+        callee: Callee,
+        args_t: &[AstType],
+        stack: &mut Stack,
+        heap: &mut Heap,
+        out: &mut dyn Write,
+    ) -> Result<(), RuntimeError> {
+        let mut args: Vec<Value<'a>> = args_t
+            .iter()
+            .rev()
+            .map(|t| self.lent.pop(t, stack, heap))
+            .collect();
+        args.reverse();
+        let i = match callee {
+            Callee::Function(i) => i,
+            Callee::Closure(adr) => heap.get_closure_ref(adr).unwrap().function,
+        };
+        // the trace of a failing callback ends at the external, as on the vm
+        let calls = mem::take(&mut self.interpreter.calls);
+        let result = match self.lent.callees[i as usize].clone() {
+            Value::Function(function) => self.interpreter.call(function, &[], args, out),
+            Value::Closure(closure) => {
+                self.interpreter
+                    .call(closure.function, &closure.captured, args, out)
+            }
+            _ => panic!(),
+        };
+        self.interpreter.calls = calls;
+        self.lent.push(result?, stack, heap);
+        Ok(())
+    }
+}
+
+fn equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Float(a), Value::Float(b)) => a == b,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::String(a), Value::String(b)) => a == b,
+        (Value::Enum(a, a_value), Value::Enum(b, b_value)) => {
+            a == b
+                && match (a_value, b_value) {
+                    (Some(a_value), Some(b_value)) => equal(a_value, b_value),
+                    (None, None) => true,
+                    _ => false,
+                }
+        }
+        _ => false,
+    }
+}
+
+/// What running a program printed, and the error that stopped it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Execution {
+    pub output: String,
+    pub error: Option<RuntimeError>,
+}
+
+/// Compiles and runs a type checked program on the vm.
+pub fn execute_vm(ast: &Ast, externals: Option<&Externals>) -> Execution {
+    let mut vm = VM::new(Compiler::compile(ast, externals), externals);
+    let mut output = vec![];
+    let error = vm.run(&mut output).err();
+    Execution {
+        output: String::from_utf8_lossy(&output).to_string(),
+        error,
+    }
+}
+
+/// Runs a type checked program on the tree-walking interpreter.
+pub fn execute_ast(ast: &Ast, externals: Option<&Externals>) -> Execution {
+    let mut interpreter = Interpreter::new(externals);
+    let mut output = vec![];
+    let error = interpreter.run(ast, &mut output).err();
+    Execution {
+        output: String::from_utf8_lossy(&output).to_string(),
+        error,
+    }
+}
@@ -53,6 +53,8 @@ pub enum TokenType {
     While,
     Enum,
     Switch,
+    Assert,
+    AssertEq,
 
     TypeFloat,
     TypeBool,
@@ -68,19 +70,53 @@ pub struct Token {
     pub end: usize,
     pub t: TokenType,
 }
+impl Token {
+    pub fn span(&self) -> Span {
+        Span::new(self.start, self.end)
+    }
+}
+
+/// Character range `start..end` in the source.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+}
+
+/// A `//` comment, kept next to the tokens as trivia for the formatter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    /// The comment including the leading `//`, without the line break.
+    pub text: String,
+    pub span: Span,
+}
 
 pub struct Lexer {
     tokens: Vec<Token>,
+    comments: Vec<Comment>,
     current: usize,
 }
 
 impl Lexer {
     pub fn new(string: &String) -> LexerResult<Lexer> {
+        let (tokens, comments) = scan_with_comments(string)?;
         Ok(Lexer {
-            tokens: scan(string)?,
+            tokens,
+            comments,
             current: 0,
         })
     }
+    pub fn tokens(&self) -> &Vec<Token> {
+        &self.tokens
+    }
+    pub fn comments(&self) -> &Vec<Comment> {
+        &self.comments
+    }
     pub fn prev(&self) -> Option<Token> {
         self.tokens.get(self.current - 1).cloned()
     }
@@ -93,6 +129,9 @@ impl Lexer {
     pub fn current_t(&self) -> TokenType {
         self.tokens[self.current].t.clone()
     }
+    pub fn peek_t(&self) -> Option<TokenType> {
+        self.peek().map(|t| t.t)
+    }
     pub fn peek(&self) -> Option<Token> {
         self.tokens.get(self.current + 1).cloned()
     }
@@ -108,7 +147,12 @@ impl Lexer {
 }
 
 pub fn scan(string: &String) -> LexerResult<Vec<Token>> {
+    scan_with_comments(string).map(|(tokens, _)| tokens)
+}
+
+pub fn scan_with_comments(string: &String) -> LexerResult<(Vec<Token>, Vec<Comment>)> {
     let mut tokens = vec![];
+    let mut comments = vec![];
     let mut chars = string.chars().enumerate().peekable();
     loop {
         let (i, c) = match chars.next() {
@@ -133,11 +177,16 @@ pub fn scan(string: &String) -> LexerResult<Vec<Token>> {
             '|' => (i, i + 1, TokenType::Bar),
             '/' => {
                 if chars.peek().map(|(_, cl)| cl == &'/').unwrap_or(false) {
-                    loop {
-                        if chars.next().map(|(_, cmt)| cmt == '\n').unwrap_or(true) {
-                            break;
-                        }
+                    let mut text = vec![c];
+                    while let Some((_, cmt)) = chars.next_if(|(_, cmt)| *cmt != '\n') {
+                        text.push(cmt);
                     }
+                    let text = text.into_iter().collect::<String>();
+                    let text = text.trim_end().to_string();
+                    comments.push(Comment {
+                        span: Span::new(i, i + text.chars().count()),
+                        text,
+                    });
                     continue;
                 } else {
                     (i, i + 1, TokenType::Slash)
@@ -223,11 +272,11 @@ pub fn scan(string: &String) -> LexerResult<Vec<Token>> {
             }
 
             _ => {
-                if c.is_alphanumeric() {
+                if c.is_alphanumeric() || c == '_' {
                     let mut literal = vec![c];
                     loop {
                         if let Some((_, l)) = chars.peek() {
-                            if !l.is_alphanumeric() {
+                            if !(l.is_alphanumeric() || *l == '_') {
                                 break;
                             }
                             let (_, l) = chars.next().unwrap();
@@ -267,6 +316,8 @@ pub fn scan(string: &String) -> LexerResult<Vec<Token>> {
                     "while" => TokenType::While,
                     "enum" => TokenType::Enum,
                     "switch" => TokenType::Switch,
+                    "assert" => TokenType::Assert,
+                    "assert_eq" => TokenType::AssertEq,
                     "nil" => TokenType::TypeNil,
                     "float" => TokenType::TypeFloat,
                     "bool" => TokenType::TypeBool,
@@ -284,5 +335,5 @@ pub fn scan(string: &String) -> LexerResult<Vec<Token>> {
         end: string.len(),
         t: TokenType::Eof,
     });
-    return Ok(tokens);
+    return Ok((tokens, comments));
 }
@@ -1,21 +1,39 @@
+mod capabilities;
 mod chunk;
 mod compiler;
 mod debugger;
+mod error_codes;
 mod externals;
+mod formatter;
+mod generator;
 mod heap;
+mod interpreter;
+mod json;
 mod lexer;
+mod lsp;
 mod parser;
+mod repl;
 mod stack;
+mod testing;
 mod types;
 mod vm;
 
+pub use capabilities::*;
 pub use chunk::*;
 pub use compiler::*;
 pub use debugger::*;
+pub use error_codes::*;
 pub use externals::*;
+pub use formatter::*;
+pub use generator::*;
 pub use heap::*;
+pub use interpreter::*;
+pub use json::*;
 pub use lexer::*;
+pub use lsp::*;
 pub use parser::*;
+pub use repl::*;
 pub use stack::*;
+pub use testing::*;
 pub use types::*;
 pub use vm::*;
@@ -0,0 +1,777 @@
+use super::*;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::panic::{self, AssertUnwindSafe};
+
+const KEYWORDS: [&str; 19] = [
+    "var",
+    "fun",
+    "enum",
+    "if",
+    "else",
+    "while",
+    "return",
+    "switch",
+    "print",
+    "true",
+    "false",
+    "and",
+    "or",
+    "float",
+    "bool",
+    "str",
+    "test",
+    "assert",
+    "assert_eq",
+];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SymbolKind {
+    Variable,
+    Function,
+    Enum,
+    EnumVariant,
+    External,
+}
+
+/// A name that can be jumped to or completed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub t: Option<AstType>,
+    /// Where the name is declared, externals have none.
+    pub span: Option<Span>,
+}
+
+/// A checked document, kept by the language server between requests.
+pub struct Analysis {
+    pub diagnostics: Vec<Diagnostic>,
+    ast: Option<Ast>,
+    tokens: Vec<Token>,
+}
+
+/// Lexes, parses and type checks `source`, keeping the annotated ast even
+/// when there are type errors.
+pub fn analyze(source: &String, externals: Option<&Externals>) -> Analysis {
+    let mut analysis = Analysis {
+        diagnostics: vec![],
+        ast: None,
+        tokens: vec![],
+    };
+    let mut lexer = match Lexer::new(source) {
+        Ok(lexer) => lexer,
+        Err(error) => {
+            analysis.diagnostics = lexer_diagnostics(error);
+            return analysis;
+        }
+    };
+    analysis.tokens = lexer.tokens().clone();
+    let mut ast = match parse(&mut lexer) {
+        Ok(ast) => ast,
+        Err(error) => {
+            analysis.diagnostics = parser_diagnostics(error);
+            return analysis;
+        }
+    };
+    let mut type_checker = TypeChecker::new(externals);
+    let result = type_checker.annotate(&mut ast);
+    for warning in type_checker.take_warnings().into_iter() {
+        analysis.diagnostics.extend(type_diagnostics(warning));
+    }
+    if let Err(error) = result {
+        analysis.diagnostics.extend(type_diagnostics(error));
+    }
+    analysis
+        .diagnostics
+        .sort_by_key(|diagnostic| diagnostic.span.start);
+    analysis.ast = Some(ast);
+    analysis
+}
+
+fn contains(span: Span, offset: usize) -> bool {
+    span.start <= offset && offset <= span.end
+}
+
+fn children(ast: &Ast) -> Vec<&Ast> {
+    match ast {
+        Ast::Program(cont) | Ast::Block { cont, .. } => cont.iter().collect(),
+        Ast::Print { expr, .. }
+        | Ast::Declaration { expr, .. }
+        | Ast::Assign { expr, .. }
+        | Ast::ExprStatement { expr, .. }
+        | Ast::Negate(expr, _)
+        | Ast::Not(expr, _) => vec![expr],
+        Ast::Return { expr, .. } => expr.iter().map(|expr| &**expr).collect(),
+        Ast::FuncDeclaration { func, .. } => vec![func],
+        Ast::Test { body, .. } => vec![body],
+        Ast::Assert { condition, .. } => vec![condition],
+        Ast::AssertEq { lhs, rhs, .. } => vec![lhs, rhs],
+        Ast::Function { body, .. } => vec![body],
+        Ast::Switch {
+            head,
+            cases,
+            default,
+            ..
+        } => {
+            let mut children = vec![&**head];
+            for (case, body) in cases.iter() {
+                children.push(case);
+                children.push(body);
+            }
+            children.extend(default.iter().map(|body| &**body));
+            children
+        }
+        Ast::If {
+            condition,
+            body,
+            else_body,
+            ..
+        } => {
+            let mut children = vec![&**condition, &**body];
+            children.extend(else_body.iter().map(|body| &**body));
+            children
+        }
+        Ast::While {
+            condition, body, ..
+        } => vec![condition, body],
+        Ast::Call { ident, args, .. } => {
+            let mut children = vec![&**ident];
+            children.extend(args.iter());
+            children
+        }
+        Ast::Multiply(lhs, rhs, _, _)
+        | Ast::Divide(lhs, rhs, _, _)
+        | Ast::Add(lhs, rhs, _, _)
+        | Ast::Sub(lhs, rhs, _, _)
+        | Ast::Equal(lhs, rhs, _, _)
+        | Ast::NotEqual(lhs, rhs, _, _)
+        | Ast::Greater(lhs, rhs, _, _)
+        | Ast::GreaterEqual(lhs, rhs, _, _)
+        | Ast::Lesser(lhs, rhs, _, _)
+        | Ast::LesserEqual(lhs, rhs, _, _)
+        | Ast::And(lhs, rhs, _)
+        | Ast::Or(lhs, rhs, _) => vec![lhs, rhs],
+        Ast::EnumDeclaration { .. }
+        | Ast::Import { .. }
+        | Ast::Variable { .. }
+        | Ast::Float(..)
+        | Ast::Bool(..)
+        | Ast::String(..) => vec![],
+    }
+}
+
+impl Analysis {
+    /// Span of the first `name` identifier inside `within`.
+    fn name_span(&self, name: &str, within: Span) -> Option<Span> {
+        self.tokens
+            .iter()
+            .filter(|token| token.start >= within.start && token.end <= within.end)
+            .find(|token| matches!(&token.t, TokenType::Identifier(n) if n == name))
+            .map(|token| token.span())
+    }
+
+    /// Top level functions, enums and enum variants.
+    pub fn globals(&self) -> Vec<Symbol> {
+        let mut globals = vec![];
+        let stmts = match &self.ast {
+            Some(Ast::Program(stmts)) => stmts,
+            _ => return globals,
+        };
+        for stmt in stmts.iter() {
+            match stmt {
+                Ast::FuncDeclaration {
+                    name,
+                    args_t,
+                    ret_t,
+                    pos,
+                    ..
+                } => globals.push(Symbol {
+                    name: name.clone(),
+                    kind: SymbolKind::Function,
+                    t: Some(AstType::Function(args_t.clone(), Box::new(ret_t.clone()))),
+                    span: self.name_span(name, *pos),
+                }),
+                Ast::EnumDeclaration {
+                    name,
+                    variants,
+                    pos,
+                } => {
+                    globals.push(Symbol {
+                        name: name.clone(),
+                        kind: SymbolKind::Enum,
+                        t: None,
+                        span: self.name_span(name, *pos),
+                    });
+                    for (variant, wraps) in variants.iter() {
+                        let wraps = match wraps {
+                            AstType::Nil => vec![],
+                            t => vec![t.clone()],
+                        };
+                        let enum_t = AstType::Unresolved(name.clone());
+                        globals.push(Symbol {
+                            name: variant.clone(),
+                            kind: SymbolKind::EnumVariant,
+                            t: Some(AstType::Function(wraps, Box::new(enum_t))),
+                            span: self.name_span(variant, *pos),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        globals
+    }
+
+    /// Local variables and parameters visible at `offset`, outer ones first.
+    pub fn locals(&self, offset: usize) -> Vec<Symbol> {
+        let mut locals = vec![];
+        if let Some(ast) = &self.ast {
+            self.collect_locals(ast, offset, &mut locals);
+        }
+        locals
+    }
+
+    fn collect_locals(&self, ast: &Ast, offset: usize, locals: &mut Vec<Symbol>) {
+        match ast {
+            Ast::Program(stmts) | Ast::Block { cont: stmts, .. } => {
+                for stmt in stmts.iter() {
+                    if let Ast::Declaration { name, t, pos, .. } = stmt {
+                        if pos.end <= offset {
+                            locals.push(Symbol {
+                                name: name.clone(),
+                                kind: SymbolKind::Variable,
+                                t: t.clone(),
+                                span: self.name_span(name, *pos),
+                            });
+                            continue;
+                        }
+                    }
+                    if contains(stmt.span(), offset) {
+                        self.collect_locals(stmt, offset, locals);
+                        return;
+                    }
+                }
+            }
+            Ast::Function { body, args, .. } => {
+                if contains(body.span(), offset) {
+                    for (name, t, span) in args.iter() {
+                        locals.push(Symbol {
+                            name: name.clone(),
+                            kind: SymbolKind::Variable,
+                            t: Some(t.clone()),
+                            span: Some(Span::new(span.start, span.start + name.chars().count())),
+                        });
+                    }
+                    self.collect_locals(body, offset, locals);
+                }
+            }
+            ast => {
+                if let Some(child) = children(ast)
+                    .into_iter()
+                    .find(|child| contains(child.span(), offset))
+                {
+                    self.collect_locals(child, offset, locals);
+                }
+            }
+        }
+    }
+
+    fn lookup(&self, name: &str, offset: usize) -> Option<Symbol> {
+        let mut locals = self.locals(offset);
+        locals.reverse();
+        locals
+            .into_iter()
+            .chain(self.globals())
+            .find(|symbol| symbol.name == name)
+    }
+
+    /// Type of an expression, for hover.
+    fn expression_type(&self, ast: &Ast) -> Option<AstType> {
+        match ast {
+            Ast::Float(..) | Ast::Negate(..) => Some(AstType::Float),
+            Ast::Bool(..)
+            | Ast::Not(..)
+            | Ast::Equal(..)
+            | Ast::NotEqual(..)
+            | Ast::Greater(..)
+            | Ast::GreaterEqual(..)
+            | Ast::Lesser(..)
+            | Ast::LesserEqual(..)
+            | Ast::And(..)
+            | Ast::Or(..) => Some(AstType::Bool),
+            Ast::String(..) => Some(AstType::String),
+            Ast::Variable { name, t, pos } => match t {
+                Some(AstType::Unresolved(_)) => self.lookup(name, pos.start).and_then(|s| s.t),
+                t => t.clone(),
+            },
+            Ast::Assign { t, .. }
+            | Ast::Multiply(_, _, t, _)
+            | Ast::Divide(_, _, t, _)
+            | Ast::Add(_, _, t, _)
+            | Ast::Sub(_, _, t, _) => t.clone(),
+            Ast::Call { ident, .. } => match self.expression_type(ident)? {
+                AstType::Function(_, ret_t)
+                | AstType::Closure(_, ret_t)
+                | AstType::ExternalFunction(_, ret_t) => Some(*ret_t),
+                _ => None,
+            },
+            Ast::Function {
+                args,
+                captured,
+                ret_t,
+                ..
+            } => {
+                let args = args.iter().map(|(_, t, _)| t.clone()).collect();
+                Some(if captured.is_empty() {
+                    AstType::Function(args, Box::new(ret_t.clone()))
+                } else {
+                    AstType::Closure(args, Box::new(ret_t.clone()))
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Text describing the name or expression at `offset`, and its span.
+    pub fn hover(&self, offset: usize) -> Option<(String, Span)> {
+        let mut ast = self.ast.as_ref()?;
+        while let Some(child) = children(ast)
+            .into_iter()
+            .find(|child| contains(child.span(), offset))
+        {
+            match ast {
+                Ast::FuncDeclaration {
+                    name,
+                    args_t,
+                    ret_t,
+                    pos,
+                    ..
+                } => {
+                    let span = self.name_span(name, *pos)?;
+                    if contains(span, offset) {
+                        let t = AstType::Function(args_t.clone(), Box::new(ret_t.clone()));
+                        return Some((format!("fun {}: {}", name, t), span));
+                    }
+                }
+                Ast::Function { args, .. } => {
+                    if let Some((name, t, span)) =
+                        args.iter().find(|(_, _, span)| contains(*span, offset))
+                    {
+                        return Some((format!("{}: {}", name, t), *span));
+                    }
+                }
+                _ => {}
+            }
+            ast = child;
+        }
+        let span = ast.span();
+        match ast {
+            Ast::Declaration { name, t, pos, .. } => {
+                let span = self.name_span(name, *pos)?;
+                let t = t.as_ref()?;
+                contains(span, offset).then(|| (format!("var {}: {}", name, t), span))
+            }
+            Ast::Variable { name, .. } => {
+                let t = self.expression_type(ast)?;
+                Some((format!("{}: {}", name, t), span))
+            }
+            Ast::Assign { name, t, .. } => {
+                let span = self.name_span(name, span)?;
+                t.as_ref().map(|t| (format!("{}: {}", name, t), span))
+            }
+            _ => self.expression_type(ast).map(|t| (t.to_string(), span)),
+        }
+    }
+
+    /// Where the name at `offset` is declared.
+    pub fn definition(&self, offset: usize) -> Option<Span> {
+        let name = self.tokens.iter().find_map(|token| match &token.t {
+            TokenType::Identifier(name) if contains(token.span(), offset) => Some(name),
+            _ => None,
+        })?;
+        self.lookup(name, offset)?.span
+    }
+
+    /// Names that can be used at `offset`, inner variables shadowing outer
+    /// ones.
+    pub fn completions(&self, offset: usize, externals: Option<&Externals>) -> Vec<Symbol> {
+        let mut symbols: Vec<Symbol> = vec![];
+        for symbol in self.locals(offset).into_iter().rev().chain(self.globals()) {
+            if !symbols.iter().any(|s| s.name == symbol.name) {
+                symbols.push(symbol);
+            }
+        }
+        if let Some(externals) = externals {
+            let mut names: Vec<&String> = externals.names().collect();
+            names.sort();
+            for name in names.into_iter() {
+                symbols.push(Symbol {
+                    name: name.clone(),
+                    kind: SymbolKind::External,
+                    t: externals.lookup_type(name),
+                    span: None,
+                });
+            }
+        }
+        symbols
+    }
+}
+
+fn position(lines: &SourceLines, pos: usize) -> Json {
+    let (line, col) = lines.location(pos);
+    let character: usize = lines
+        .line(line)
+        .chars()
+        .take(col)
+        .map(char::len_utf16)
+        .sum();
+    Json::object(vec![("line", line.into()), ("character", character.into())])
+}
+
+fn range(lines: &SourceLines, span: Span) -> Json {
+    Json::object(vec![
+        ("start", position(lines, span.start)),
+        ("end", position(lines, span.end)),
+    ])
+}
+
+/// Position of an lsp `{line, character}`, where characters count utf-16
+/// code units.
+fn offset(lines: &SourceLines, position: &Json) -> usize {
+    let line = position.get("line").as_f64().unwrap_or(0.0) as usize;
+    let character = position.get("character").as_f64().unwrap_or(0.0) as usize;
+    let start = lines.offset(line, 0);
+    if lines.location(start).0 != line {
+        return start;
+    }
+    let mut units = 0;
+    let col = lines
+        .line(line)
+        .chars()
+        .take_while(|c| {
+            units += c.len_utf16();
+            units <= character
+        })
+        .count();
+    lines.offset(line, col)
+}
+
+fn diagnostic_lsp(uri: &str, lines: &SourceLines, diagnostic: &Diagnostic) -> Json {
+    let mut message = diagnostic.message.clone();
+    if let Some(help) = &diagnostic.help {
+        message.push_str(&format!("\nhelp: {}", help));
+    }
+    let related = diagnostic
+        .labels
+        .iter()
+        .map(|(label, span)| {
+            Json::object(vec![
+                (
+                    "location",
+                    Json::object(vec![("uri", uri.into()), ("range", range(lines, *span))]),
+                ),
+                ("message", label.as_str().into()),
+            ])
+        })
+        .collect();
+    Json::object(vec![
+        ("range", range(lines, diagnostic.span)),
+        (
+            "severity",
+            Json::Number(if diagnostic.code.is_warning() {
+                2.0
+            } else {
+                1.0
+            }),
+        ),
+        ("code", diagnostic.code.code().into()),
+        ("source", "imglang".into()),
+        ("message", message.into()),
+        ("relatedInformation", Json::Array(related)),
+    ])
+}
+
+fn completion_item(symbol: &Symbol) -> Json {
+    // CompletionItemKind from the lsp specification
+    let kind = match symbol.kind {
+        SymbolKind::Variable => 6.0,
+        SymbolKind::Function | SymbolKind::External => 3.0,
+        SymbolKind::Enum => 13.0,
+        SymbolKind::EnumVariant => 20.0,
+    };
+    let mut fields = vec![
+        ("label", symbol.name.as_str().into()),
+        ("kind", Json::Number(kind)),
+    ];
+    if let Some(t) = &symbol.t {
+        fields.push(("detail", t.to_string().into()));
+    }
+    Json::object(fields)
+}
+
+fn response(id: &Json, result: Json) -> Json {
+    Json::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("id", id.clone()),
+        ("result", result),
+    ])
+}
+
+fn notification(method: &str, params: Json) -> Json {
+    Json::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("method", method.into()),
+        ("params", params),
+    ])
+}
+
+struct Document {
+    text: String,
+    analysis: Analysis,
+    /// The last analysis with an ast while the document doesn't parse, for
+    /// completion. Its spans are of an older text.
+    parsed: Option<Analysis>,
+}
+
+/// Language server speaking the lsp over stdio, publishing diagnostics and
+/// answering hover, go to definition and completion requests.
+pub struct LanguageServer<'a> {
+    externals: Option<&'a Externals>,
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+impl<'a> LanguageServer<'a> {
+    pub fn new(externals: Option<&'a Externals>) -> LanguageServer<'a> {
+        LanguageServer {
+            externals,
+            documents: HashMap::new(),
+            shutdown: false,
+        }
+    }
+
+    /// Checks a changed document and returns its diagnostics notification.
+    fn update(&mut self, uri: &str, text: String) -> Json {
+        let externals = self.externals;
+        let analyzed = panic::catch_unwind(AssertUnwindSafe(|| analyze(&text, externals)));
+        let analysis = analyzed.unwrap_or_else(|error| {
+            let message = error
+                .downcast_ref::<String>()
+                .map(|s| s.as_str())
+                .or_else(|| error.downcast_ref::<&str>().cloned())
+                .unwrap_or("unknown panic");
+            Analysis {
+                diagnostics: vec![Diagnostic {
+                    code: ErrorCode::InternalError,
+                    message: format!("internal error while checking the document: {}", message),
+                    span: Span { start: 0, end: 0 },
+                    labels: vec![],
+                    help: None,
+                }],
+                ast: None,
+                tokens: vec![],
+            }
+        });
+        let parsed = match self.documents.remove(uri) {
+            Some(previous) if analysis.ast.is_none() => match previous.analysis.ast {
+                Some(_) => Some(previous.analysis),
+                None => previous.parsed,
+            },
+            _ => None,
+        };
+        let lines = SourceLines::new(&text);
+        let diagnostics = analysis
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic_lsp(uri, &lines, diagnostic))
+            .collect();
+        self.documents.insert(
+            uri.to_string(),
+            Document {
+                text,
+                analysis,
+                parsed,
+            },
+        );
+        notification(
+            "textDocument/publishDiagnostics",
+            Json::object(vec![
+                ("uri", uri.into()),
+                ("diagnostics", Json::Array(diagnostics)),
+            ]),
+        )
+    }
+
+    /// The document and position a request refers to.
+    fn document_position(&self, params: &Json) -> Option<(&Document, SourceLines, usize)> {
+        let uri = params.get("textDocument").get("uri").as_str()?;
+        let document = self.documents.get(uri)?;
+        let lines = SourceLines::new(&document.text);
+        let offset = offset(&lines, params.get("position"));
+        Some((document, lines, offset))
+    }
+
+    fn hover(&self, params: &Json) -> Option<Json> {
+        let (document, lines, offset) = self.document_position(params)?;
+        let (text, span) = document.analysis.hover(offset)?;
+        Some(Json::object(vec![
+            (
+                "contents",
+                Json::object(vec![
+                    ("kind", "markdown".into()),
+                    ("value", format!("```imglang\n{}\n```", text).into()),
+                ]),
+            ),
+            ("range", range(&lines, span)),
+        ]))
+    }
+
+    fn definition(&self, params: &Json) -> Option<Json> {
+        let (document, lines, offset) = self.document_position(params)?;
+        let span = document.analysis.definition(offset)?;
+        Some(Json::object(vec![
+            ("uri", params.get("textDocument").get("uri").clone()),
+            ("range", range(&lines, span)),
+        ]))
+    }
+
+    fn completion(&self, params: &Json) -> Json {
+        let mut items = vec![];
+        if let Some((document, _, offset)) = self.document_position(params) {
+            let analysis = document.parsed.as_ref().unwrap_or(&document.analysis);
+            for symbol in analysis.completions(offset, self.externals).iter() {
+                items.push(completion_item(symbol));
+            }
+        }
+        for keyword in KEYWORDS.iter() {
+            items.push(Json::object(vec![
+                ("label", (*keyword).into()),
+                ("kind", Json::Number(14.0)),
+            ]));
+        }
+        Json::Array(items)
+    }
+
+    /// Handles one request or notification, returning the messages to send
+    /// back.
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let id = message.get("id");
+        let params = message.get("params");
+        let uri = params.get("textDocument").get("uri").as_str().unwrap_or("");
+        match message.get("method").as_str().unwrap_or("") {
+            "initialize" => vec![response(
+                id,
+                Json::object(vec![
+                    (
+                        "capabilities",
+                        Json::object(vec![
+                            // full text is sent on every change
+                            ("textDocumentSync", Json::Number(1.0)),
+                            ("hoverProvider", true.into()),
+                            ("definitionProvider", true.into()),
+                            ("completionProvider", Json::object(vec![])),
+                        ]),
+                    ),
+                    ("serverInfo", Json::object(vec![("name", "imglang".into())])),
+                ]),
+            )],
+            "shutdown" => {
+                self.shutdown = true;
+                vec![response(id, Json::Null)]
+            }
+            "textDocument/didOpen" => {
+                let text = params
+                    .get("textDocument")
+                    .get("text")
+                    .as_str()
+                    .unwrap_or("");
+                vec![self.update(uri, text.to_string())]
+            }
+            "textDocument/didChange" => {
+                let changes = params.get("contentChanges").as_array();
+                match changes.and_then(|changes| changes.last()?.get("text").as_str()) {
+                    Some(text) => vec![self.update(uri, text.to_string())],
+                    None => vec![],
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                vec![notification(
+                    "textDocument/publishDiagnostics",
+                    Json::object(vec![
+                        ("uri", uri.into()),
+                        ("diagnostics", Json::Array(vec![])),
+                    ]),
+                )]
+            }
+            "textDocument/hover" => vec![response(id, self.hover(params).unwrap_or(Json::Null))],
+            "textDocument/definition" => {
+                vec![response(id, self.definition(params).unwrap_or(Json::Null))]
+            }
+            "textDocument/completion" => vec![response(id, self.completion(params))],
+            _ if *id != Json::Null => vec![Json::object(vec![
+                ("jsonrpc", "2.0".into()),
+                ("id", id.clone()),
+                (
+                    "error",
+                    Json::object(vec![
+                        ("code", Json::Number(-32601.0)),
+                        ("message", "method not found".into()),
+                    ]),
+                ),
+            ])],
+            _ => vec![],
+        }
+    }
+
+    /// Serves messages from `input` until the client exits, returning the
+    /// exit code.
+    pub fn run(&mut self, input: &mut dyn BufRead, output: &mut dyn Write) -> i32 {
+        loop {
+            let message = match read_message(input) {
+                Ok(Some(message)) => message,
+                _ => return 1,
+            };
+            if message.get("method").as_str() == Some("exit") {
+                return if self.shutdown { 0 } else { 1 };
+            }
+            for reply in self.handle(&message).iter() {
+                if write_message(output, reply).is_err() {
+                    return 1;
+                }
+            }
+        }
+    }
+}
+
+/// Reads one `Content-Length` framed message, `None` at the end of input.
+pub fn read_message(input: &mut dyn BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header")
+    })?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    let body = String::from_utf8_lossy(&body);
+    Ok(Some(Json::parse(&body).unwrap_or(Json::Null)))
+}
+
+pub fn write_message(output: &mut dyn Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
@@ -1,81 +1,392 @@
-mod chunk;
-mod compiler;
-mod debugger;
-#[macro_use]
-mod externals;
-mod heap;
-mod lexer;
-mod parser;
-mod stack;
-mod types;
-mod vm;
-
-use chunk::*;
-use compiler::*;
-use debugger::*;
-use externals::*;
-use heap::*;
-use lexer::*;
-use parser::*;
-use stack::*;
+use imglang::*;
 use std::env;
 use std::fs;
-use std::io::stdout;
-use types::*;
-use vm::*;
+use std::io::{self, stdout, Read};
+use std::process;
+use std::sync::OnceLock;
 
-fn main() {
-    let mut args = env::args();
-    if args.len() != 2 {
-        eprintln!("usage \"imglang [script]\"");
-        return;
+/// Arguments given after the script, read by the `argc`/`arg` externals.
+static SCRIPT_ARGS: OnceLock<Vec<String>> = OnceLock::new();
+
+fn script_arg(i: f64) -> Option<&'static String> {
+    if i < 0.0 || i.fract() != 0.0 {
+        return None;
     }
-    args.next();
-    let source = String::from_utf8(fs::read(args.next().unwrap()).unwrap()).unwrap();
-    let mut lexer = match Lexer::new(&source) {
+    SCRIPT_ARGS.get().and_then(|args| args.get(i as usize))
+}
+
+/// The externals of the `capabilities` groups, along with the script
+/// arguments.
+fn externals(capabilities: &[Capability]) -> Externals {
+    let mut externals = Externals::with_capabilities(capabilities);
+    externals.register("testExternal", |arg0: f64, arg1: f64| -> f64 {
+        println!("from external: {}", arg0 / arg1);
+        12.0
+    });
+    externals.register("argc", || -> f64 {
+        SCRIPT_ARGS.get().map(|args| args.len()).unwrap_or(0) as f64
+    });
+    externals.register("arg", |i: f64| -> String {
+        script_arg(i).cloned().unwrap_or_default()
+    });
+    externals.register("argFloat", |i: f64| -> f64 {
+        let arg = script_arg(i).and_then(|arg| arg.parse().ok());
+        arg.unwrap_or(f64::NAN)
+    });
+    externals
+}
+
+const USAGE: &str = "usage:
+    imglang                                     start a repl
+    imglang run [options] [script] [args...]    run a script
+    imglang check [options] [script]            type check a script without running it
+    imglang disasm [options] [script]           print the bytecode of a script
+    imglang test [options] [script]             run the test blocks of a script
+    imglang eval [options] -e <code> [args...]  run code given as an argument
+    imglang fmt [options] [script]              format a script in place
+    imglang explain [code]                      describe an error code, like E0008
+    imglang generate [seed]                     print a random program, for fuzzing
+    imglang lsp                                 start a language server on stdio
+
+options:
+    --error-format=human|json    how diagnostics are printed, json prints one
+                                 object per line to stderr
+    --warnings-as-errors         fail when there are warnings, without running
+    --check                      with fmt, fail when the script is not
+                                 formatted instead of formatting it
+    --interpret                  run with the tree-walking interpreter instead
+                                 of compiling to bytecode
+    --allow=GROUP,...            only let the script use these groups of
+                                 externals: math, string, fs-read, fs-write,
+                                 clock and env, only math and string by
+                                 default
+
+scripts are read from stdin when no path (or '-') is given, args are
+available to the script through argc(), arg(i) and argFloat(i)";
+
+const EXIT_ERROR: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+
+struct Options {
+    error_format: ErrorFormat,
+    warnings_as_errors: bool,
+    check: bool,
+    interpret: bool,
+    /// Capability groups of externals the script is granted.
+    capabilities: Vec<Capability>,
+}
+
+enum Command {
+    Run,
+    Check,
+    Disasm,
+    Test,
+}
+
+/// Reads the script at `path`, or stdin, returning the name to use in
+/// diagnostics along with the source.
+fn read_source(path: Option<String>) -> Result<(String, String), String> {
+    let name = match path.as_deref() {
+        None | Some("-") => "<stdin>".to_string(),
+        Some(path) => path.to_string(),
+    };
+    let bytes = match path.as_deref() {
+        None | Some("-") => {
+            let mut bytes = vec![];
+            io::stdin()
+                .read_to_end(&mut bytes)
+                .map_err(|error| format!("could not read stdin: {}", error))?;
+            bytes
+        }
+        Some(path) => {
+            fs::read(path).map_err(|error| format!("could not read {}: {}", path, error))?
+        }
+    };
+    let source = String::from_utf8(bytes).map_err(|_| format!("{} is not valid utf-8", name))?;
+    Ok((name, source))
+}
+
+/// Lexes, parses and type checks `source`, printing any errors and warnings.
+fn check(name: &str, source: &String, externals: &Externals, options: &Options) -> Option<Ast> {
+    let format = options.error_format;
+    let mut lexer = match Lexer::new(source) {
         Ok(tokens) => tokens,
         Err(error) => {
-            print_lexer_err(&source, error);
-            return;
+            emit_diagnostics(format, name, source, &lexer_diagnostics(error));
+            return None;
         }
     };
     let mut ast = match parse(&mut lexer) {
         Ok(ast) => ast,
         Err(error) => {
-            print_parser_error(&source, error);
-            return;
+            emit_diagnostics(format, name, source, &parser_diagnostics(error));
+            return None;
         }
     };
 
-    let mut externals = Externals::new();
-    externals.add_function(
-        "testExternal".to_string(),
-        ExternalFunction {
-            args_t: vec![AstType::Float, AstType::Float],
-            ret_t: AstType::Float,
-            dispatch: |stack: &mut Stack| {
-                external_pop_args!(stack, (arg0, f64), (arg1, f64));
-                println!("from external: {}", arg0 / arg1);
-                stack.push(12.0);
-            },
-        },
-    );
-
     #[cfg(feature = "debug_build")]
     eprintln!("{:?}", ast);
 
-    if let Err(error) = TypeChecker::annotate_types(&mut ast, Some(&externals)) {
-        print_type_error(&source, error);
-        return;
+    let mut type_checker = TypeChecker::new(Some(externals));
+    let result = type_checker.annotate(&mut ast);
+    let warnings = type_checker.take_warnings();
+    let mut diagnostics: Vec<Diagnostic> =
+        warnings.into_iter().flat_map(type_diagnostics).collect();
+    let has_warnings = !diagnostics.is_empty();
+    if let Err(error) = &result {
+        diagnostics.extend(type_diagnostics(error.clone()));
+    }
+    diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+    if !diagnostics.is_empty() {
+        emit_diagnostics(format, name, source, &diagnostics);
+    }
+    if result.is_err() || (has_warnings && options.warnings_as_errors) {
+        return None;
     }
-    let chunks = Compiler::compile(&ast, Some(&externals));
 
     #[cfg(feature = "debug_build")]
     eprintln!("{:?}", ast);
 
+    Some(ast)
+}
+
+fn execute(command: Command, name: &str, source: &String, options: &Options) -> i32 {
+    let externals = externals(&options.capabilities);
+    let ast = match check(name, source, &externals, options) {
+        Some(ast) => ast,
+        None => return EXIT_ERROR,
+    };
+    if let Command::Check = command {
+        return 0;
+    }
+    if let Command::Test = command {
+        let results = run_tests(&ast, Some(&externals));
+        return match report_tests(name, source, &results, &mut stdout()) {
+            true => 0,
+            false => EXIT_ERROR,
+        };
+    }
+    if options.interpret {
+        let mut interpreter = Interpreter::new(Some(&externals));
+        return match interpreter.run(&ast, &mut stdout()) {
+            Ok(()) => 0,
+            Err(error) => {
+                print_runtime_error(name, source, &error);
+                EXIT_ERROR
+            }
+        };
+    }
+    let chunks = Compiler::compile(&ast, Some(&externals));
+
     #[cfg(feature = "debug_build")]
-    disassemble_chunk(&chunks);
+    disassemble_chunk(&chunks, &mut io::stderr());
+
+    if let Command::Disasm = command {
+        disassemble_chunk(&chunks, &mut stdout());
+        return 0;
+    }
 
     let mut vm = VM::new(chunks, Some(&externals));
-    vm.run(&mut stdout());
+    match vm.run(&mut stdout()) {
+        Ok(()) => 0,
+        Err(error) => {
+            print_runtime_error(name, source, &error);
+            EXIT_ERROR
+        }
+    }
+}
+
+/// Formats a script in place, or prints it when it was read from stdin.
+fn format_script(path: Option<String>, options: &Options) -> i32 {
+    let (name, source) = match read_source(path.clone()) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("{}", error);
+            return EXIT_USAGE;
+        }
+    };
+    let formatted = match format_source(&source) {
+        Ok(formatted) => formatted,
+        Err(error) => {
+            let diagnostics = match error {
+                FormatError::Lexer(error) => lexer_diagnostics(error),
+                FormatError::Parser(error) => parser_diagnostics(error),
+                FormatError::Comment(span) => vec![Diagnostic {
+                    code: ErrorCode::CommentInExpression,
+                    message: "cannot format a comment inside an expression".to_string(),
+                    span,
+                    labels: vec![],
+                    help: Some("move the comment before or after the statement".to_string()),
+                }],
+            };
+            emit_diagnostics(options.error_format, &name, &source, &diagnostics);
+            return EXIT_ERROR;
+        }
+    };
+    if options.check {
+        if formatted != source {
+            eprintln!("{} is not formatted", name);
+            return EXIT_ERROR;
+        }
+        return 0;
+    }
+    match path.as_deref() {
+        None | Some("-") => print!("{}", formatted),
+        Some(path) if formatted != source => {
+            if let Err(error) = fs::write(path, formatted) {
+                eprintln!("could not write {}: {}", path, error);
+                return EXIT_ERROR;
+            }
+        }
+        Some(_) => {}
+    }
+    0
+}
+
+fn explain(code: Option<String>) -> i32 {
+    match code {
+        None => {
+            for code in ERROR_CODES.iter() {
+                println!("{}  {}", code.code(), code.title());
+            }
+            0
+        }
+        Some(code) => match ErrorCode::from_code(&code) {
+            Some(code) => {
+                println!(
+                    "{}: {}\n\n{}",
+                    code.code(),
+                    code.title(),
+                    code.explanation()
+                );
+                0
+            }
+            None => {
+                eprintln!("{} is not an error code", code);
+                EXIT_USAGE
+            }
+        },
+    }
+}
+
+/// Parses the comma separated groups of `--allow`.
+fn parse_capabilities(names: &str) -> Vec<Capability> {
+    names
+        .split(',')
+        .filter(|name| !name.is_empty())
+        .map(|name| match Capability::from_name(name) {
+            Some(capability) => capability,
+            None => {
+                eprintln!("unknown capability {}", name);
+                usage_error()
+            }
+        })
+        .collect()
+}
+
+fn usage_error() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(EXIT_USAGE);
+}
+
+fn main() {
+    let mut args = env::args().skip(1).peekable();
+    let command = match args.next() {
+        None => {
+            run_repl(Some(&externals(&CAPABILITIES)));
+            return;
+        }
+        Some(command) => command,
+    };
+    let mut options = Options {
+        error_format: ErrorFormat::Human,
+        warnings_as_errors: false,
+        check: false,
+        interpret: false,
+        capabilities: DEFAULT_CAPABILITIES.to_vec(),
+    };
+    while let Some(option) = args.next_if(|arg| arg.starts_with("--")) {
+        match option.as_str() {
+            "--error-format=human" => options.error_format = ErrorFormat::Human,
+            "--error-format=json" => options.error_format = ErrorFormat::Json,
+            "--warnings-as-errors" => options.warnings_as_errors = true,
+            "--check" => options.check = true,
+            "--interpret" => options.interpret = true,
+            option if option.starts_with("--allow=") => {
+                options.capabilities = parse_capabilities(&option["--allow=".len()..])
+            }
+            "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => usage_error(),
+        }
+    }
+    let (command, source) = match command.as_str() {
+        "run" => (Command::Run, read_source(args.next())),
+        "check" => (Command::Check, read_source(args.next())),
+        "disasm" => (Command::Disasm, read_source(args.next())),
+        "test" => (Command::Test, read_source(args.next())),
+        "eval" => match (args.next().as_deref(), args.next()) {
+            (Some("-e"), Some(code)) => (Command::Run, Ok(("<eval>".to_string(), code))),
+            _ => usage_error(),
+        },
+        "fmt" => {
+            let path = args.next();
+            if args.next().is_some() {
+                usage_error();
+            }
+            process::exit(format_script(path, &options));
+        }
+        "lsp" => {
+            if args.next().is_some() {
+                usage_error();
+            }
+            let externals = externals(&options.capabilities);
+            let mut server = LanguageServer::new(Some(&externals));
+            process::exit(server.run(&mut io::stdin().lock(), &mut stdout()));
+        }
+        "generate" => {
+            let seed = match (args.next().map(|seed| seed.parse()), args.next()) {
+                (None, None) => 0,
+                (Some(Ok(seed)), None) => seed,
+                _ => usage_error(),
+            };
+            print!("{}", generate_program(seed));
+            return;
+        }
+        "explain" => {
+            let code = args.next();
+            if args.next().is_some() {
+                usage_error();
+            }
+            process::exit(explain(code));
+        }
+        "help" | "-h" | "--help" => {
+            println!("{}", USAGE);
+            return;
+        }
+        _ => usage_error(),
+    };
+    if options.check || (options.interpret && !matches!(command, Command::Run)) {
+        usage_error();
+    }
+    let script_args: Vec<String> = args.collect();
+    match command {
+        Command::Run => {
+            SCRIPT_ARGS.set(script_args).unwrap();
+        }
+        Command::Check | Command::Disasm | Command::Test if !script_args.is_empty() => {
+            usage_error()
+        }
+        _ => {}
+    }
+    let (name, source) = match source {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(EXIT_USAGE);
+        }
+    };
+    process::exit(execute(command, &name, &source, &options));
 }
@@ -5,112 +5,180 @@ pub enum Ast {
     Program(Vec<Ast>),
     Block {
         cont: Vec<Ast>,
-        pos: usize,
+        pos: Span,
     },
     Print {
         expr: Box<Ast>,
         t: Option<AstType>,
-        pos: usize,
+        pos: Span,
     },
     Return {
         expr: Option<Box<Ast>>,
         t: Option<AstType>,
-        pos: usize,
+        pos: Span,
     },
 
     Declaration {
         name: String,
         expr: Box<Ast>,
         t: Option<AstType>,
-        pos: usize,
+        pos: Span,
     },
     FuncDeclaration {
         name: String,
         func: Box<Ast>,
         args_t: Vec<AstType>,
         ret_t: AstType,
-        pos: usize,
+        pos: Span,
     },
     EnumDeclaration {
         name: String,
         variants: Vec<(String, AstType)>,
-        pos: usize,
+        pos: Span,
+    },
+    /// `import module;`, lets the externals of the module be used without
+    /// the module name.
+    Import {
+        module: String,
+        pos: Span,
+    },
+    /// `test "name" { ... }`, only compiled for the test runner.
+    Test {
+        name: String,
+        body: Box<Ast>,
+        pos: Span,
     },
 
     Variable {
         name: String,
         t: Option<AstType>,
-        pos: usize,
+        pos: Span,
     },
     Assign {
         name: String,
         expr: Box<Ast>,
         t: Option<AstType>,
         move_to_heap: Option<bool>,
-        pos: usize,
+        pos: Span,
     },
 
     Switch {
         head: Box<Ast>,
         cases: Vec<(Ast, Ast)>,
         default: Option<Box<Ast>>,
-        pos: usize,
+        pos: Span,
     },
     If {
         condition: Box<Ast>,
         body: Box<Ast>,
         else_body: Option<Box<Ast>>,
-        pos: usize,
+        pos: Span,
     },
     While {
         condition: Box<Ast>,
         body: Box<Ast>,
-        pos: usize,
+        pos: Span,
     },
 
     ExprStatement {
         expr: Box<Ast>,
         t: Option<AstType>,
-        pos: usize,
+        pos: Span,
+    },
+    Assert {
+        condition: Box<Ast>,
+        pos: Span,
+    },
+    AssertEq {
+        lhs: Box<Ast>,
+        rhs: Box<Ast>,
+        t: Option<AstType>,
+        pos: Span,
     },
 
     Function {
         body: Box<Ast>,
-        args: Vec<(String, AstType)>,
+        args: Vec<(String, AstType, Span)>,
         captured: Vec<(String, Option<AstType>)>,
         ret_t: AstType,
-        pos: usize,
+        pos: Span,
     },
     Call {
         ident: Box<Ast>,
         args: Vec<Ast>,
         args_width: Option<u8>,
         call_t: Option<CallType>,
-        pos: usize,
+        pos: Span,
     },
 
-    Float(f64, usize),
-    Bool(bool, usize),
+    Float(f64, Span),
+    Bool(bool, Span),
+
+    String(String, Span),
 
-    String(String, usize),
+    Negate(Box<Ast>, Span),
+    Not(Box<Ast>, Span),
 
-    Negate(Box<Ast>, usize),
-    Not(Box<Ast>, usize),
+    Multiply(Box<Ast>, Box<Ast>, Option<AstType>, Span),
+    Divide(Box<Ast>, Box<Ast>, Option<AstType>, Span),
+    Add(Box<Ast>, Box<Ast>, Option<AstType>, Span),
+    Sub(Box<Ast>, Box<Ast>, Option<AstType>, Span),
 
-    Multiply(Box<Ast>, Box<Ast>, Option<AstType>, usize),
-    Divide(Box<Ast>, Box<Ast>, Option<AstType>, usize),
-    Add(Box<Ast>, Box<Ast>, Option<AstType>, usize),
-    Sub(Box<Ast>, Box<Ast>, Option<AstType>, usize),
+    Equal(Box<Ast>, Box<Ast>, Option<AstType>, Span),
+    NotEqual(Box<Ast>, Box<Ast>, Option<AstType>, Span),
+    Greater(Box<Ast>, Box<Ast>, Option<AstType>, Span),
+    GreaterEqual(Box<Ast>, Box<Ast>, Option<AstType>, Span),
+    Lesser(Box<Ast>, Box<Ast>, Option<AstType>, Span),
+    LesserEqual(Box<Ast>, Box<Ast>, Option<AstType>, Span),
 
-    Equal(Box<Ast>, Box<Ast>, Option<AstType>, usize),
-    NotEqual(Box<Ast>, Box<Ast>, Option<AstType>, usize),
-    Greater(Box<Ast>, Box<Ast>, Option<AstType>, usize),
-    GreaterEqual(Box<Ast>, Box<Ast>, Option<AstType>, usize),
-    Lesser(Box<Ast>, Box<Ast>, Option<AstType>, usize),
-    LesserEqual(Box<Ast>, Box<Ast>, Option<AstType>, usize),
+    And(Box<Ast>, Box<Ast>, Span),
+    Or(Box<Ast>, Box<Ast>, Span),
+}
 
-    And(Box<Ast>, Box<Ast>, usize),
-    Or(Box<Ast>, Box<Ast>, usize),
+impl Ast {
+    pub fn span(&self) -> Span {
+        match self {
+            Ast::Program(ps) => match (ps.first(), ps.last()) {
+                (Some(first), Some(last)) => Span::new(first.span().start, last.span().end),
+                _ => Span::new(0, 0),
+            },
+            Ast::Block { pos, .. }
+            | Ast::Print { pos, .. }
+            | Ast::Return { pos, .. }
+            | Ast::Declaration { pos, .. }
+            | Ast::FuncDeclaration { pos, .. }
+            | Ast::EnumDeclaration { pos, .. }
+            | Ast::Test { pos, .. }
+            | Ast::Import { pos, .. }
+            | Ast::Variable { pos, .. }
+            | Ast::Assign { pos, .. }
+            | Ast::Switch { pos, .. }
+            | Ast::If { pos, .. }
+            | Ast::While { pos, .. }
+            | Ast::ExprStatement { pos, .. }
+            | Ast::Assert { pos, .. }
+            | Ast::AssertEq { pos, .. }
+            | Ast::Function { pos, .. }
+            | Ast::Call { pos, .. }
+            | Ast::Float(_, pos)
+            | Ast::Bool(_, pos)
+            | Ast::String(_, pos)
+            | Ast::Negate(_, pos)
+            | Ast::Not(_, pos)
+            | Ast::Multiply(_, _, _, pos)
+            | Ast::Divide(_, _, _, pos)
+            | Ast::Add(_, _, _, pos)
+            | Ast::Sub(_, _, _, pos)
+            | Ast::Equal(_, _, _, pos)
+            | Ast::NotEqual(_, _, _, pos)
+            | Ast::Greater(_, _, _, pos)
+            | Ast::GreaterEqual(_, _, _, pos)
+            | Ast::Lesser(_, _, _, pos)
+            | Ast::LesserEqual(_, _, _, pos)
+            | Ast::And(_, _, pos)
+            | Ast::Or(_, _, pos) => *pos,
+        }
+    }
 }
 
 type ParserResult<T> = Result<T, ParserError>;
@@ -137,6 +205,11 @@ pub const PREC_FACTOR: u32 = 70; // * /
 pub const PREC_UNARY: u32 = 80; // ! -
 pub const PREC_CALL: u32 = 90; // . ()
 
+/// Span from `start` to the end of the last consumed token.
+fn span_from(lexer: &Lexer, start: usize) -> Span {
+    Span::new(start, lexer.prev().map(|t| t.end).unwrap_or(start))
+}
+
 fn consume(lexer: &mut Lexer, p: fn(&TokenType) -> bool, msg: &'static str) -> ParserResult<()> {
     if !p(&lexer.current_t()) {
         return Err(ParserError::Unexpected(lexer.current(), msg));
@@ -170,7 +243,7 @@ fn parse_type(lexer: &mut Lexer, default: &Option<AstType>) -> ParserResult<AstT
                 TokenType::Semicolon | TokenType::Greater => false,
                 _ => true,
             } {
-                args.push(parse_type(lexer, default)?);
+                args.push(parse_type(lexer, &None)?);
                 if lexer.current_t() == TokenType::Comma {
                     lexer.next();
                 }
@@ -234,29 +307,62 @@ fn get_rule(t: &TokenType) -> Rule {
     }
 }
 
+/// Skips tokens after an error until the start of the next statement or
+/// declaration. Blocks are skipped as a whole, and a '}' closing the
+/// enclosing block is left for it to consume.
 fn synchronize(lexer: &mut Lexer) {
+    let mut depth = 0;
     while lexer.current_t() != TokenType::Eof {
-        if let Some(TokenType::Semicolon) = lexer.prev_t() {
-            return;
+        if depth == 0 {
+            match (lexer.prev_t(), lexer.current_t()) {
+                (Some(TokenType::Semicolon), _) => return,
+                (Some(TokenType::RightBrace), TokenType::Semicolon)
+                | (Some(TokenType::RightBrace), TokenType::Else) => {}
+                (Some(TokenType::RightBrace), _) => return,
+                _ => {}
+            }
+            match lexer.current_t() {
+                TokenType::Var
+                | TokenType::Print
+                | TokenType::Fun
+                | TokenType::Enum
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Return
+                | TokenType::Switch
+                | TokenType::Assert
+                | TokenType::AssertEq
+                | TokenType::RightBrace => return,
+                _ => {}
+            }
         }
         match lexer.current_t() {
-            TokenType::Var | TokenType::Print => return,
+            TokenType::LeftBrace => depth += 1,
+            TokenType::RightBrace => depth -= 1,
             _ => {}
         }
         lexer.next();
     }
 }
 
+/// Records `error` from a declaration starting at `start` and synchronizes,
+/// skipping at least one token so the caller can't get stuck on it.
+fn recover(lexer: &mut Lexer, start: usize, error: ParserError, errors: &mut Vec<ParserError>) {
+    errors.push(error);
+    synchronize(lexer);
+    if lexer.current().start == start {
+        lexer.next();
+    }
+}
+
 pub fn parse(lexer: &mut Lexer) -> ParserResult<Ast> {
     let mut parsed = vec![];
     let mut errors = vec![];
     while lexer.current_t() != TokenType::Eof {
+        let decl_start = lexer.current().start;
         match declaration(lexer) {
             Ok(decl) => parsed.push(decl),
-            Err(error) => {
-                errors.push(error);
-                synchronize(lexer);
-            }
+            Err(error) => recover(lexer, decl_start, error, &mut errors),
         }
     }
     if errors.len() > 0 {
@@ -267,7 +373,12 @@ pub fn parse(lexer: &mut Lexer) -> ParserResult<Ast> {
 }
 
 fn parse_precedence(lexer: &mut Lexer, prec: u32) -> ParserResult<Ast> {
-    lexer.next().unwrap();
+    if lexer.next().is_none() {
+        return Err(ParserError::Unexpected(
+            lexer.current(),
+            "expected expression, found end of input",
+        ));
+    }
 
     let prefix_rule = get_rule(&lexer.prev_t().unwrap()).0.ok_or_else(|| {
         ParserError::Unexpected(lexer.prev().unwrap(), "unexpected token in prefix position")
@@ -287,7 +398,7 @@ fn parse_precedence(lexer: &mut Lexer, prec: u32) -> ParserResult<Ast> {
 }
 
 fn literal(lexer: &mut Lexer) -> ParserResult<Ast> {
-    let pos = lexer.prev().unwrap().start;
+    let pos = lexer.prev().unwrap().span();
     let ast = match lexer.prev_t().unwrap() {
         TokenType::Float(f) => Ast::Float(f, pos),
         TokenType::True => Ast::Bool(true, pos),
@@ -308,9 +419,10 @@ fn expression(lexer: &mut Lexer) -> ParserResult<Ast> {
 }
 
 fn unary(lexer: &mut Lexer) -> ParserResult<Ast> {
-    let pos = lexer.prev().unwrap().start;
+    let start = lexer.prev().unwrap().start;
     let t = lexer.prev_t().unwrap();
     let expr = parse_precedence(lexer, PREC_UNARY)?;
+    let pos = span_from(lexer, start);
     let ast = match t {
         TokenType::Minus => Ast::Negate(Box::new(expr), pos),
         TokenType::Bang => Ast::Not(Box::new(expr), pos),
@@ -325,10 +437,11 @@ fn unary(lexer: &mut Lexer) -> ParserResult<Ast> {
 }
 
 fn binary(lexer: &mut Lexer, lhs: Ast) -> ParserResult<Ast> {
-    let pos = lexer.prev().unwrap().start;
+    let start = lhs.span().start;
     let t = lexer.prev_t().unwrap();
     let rule = get_rule(&t);
     let rhs = parse_precedence(lexer, rule.2 + 1)?;
+    let pos = span_from(lexer, start);
     let ast = match t {
         TokenType::Star => Ast::Multiply(Box::new(lhs), Box::new(rhs), None, pos),
         TokenType::Slash => Ast::Divide(Box::new(lhs), Box::new(rhs), None, pos),
@@ -381,12 +494,25 @@ fn declaration(lexer: &mut Lexer) -> ParserResult<Ast> {
             lexer.next();
             enum_declaration(lexer)
         }
+        // `test` is only a keyword in front of the test name
+        TokenType::Identifier(name)
+            if name == "test" && matches!(lexer.peek_t(), Some(TokenType::String(_))) =>
+        {
+            lexer.next();
+            test_declaration(lexer)
+        }
+        TokenType::Identifier(name)
+            if name == "import" && matches!(lexer.peek_t(), Some(TokenType::Identifier(_))) =>
+        {
+            lexer.next();
+            import_declaration(lexer)
+        }
         _ => statement(lexer),
     }
 }
 
 fn var_declaration(lexer: &mut Lexer) -> ParserResult<Ast> {
-    let pos = lexer.prev().unwrap().start;
+    let start = lexer.prev().unwrap().start;
     let name = parse_variable(lexer)?;
 
     consume(
@@ -401,6 +527,7 @@ fn var_declaration(lexer: &mut Lexer) -> ParserResult<Ast> {
         |t| t == &TokenType::Semicolon,
         "expected ';' after declaration",
     )?;
+    let pos = span_from(lexer, start);
 
     Ok(Ast::Declaration {
         name,
@@ -430,7 +557,7 @@ fn variable(lexer: &mut Lexer) -> ParserResult<Ast> {
 }
 
 fn named_variable(lexer: &mut Lexer) -> ParserResult<Ast> {
-    let pos = lexer.prev().unwrap().start;
+    let pos = lexer.prev().unwrap().span();
     let name = match lexer.prev_t().unwrap() {
         TokenType::Identifier(name) => name,
         _ => {
@@ -440,6 +567,14 @@ fn named_variable(lexer: &mut Lexer) -> ParserResult<Ast> {
             ))
         }
     };
+    // `module.name` of an external in a module
+    let (name, pos) = if lexer.current_t() == TokenType::Dot {
+        lexer.next();
+        let member = parse_variable(lexer)?;
+        (format!("{}.{}", name, member), span_from(lexer, pos.start))
+    } else {
+        (name, pos)
+    };
     match lexer.current_t() {
         TokenType::Equal => {
             lexer.next();
@@ -449,7 +584,7 @@ fn named_variable(lexer: &mut Lexer) -> ParserResult<Ast> {
                 expr: Box::new(expr),
                 t: None,
                 move_to_heap: None,
-                pos,
+                pos: span_from(lexer, pos.start),
             })
         }
         _ => Ok(Ast::Variable { name, t: None, pos }),
@@ -482,18 +617,27 @@ fn statement(lexer: &mut Lexer) -> ParserResult<Ast> {
             lexer.next();
             switch_statement(lexer)
         }
+        TokenType::Assert => {
+            lexer.next();
+            assert_statement(lexer)
+        }
+        TokenType::AssertEq => {
+            lexer.next();
+            assert_eq_statement(lexer)
+        }
         _ => expression_statement(lexer),
     }
 }
 
 fn print_statement(lexer: &mut Lexer) -> ParserResult<Ast> {
-    let pos = lexer.prev().unwrap().start;
+    let start = lexer.prev().unwrap().start;
     let expr = expression(lexer)?;
     consume(
         lexer,
         |t| t == &TokenType::Semicolon,
         "expected ';' after print statement",
     )?;
+    let pos = span_from(lexer, start);
     Ok(Ast::Print {
         expr: Box::new(expr),
         t: None,
@@ -501,8 +645,56 @@ fn print_statement(lexer: &mut Lexer) -> ParserResult<Ast> {
     })
 }
 
+fn assert_statement(lexer: &mut Lexer) -> ParserResult<Ast> {
+    let start = lexer.prev().unwrap().start;
+    let condition = expression(lexer)?;
+    consume(
+        lexer,
+        |t| t == &TokenType::Semicolon,
+        "expected ';' after assert statement",
+    )?;
+    let pos = span_from(lexer, start);
+    Ok(Ast::Assert {
+        condition: Box::new(condition),
+        pos,
+    })
+}
+
+fn assert_eq_statement(lexer: &mut Lexer) -> ParserResult<Ast> {
+    let start = lexer.prev().unwrap().start;
+    consume(
+        lexer,
+        |t| t == &TokenType::LeftPar,
+        "expected '(' after assert_eq",
+    )?;
+    let lhs = expression(lexer)?;
+    consume(
+        lexer,
+        |t| t == &TokenType::Comma,
+        "expected ',' between the values of assert_eq",
+    )?;
+    let rhs = expression(lexer)?;
+    consume(
+        lexer,
+        |t| t == &TokenType::RightPar,
+        "expected ')' after the values of assert_eq",
+    )?;
+    consume(
+        lexer,
+        |t| t == &TokenType::Semicolon,
+        "expected ';' after assert_eq",
+    )?;
+    let pos = span_from(lexer, start);
+    Ok(Ast::AssertEq {
+        lhs: Box::new(lhs),
+        rhs: Box::new(rhs),
+        t: None,
+        pos,
+    })
+}
+
 fn if_statement(lexer: &mut Lexer) -> ParserResult<Ast> {
-    let pos = lexer.prev().unwrap().start;
+    let start = lexer.prev().unwrap().start;
     consume(lexer, |t| t == &TokenType::LeftPar, "expected '(' after if")?;
     let expr = expression(lexer)?;
     consume(
@@ -518,6 +710,7 @@ fn if_statement(lexer: &mut Lexer) -> ParserResult<Ast> {
         }
         _ => None,
     };
+    let pos = span_from(lexer, start);
     Ok(Ast::If {
         condition: Box::new(expr),
         body: Box::new(stmt),
@@ -527,7 +720,7 @@ fn if_statement(lexer: &mut Lexer) -> ParserResult<Ast> {
 }
 
 fn switch_statement(lexer: &mut Lexer) -> ParserResult<Ast> {
-    let pos = lexer.prev().unwrap().start;
+    let start = lexer.prev().unwrap().start;
     consume(
         lexer,
         |t| t == &TokenType::LeftPar,
@@ -575,6 +768,7 @@ fn switch_statement(lexer: &mut Lexer) -> ParserResult<Ast> {
         |t| t == &TokenType::RightBrace,
         "expected '}' after switch cases",
     )?;
+    let pos = span_from(lexer, start);
 
     Ok(Ast::Switch {
         head: Box::new(head),
@@ -585,19 +779,17 @@ fn switch_statement(lexer: &mut Lexer) -> ParserResult<Ast> {
 }
 
 fn block(lexer: &mut Lexer) -> ParserResult<Ast> {
-    let pos = lexer.prev().unwrap().start;
+    let start = lexer.prev().unwrap().start;
     let mut parsed = vec![];
     let mut errors = vec![];
     while match lexer.current_t() {
         TokenType::RightBrace | TokenType::Eof => false,
         _ => true,
     } {
+        let decl_start = lexer.current().start;
         match declaration(lexer) {
             Ok(decl) => parsed.push(decl),
-            Err(error) => {
-                errors.push(error);
-                synchronize(lexer);
-            }
+            Err(error) => recover(lexer, decl_start, error, &mut errors),
         }
     }
     if let Err(error) = consume(
@@ -611,14 +803,18 @@ fn block(lexer: &mut Lexer) -> ParserResult<Ast> {
     if errors.len() > 0 {
         Err(ParserError::BlockErrors(errors))
     } else {
-        Ok(Ast::Block { cont: parsed, pos })
+        Ok(Ast::Block {
+            cont: parsed,
+            pos: span_from(lexer, start),
+        })
     }
 }
 
 fn expression_statement(lexer: &mut Lexer) -> ParserResult<Ast> {
-    let pos = lexer.prev().unwrap().start;
+    let start = lexer.current().start;
     let expr = expression(lexer)?;
     consume(lexer, |t| t == &TokenType::Semicolon, "expected ';'")?;
+    let pos = span_from(lexer, start);
     Ok(Ast::ExprStatement {
         expr: Box::new(expr),
         t: None,
@@ -627,19 +823,21 @@ fn expression_statement(lexer: &mut Lexer) -> ParserResult<Ast> {
 }
 
 fn logic_and(lexer: &mut Lexer, lhs: Ast) -> ParserResult<Ast> {
-    let pos = lexer.prev().unwrap().start;
+    let start = lhs.span().start;
     let rhs = parse_precedence(lexer, PREC_AND)?;
+    let pos = span_from(lexer, start);
     Ok(Ast::And(Box::new(lhs), Box::new(rhs), pos))
 }
 
 fn logic_or(lexer: &mut Lexer, lhs: Ast) -> ParserResult<Ast> {
-    let pos = lexer.prev().unwrap().start;
+    let start = lhs.span().start;
     let rhs = parse_precedence(lexer, PREC_OR)?;
+    let pos = span_from(lexer, start);
     Ok(Ast::Or(Box::new(lhs), Box::new(rhs), pos))
 }
 
 fn while_statement(lexer: &mut Lexer) -> ParserResult<Ast> {
-    let pos = lexer.prev().unwrap().start;
+    let start = lexer.prev().unwrap().start;
     consume(
         lexer,
         |t| t == &TokenType::LeftPar,
@@ -652,6 +850,7 @@ fn while_statement(lexer: &mut Lexer) -> ParserResult<Ast> {
         "expected ')' after condition",
     )?;
     let stmt = statement(lexer)?;
+    let pos = span_from(lexer, start);
 
     Ok(Ast::While {
         condition: Box::new(expr),
@@ -661,14 +860,19 @@ fn while_statement(lexer: &mut Lexer) -> ParserResult<Ast> {
 }
 
 fn function(lexer: &mut Lexer) -> ParserResult<Ast> {
-    let pos = lexer.prev().unwrap().start;
+    let start = lexer.prev().unwrap().start;
     let mut captured = Vec::new();
     if lexer.current_t() == TokenType::LeftSquare {
         lexer.next();
         while lexer.current_t() != TokenType::RightSquare {
             let var = match lexer.current_t() {
                 TokenType::Identifier(var) => var,
-                _ => todo!(),
+                _ => {
+                    return Err(ParserError::Unexpected(
+                        lexer.current(),
+                        "expected name of captured variable",
+                    ))
+                }
             };
             captured.push((var, None));
 
@@ -692,15 +896,21 @@ fn function(lexer: &mut Lexer) -> ParserResult<Ast> {
     )?;
     let mut args = vec![];
     while lexer.current_t() != TokenType::RightPar {
+        let arg_start = lexer.current().start;
         let arg = match lexer.current_t() {
             TokenType::Identifier(arg) => arg,
-            _ => todo!(),
+            _ => {
+                return Err(ParserError::Unexpected(
+                    lexer.current(),
+                    "expected argument name",
+                ))
+            }
         };
         lexer.next();
 
         let arg_t = parse_type(lexer, &None)?;
 
-        args.push((arg, arg_t));
+        args.push((arg, arg_t, span_from(lexer, arg_start)));
 
         if lexer.current_t() != TokenType::Comma {
             break;
@@ -717,6 +927,7 @@ fn function(lexer: &mut Lexer) -> ParserResult<Ast> {
         statement(lexer)?
     } else {
         let ret_expr = expression(lexer)?;
+        let pos = ret_expr.span();
         Ast::Block {
             cont: vec![Ast::Return {
                 expr: Some(Box::new(ret_expr)),
@@ -726,6 +937,7 @@ fn function(lexer: &mut Lexer) -> ParserResult<Ast> {
             pos,
         }
     };
+    let pos = span_from(lexer, start);
     Ok(Ast::Function {
         body: Box::new(body),
         args,
@@ -736,7 +948,7 @@ fn function(lexer: &mut Lexer) -> ParserResult<Ast> {
 }
 
 fn call(lexer: &mut Lexer, ident: Ast) -> ParserResult<Ast> {
-    let pos = lexer.prev().unwrap().start;
+    let start = ident.span().start;
     let mut args = vec![];
     while lexer.current_t() != TokenType::RightPar {
         args.push(expression(lexer)?);
@@ -749,6 +961,7 @@ fn call(lexer: &mut Lexer, ident: Ast) -> ParserResult<Ast> {
         |t| t == &TokenType::RightPar,
         "expected ')' after arguments",
     )?;
+    let pos = span_from(lexer, start);
     Ok(Ast::Call {
         ident: Box::new(ident),
         args,
@@ -759,6 +972,7 @@ fn call(lexer: &mut Lexer, ident: Ast) -> ParserResult<Ast> {
 }
 
 fn func_declaration(lexer: &mut Lexer) -> ParserResult<Ast> {
+    let start = lexer.prev().unwrap().start;
     let name = parse_variable(lexer)?;
 
     let function = match function(lexer)? {
@@ -787,7 +1001,7 @@ fn func_declaration(lexer: &mut Lexer) -> ParserResult<Ast> {
                 }),
                 args_t: args.into_iter().map(|a| a.1).collect(),
                 ret_t,
-                pos,
+                pos: span_from(lexer, start),
             }
         }
         _ => panic!(),
@@ -797,7 +1011,7 @@ fn func_declaration(lexer: &mut Lexer) -> ParserResult<Ast> {
 }
 
 fn enum_declaration(lexer: &mut Lexer) -> ParserResult<Ast> {
-    let pos = lexer.prev().unwrap().start;
+    let start = lexer.prev().unwrap().start;
     let name = match lexer.current_t() {
         TokenType::Identifier(name) => name,
         _ => {
@@ -832,6 +1046,7 @@ fn enum_declaration(lexer: &mut Lexer) -> ParserResult<Ast> {
         }
     }
     lexer.next();
+    let pos = span_from(lexer, start);
     Ok(Ast::EnumDeclaration {
         name,
         variants,
@@ -839,8 +1054,48 @@ fn enum_declaration(lexer: &mut Lexer) -> ParserResult<Ast> {
     })
 }
 
+fn test_declaration(lexer: &mut Lexer) -> ParserResult<Ast> {
+    let start = lexer.prev().unwrap().start;
+    let name = match lexer.current_t() {
+        TokenType::String(name) => name,
+        _ => {
+            return Err(ParserError::Unexpected(
+                lexer.current(),
+                "expected test name",
+            ))
+        }
+    };
+    lexer.next();
+    consume(
+        lexer,
+        |t| t == &TokenType::LeftBrace,
+        "expected '{' before test body",
+    )?;
+    let body = block(lexer)?;
+    let pos = span_from(lexer, start);
+    Ok(Ast::Test {
+        name,
+        body: Box::new(body),
+        pos,
+    })
+}
+
+fn import_declaration(lexer: &mut Lexer) -> ParserResult<Ast> {
+    let start = lexer.prev().unwrap().start;
+    let module = parse_variable(lexer)?;
+    consume(
+        lexer,
+        |t| t == &TokenType::Semicolon,
+        "expected ';' after import",
+    )?;
+    Ok(Ast::Import {
+        module,
+        pos: span_from(lexer, start),
+    })
+}
+
 fn return_statement(lexer: &mut Lexer) -> ParserResult<Ast> {
-    let pos = lexer.prev().unwrap().start;
+    let start = lexer.prev().unwrap().start;
     let expr = if lexer.current_t() != TokenType::Semicolon {
         Some(Box::new(expression(lexer)?))
     } else {
@@ -851,5 +1106,6 @@ fn return_statement(lexer: &mut Lexer) -> ParserResult<Ast> {
         |t| t == &TokenType::Semicolon,
         "expected ';' after return statement",
     )?;
+    let pos = span_from(lexer, start);
     Ok(Ast::Return { expr, t: None, pos })
 }
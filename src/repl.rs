@@ -0,0 +1,144 @@
+use super::*;
+use std::io::{self, BufRead, Write};
+
+#[derive(Debug)]
+pub enum ReplError {
+    Lexer(LexerError),
+    Parser(ParserError),
+    Type(TypeError),
+    Runtime(RuntimeError),
+}
+
+pub struct Repl<'a> {
+    type_checker: TypeChecker<'a>,
+    compiler: Compiler<'a>,
+    vm: VM<'a>,
+}
+
+impl<'a> Repl<'a> {
+    pub fn new(externals: Option<&'a Externals>) -> Repl<'a> {
+        Repl {
+            type_checker: TypeChecker::new(externals),
+            compiler: Compiler::new(externals),
+            vm: VM::new(vec![], externals),
+        }
+    }
+
+    /// Runs one input on top of everything evaluated before it. A trailing
+    /// expression statement is printed, and input that fails to type check
+    /// or fails while running leaves no declarations behind.
+    pub fn eval(&mut self, source: &String, out: &mut dyn Write) -> Result<(), ReplError> {
+        let source = terminate_statement(source);
+        let mut lexer = Lexer::new(&source).map_err(ReplError::Lexer)?;
+        let mut ast = parse(&mut lexer).map_err(ReplError::Parser)?;
+
+        let checkpoint = self.type_checker.clone();
+        if let Err(error) = self.type_checker.annotate(&mut ast) {
+            self.type_checker = checkpoint;
+            return Err(ReplError::Type(error));
+        }
+        // Warnings like unused variables are noise while typing line by line.
+        self.type_checker.take_warnings();
+        print_last_expression(&mut ast);
+
+        let variable_count = self.compiler.variable_count();
+        let entry = self.compiler.compile_entry(&ast);
+        self.vm.add_chunks(self.compiler.take_chunks());
+        if let Err(error) = self.vm.run_chunk(entry, out) {
+            // The vm dropped the values of variables declared by this input.
+            self.type_checker = checkpoint;
+            self.compiler.truncate_variables(variable_count);
+            return Err(ReplError::Runtime(error));
+        }
+        Ok(())
+    }
+
+    pub fn heap_ptr(&self) -> &Heap {
+        self.vm.heap_ptr()
+    }
+}
+
+/// Whether `source` ends inside a block or a string, and the repl should
+/// keep reading lines before evaluating it.
+pub fn needs_more_input(source: &String) -> bool {
+    match scan(source) {
+        Ok(tokens) => {
+            let depth = tokens.iter().fold(0, |depth, token| match token.t {
+                TokenType::LeftBrace => depth + 1,
+                TokenType::RightBrace => depth - 1,
+                _ => depth,
+            });
+            depth > 0
+        }
+        Err(LexerError::Unescaped(_)) => true,
+        Err(_) => false,
+    }
+}
+
+fn terminate_statement(source: &str) -> String {
+    match source.trim_end().chars().last() {
+        None | Some(';') | Some('}') => source.to_string(),
+        _ => format!("{};", source.trim_end()),
+    }
+}
+
+fn print_last_expression(ast: &mut Ast) {
+    let last = match ast {
+        Ast::Program(ps) => match ps.last_mut() {
+            Some(last) => last,
+            None => return,
+        },
+        _ => return,
+    };
+    let (expr, t, pos) = match last {
+        Ast::ExprStatement { expr, t, pos } => match (&**expr, t) {
+            (Ast::Assign { .. }, _) => return,
+            (_, Some(t @ AstType::Float))
+            | (_, Some(t @ AstType::Bool))
+            | (_, Some(t @ AstType::String)) => (expr.clone(), t.clone(), *pos),
+            _ => return,
+        },
+        _ => return,
+    };
+    *last = Ast::Print {
+        expr,
+        t: Some(t),
+        pos,
+    };
+}
+
+pub fn run_repl(externals: Option<&Externals>) {
+    let mut repl = Repl::new(externals);
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        let mut source = String::new();
+        loop {
+            eprint!("{}", if source.is_empty() { "> " } else { "... " });
+            io::stderr().flush().unwrap();
+            match lines.next() {
+                Some(Ok(line)) => {
+                    source.push_str(&line);
+                    source.push('\n');
+                }
+                _ => {
+                    eprintln!();
+                    return;
+                }
+            }
+            if !needs_more_input(&source) {
+                break;
+            }
+        }
+        if source.trim().is_empty() {
+            continue;
+        }
+        match repl.eval(&source, &mut io::stdout()) {
+            Ok(()) => {}
+            Err(ReplError::Lexer(error)) => print_lexer_err("<repl>", &source, error),
+            Err(ReplError::Parser(error)) => print_parser_error("<repl>", &source, error),
+            Err(ReplError::Type(error)) => print_type_error("<repl>", &source, error),
+            Err(ReplError::Runtime(error)) => print_runtime_error("<repl>", &source, &error),
+        }
+    }
+}
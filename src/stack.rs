@@ -56,12 +56,12 @@ macro_rules! impl_codec_default {
             fn set(self, ptr: *mut u8) {
                 let ptr: *mut $t = ptr.cast();
                 unsafe {
-                    *ptr = self;
+                    ptr.write_unaligned(self);
                 }
             }
             fn get(ptr: *const u8) -> Self {
                 let ptr: *const $t = ptr.cast();
-                unsafe { *ptr }
+                unsafe { ptr.read_unaligned() }
             }
         }
     };
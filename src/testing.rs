@@ -0,0 +1,67 @@
+use super::*;
+use std::io::Write;
+
+#[derive(Debug)]
+pub struct TestResult {
+    pub name: String,
+    /// What the test printed, shown when it fails.
+    pub output: String,
+    pub error: Option<RuntimeError>,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Runs every test block of a type checked program, each in a fresh vm so
+/// tests can't affect each other.
+pub fn run_tests(ast: &Ast, externals: Option<&Externals>) -> Vec<TestResult> {
+    let (chunks, tests) = Compiler::compile_tests(ast, externals);
+    tests
+        .into_iter()
+        .map(|test| {
+            let mut vm = VM::new(chunks.clone(), externals);
+            let mut output = vec![];
+            let error = vm.run_chunk(test.entry, &mut output).err();
+            TestResult {
+                name: test.name,
+                output: String::from_utf8_lossy(&output).to_string(),
+                error,
+            }
+        })
+        .collect()
+}
+
+/// Prints one line per test, then the output and error of each failed
+/// test and a summary. Returns whether every test passed.
+pub fn report_tests(name: &str, source: &str, results: &[TestResult], out: &mut dyn Write) -> bool {
+    let plural = if results.len() == 1 { "" } else { "s" };
+    writeln!(out, "running {} test{}", results.len(), plural).unwrap();
+    for result in results.iter() {
+        let status = if result.passed() { "ok" } else { "FAILED" };
+        writeln!(out, "test {} ... {}", result.name, status).unwrap();
+    }
+    writeln!(out).unwrap();
+    let failed: Vec<&TestResult> = results.iter().filter(|r| !r.passed()).collect();
+    if !failed.is_empty() {
+        writeln!(out, "failures:\n").unwrap();
+        for result in failed.iter() {
+            writeln!(out, "---- {} ----", result.name).unwrap();
+            write!(out, "{}", result.output).unwrap();
+            if let Some(error) = &result.error {
+                render_runtime_error(name, source, error, out);
+            }
+        }
+    }
+    writeln!(
+        out,
+        "test result: {}. {} passed; {} failed",
+        if failed.is_empty() { "ok" } else { "FAILED" },
+        results.len() - failed.len(),
+        failed.len()
+    )
+    .unwrap();
+    failed.is_empty()
+}
@@ -1,5 +1,6 @@
 use super::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::mem;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -16,7 +17,12 @@ pub enum AstType {
     Closure(Vec<AstType>, Box<AstType>),
     ExternalFunction(Vec<AstType>, Box<AstType>),
 
-    EnumVariant { enum_type: String, max_size: usize },
+    EnumVariant {
+        enum_type: String,
+        max_size: usize,
+    },
+    /// Host object registered with `Externals::add_foreign_type`.
+    Foreign(String),
 
     Float,
     Bool,
@@ -27,11 +33,18 @@ pub enum AstType {
     HeapAllocated(Box<AstType>),
 
     Unresolved(String),
+    /// Type of an expression that failed to type check. Checks involving it
+    /// are skipped, so the error isn't reported again by everything using
+    /// the expression.
+    Unknown,
 }
 impl AstType {
     pub fn is_obj(&self) -> bool {
         match self {
-            AstType::HeapAllocated(_) | AstType::Closure(_, _) | AstType::String => true,
+            AstType::HeapAllocated(_)
+            | AstType::Closure(_, _)
+            | AstType::String
+            | AstType::Foreign(_) => true,
             _ => false,
         }
     }
@@ -41,10 +54,81 @@ impl AstType {
             AstType::Function(..) => ChunkAdr::width(),
             AstType::Float => f64::width(),
             AstType::ExternalFunction(..) => ExternalAdr::width(),
-            AstType::Closure(..) | AstType::HeapAllocated(_) | AstType::String => HeapAdr::width(),
+            AstType::Closure(..)
+            | AstType::HeapAllocated(_)
+            | AstType::String
+            | AstType::Foreign(_) => HeapAdr::width(),
             AstType::Nil => 0,
             AstType::EnumVariant { max_size, .. } => u8::width() + max_size,
             AstType::Unresolved { .. } => panic!(),
+            AstType::Unknown => 0,
+        }
+    }
+}
+
+/// Number of single character insertions, deletions, substitutions and
+/// swaps of adjacent characters needed to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// Comma separated list of types, as written in function signatures.
+pub fn type_list(types: &[AstType]) -> String {
+    types
+        .iter()
+        .map(|t| t.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Renders types the way they are written in source, `<float;bool>*` for a
+/// closure taking a float and returning a bool.
+impl fmt::Display for AstType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AstType::Function(args, ret_t)
+            | AstType::Closure(args, ret_t)
+            | AstType::ExternalFunction(args, ret_t) => {
+                write!(f, "<{}", type_list(args))?;
+                if **ret_t != AstType::Nil {
+                    write!(f, ";{}", ret_t)?;
+                }
+                write!(f, ">")?;
+                if let AstType::Closure(..) = self {
+                    write!(f, "*")?;
+                }
+                Ok(())
+            }
+            AstType::EnumVariant { enum_type, .. } | AstType::Foreign(enum_type) => {
+                write!(f, "{}", enum_type)
+            }
+            AstType::Float => write!(f, "float"),
+            AstType::Bool => write!(f, "bool"),
+            AstType::Nil => write!(f, "nil"),
+            AstType::String => write!(f, "str"),
+            AstType::HeapAllocated(t) => write!(f, "{}", t),
+            AstType::Unresolved(name) => write!(f, "{}", name),
+            AstType::Unknown => write!(f, "{{unknown}}"),
         }
     }
 }
@@ -64,7 +148,11 @@ pub enum CustomType {
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum TypeError {
-    Error(String, usize),
+    Error(ErrorCode, String, Span),
+    /// Error with secondary spans, like where a variable was declared.
+    Labeled(ErrorCode, String, Span, Vec<(String, Span)>),
+    /// Error with a hint on how to fix it.
+    Help(Box<TypeError>, String),
 
     BlockErrors(Vec<TypeError>),
 }
@@ -74,46 +162,154 @@ struct LocalVariable {
     name: String,
     depth: u16,
     t: AstType,
+    pos: Span,
+    used: bool,
 }
 enum Variable {
     Local(LocalVariable),
     Global(AstType),
 }
 
+#[derive(Clone)]
 pub struct TypeChecker<'a> {
     variables: Vec<LocalVariable>,
     globals: HashMap<String, AstType>,
+    global_spans: HashMap<String, Span>,
+    enum_spans: HashMap<String, Span>,
+    test_spans: HashMap<String, Span>,
+    /// Functions and enums referred to after their declaration.
+    used_globals: HashSet<String>,
     custom_types: HashMap<String, CustomType>,
     externals: Option<&'a Externals>,
+    /// Modules of externals whose names can be used unqualified.
+    imports: Vec<String>,
     current_scope_depth: u16,
     is_root: bool,
-    return_values: Vec<AstType>,
+    return_values: Vec<(AstType, Span)>,
+    errors: Vec<TypeError>,
+    warnings: Vec<TypeError>,
 }
 
 impl<'a> TypeChecker<'a> {
-    pub fn annotate_types(
-        ast: &mut Ast,
-        externals: Option<&'a Externals>,
-    ) -> Result<(), TypeError> {
-        let mut type_checker = TypeChecker {
+    pub fn new(externals: Option<&'a Externals>) -> TypeChecker<'a> {
+        TypeChecker {
             variables: vec![],
             globals: HashMap::new(),
+            global_spans: HashMap::new(),
+            enum_spans: HashMap::new(),
+            test_spans: HashMap::new(),
+            used_globals: HashSet::new(),
             custom_types: HashMap::new(),
             externals,
+            imports: vec![],
             current_scope_depth: 0,
             is_root: true,
             return_values: vec![],
-        };
-        type_checker.annotate_type(ast)?;
-        Ok(())
+            errors: vec![],
+            warnings: vec![],
+        }
+    }
+    pub fn annotate_types(
+        ast: &mut Ast,
+        externals: Option<&'a Externals>,
+    ) -> Result<(), TypeError> {
+        TypeChecker::new(externals).annotate(ast)
     }
-    fn declare_variable(&mut self, name: &String, t: AstType) {
+    /// Annotates `ast` while keeping the declared globals, enums and root
+    /// locals, so a later call can refer to them (used by the repl).
+    pub fn annotate(&mut self, ast: &mut Ast) -> Result<(), TypeError> {
+        self.check(ast);
+        let mut errors = mem::take(&mut self.errors);
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.pop().unwrap()),
+            _ => Err(TypeError::BlockErrors(errors)),
+        }
+    }
+    /// Warnings found by `annotate` so far, like unused variables. Root
+    /// variables and top level declarations are only checked once the whole
+    /// program is annotated.
+    pub fn take_warnings(&mut self) -> Vec<TypeError> {
+        let mut warnings = mem::take(&mut self.warnings);
+        warnings.sort_by_key(|warning| match warning {
+            TypeError::Error(_, _, span) | TypeError::Labeled(_, _, span, _) => span.start,
+            _ => 0,
+        });
+        warnings
+    }
+    fn warn(&mut self, code: ErrorCode, message: String, span: Span) {
+        self.warnings.push(TypeError::Error(code, message, span));
+    }
+    fn declare_variable(&mut self, name: &String, t: AstType, pos: Span) {
+        let shadowed = self
+            .variables
+            .iter()
+            .rev()
+            .find(|v| &v.name == name && v.depth < self.current_scope_depth)
+            .map(|v| v.pos);
+        if let Some(shadowed) = shadowed {
+            self.warnings.push(TypeError::Labeled(
+                ErrorCode::ShadowedVariable,
+                format!("{} shadows a variable from an outer scope", name),
+                pos,
+                vec![("shadowed variable declared here".to_string(), shadowed)],
+            ));
+        }
         self.variables.push(LocalVariable {
             name: name.clone(),
             depth: self.current_scope_depth,
             t,
+            pos,
+            used: false,
         });
     }
+    fn mark_used(&mut self, name: &String) {
+        match self.variables.iter_mut().rev().find(|v| &v.name == name) {
+            Some(local) => local.used = true,
+            None => {
+                self.used_globals.insert(name.clone());
+            }
+        }
+    }
+    /// Pops the variables declared deeper than the current scope, warning
+    /// about the ones that were never read.
+    fn end_scope(&mut self) {
+        while self.variables.last().map(|v| v.depth).unwrap_or(0) > self.current_scope_depth {
+            let local = self.variables.pop().unwrap();
+            self.warn_unused(local);
+        }
+    }
+    fn warn_unused(&mut self, local: LocalVariable) {
+        if !local.used && !local.name.starts_with('_') {
+            self.warn(
+                ErrorCode::UnusedVariable,
+                format!("unused variable {}", local.name),
+                local.pos,
+            );
+        }
+    }
+    fn warn_unused_globals(&mut self) {
+        let mut unused: Vec<(String, Span, &str)> = self
+            .global_spans
+            .iter()
+            .map(|(name, span)| (name, span, "function"))
+            .chain(
+                self.enum_spans
+                    .iter()
+                    .map(|(name, span)| (name, span, "enum")),
+            )
+            .filter(|(name, _, _)| !self.used_globals.contains(*name) && !name.starts_with('_'))
+            .map(|(name, span, kind)| (name.clone(), *span, kind))
+            .collect();
+        unused.sort_by_key(|(_, span, _)| span.start);
+        for (name, span, kind) in unused.into_iter() {
+            self.warn(
+                ErrorCode::UnusedDeclaration,
+                format!("{} {} is never used", kind, name),
+                span,
+            );
+        }
+    }
     fn resolve_variable(&mut self, name: &String) -> Option<Variable> {
         let local = self
             .variables
@@ -137,7 +333,99 @@ impl<'a> TypeChecker<'a> {
             .flatten()
             .map(|t| Variable::Global(t))
     }
+    /// Replaces `name` by `module.name` when it's only defined in an
+    /// imported module.
+    fn qualify(&mut self, name: &mut String, span: Span) -> Result<(), TypeError> {
+        let externals = match self.externals {
+            Some(externals) if !self.imports.is_empty() => externals,
+            _ => return Ok(()),
+        };
+        if self.resolve_variable(name).is_some() {
+            return Ok(());
+        }
+        let mut qualified = self
+            .imports
+            .iter()
+            .map(|module| format!("{}.{}", module, name))
+            .filter(|qualified| {
+                externals.lookup_type(qualified).is_some()
+                    || externals.lookup_denied(qualified).is_some()
+            });
+        match (qualified.next(), qualified.next()) {
+            (Some(first), Some(second)) => Err(TypeError::Error(
+                ErrorCode::AmbiguousName,
+                format!("{} is ambiguous, it could be {} or {}", name, first, second),
+                span,
+            )),
+            (Some(first), None) => {
+                *name = first;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+    /// Error for an external of a capability group the script isn't
+    /// granted.
+    fn check_granted(&self, name: &String, span: Span) -> Result<(), TypeError> {
+        match self.externals.and_then(|ext| ext.lookup_denied(name)) {
+            Some(capability) => Err(TypeError::Error(
+                ErrorCode::NotAllowed,
+                format!(
+                    "{} needs the {} capability, which the script isn't granted",
+                    name,
+                    capability.name()
+                ),
+                span,
+            )),
+            None => Ok(()),
+        }
+    }
+    /// Error for a name that doesn't resolve, suggesting a similarly spelled
+    /// name if there is one in scope.
+    fn undefined(&self, name: &str, span: Span) -> TypeError {
+        let error = TypeError::Error(
+            ErrorCode::UndefinedName,
+            format!("{} is not defined", name),
+            span,
+        );
+        match self.similar_name(name) {
+            Some(similar) => {
+                TypeError::Help(Box::new(error), format!("did you mean `{}`?", similar))
+            }
+            None => error,
+        }
+    }
+    fn similar_name(&self, name: &str) -> Option<String> {
+        let variants = self.custom_types.iter().filter_map(|(name, t)| match t {
+            CustomType::EnumVariant { .. } => Some(name),
+            _ => None,
+        });
+        let externals = self.externals.into_iter().flat_map(|ext| ext.names());
+        let max_distance = (name.chars().count() / 3).max(1);
+        self.variables
+            .iter()
+            .map(|v| &v.name)
+            .chain(self.globals.keys())
+            .chain(variants)
+            .chain(externals)
+            .map(|candidate| (edit_distance(name, candidate), candidate))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .min()
+            .map(|(_, candidate)| candidate.clone())
+    }
+    fn declaration_span(&self, name: &String) -> Option<Span> {
+        self.variables
+            .iter()
+            .rev()
+            .find(|v| &v.name == name)
+            .map(|v| v.pos)
+            .or_else(|| self.global_spans.get(name).cloned())
+    }
     fn resolve_type(&self, name: &String) -> Option<AstType> {
+        let foreign = self.externals.and_then(|ext| ext.lookup_foreign_type(name));
+        if foreign.is_some() {
+            return foreign;
+        }
         self.custom_types.get(name).map(|t| match t {
             CustomType::Enum {
                 enum_type,
@@ -153,59 +441,117 @@ impl<'a> TypeChecker<'a> {
             },
         })
     }
-    fn resolve_unresolved_type(&self, t: &mut AstType) {
+    fn resolve_unresolved_type(&mut self, t: &mut AstType, span: Span) {
         match t {
-            AstType::Unresolved(name) => {
-                *t = self.resolve_type(name).unwrap().clone();
+            AstType::Unresolved(name) => match self.resolve_type(name) {
+                Some(resolved) => {
+                    if let AstType::EnumVariant { enum_type, .. } = &resolved {
+                        self.used_globals.insert(enum_type.clone());
+                    }
+                    *t = resolved;
+                }
+                None => {
+                    let error = self.undefined(name, span);
+                    self.errors.push(error);
+                    *t = AstType::Unknown;
+                }
+            },
+            AstType::Function(args, ret) | AstType::Closure(args, ret) => {
+                for arg in args.iter_mut() {
+                    self.resolve_unresolved_type(arg, span);
+                }
+                self.resolve_unresolved_type(ret, span);
             }
             _ => {}
         }
     }
+    /// Annotates `ast`, recording the error and continuing with an unknown
+    /// type if it fails.
+    fn check(&mut self, ast: &mut Ast) -> (AstType, bool) {
+        match self.annotate_type(ast) {
+            Ok(result) => result,
+            Err(error) => {
+                self.errors.push(error);
+                (AstType::Unknown, false)
+            }
+        }
+    }
+    /// Like `check`, for expressions used as values, where an unresolved
+    /// name or an external function is an error.
+    fn check_value(&mut self, ast: &mut Ast) -> AstType {
+        match self.check(ast).0 {
+            AstType::Unresolved(name) => {
+                let error = self.undefined(&name, ast.span());
+                self.errors.push(error);
+                AstType::Unknown
+            }
+            AstType::ExternalFunction(..) => {
+                let error = TypeError::Error(
+                    ErrorCode::ExternalAsValue,
+                    "external functions can only be called".to_string(),
+                    ast.span(),
+                );
+                self.errors.push(TypeError::Help(
+                    Box::new(error),
+                    "call it from a script function and use that instead".to_string(),
+                ));
+                AstType::Unknown
+            }
+            t => t,
+        }
+    }
     fn annotate_type(&mut self, ast: &mut Ast) -> Result<(AstType, bool), TypeError> {
         let (t, diverges) = match ast {
             Ast::Program(ps) => {
-                let mut errors = Vec::new();
                 let mut diverges = false;
                 for p in ps.iter_mut() {
-                    match self.annotate_type(p) {
-                        Ok((_, d)) => {
-                            diverges = diverges || d;
-                        }
-                        Err(error) => errors.push(error),
-                    }
+                    diverges = self.check(p).1 || diverges;
                 }
-                if errors.len() > 0 {
-                    return Err(TypeError::BlockErrors(errors));
+                for local in self.variables.clone().into_iter() {
+                    self.warn_unused(local);
                 }
+                self.warn_unused_globals();
                 (AstType::Nil, diverges)
             }
             Ast::Block { cont, .. } => {
-                let mut errors = Vec::new();
                 self.current_scope_depth += 1;
-                let mut diverges = false;
+                let mut diverges = None;
+                let mut warned_unreachable = false;
                 for p in cont.iter_mut() {
-                    match self.annotate_type(p) {
-                        Ok((_, d)) => {
-                            diverges = d || diverges;
-                        }
-                        Err(error) => errors.push(error),
+                    if let (Some(diverging), false) = (diverges, warned_unreachable) {
+                        self.warnings.push(TypeError::Labeled(
+                            ErrorCode::UnreachableCode,
+                            "unreachable statement".to_string(),
+                            p.span(),
+                            vec![(
+                                "any code following this is unreachable".to_string(),
+                                diverging,
+                            )],
+                        ));
+                        warned_unreachable = true;
+                    }
+                    if self.check(p).1 && diverges.is_none() {
+                        diverges = Some(p.span());
                     }
                 }
+                let diverges = diverges.is_some();
                 self.current_scope_depth -= 1;
-                while self.variables.last().map(|v| v.depth).unwrap_or(0) > self.current_scope_depth
-                {
-                    self.variables.pop();
-                }
-                if errors.len() > 0 {
-                    return Err(TypeError::BlockErrors(errors));
-                }
+                self.end_scope();
                 (AstType::Nil, diverges)
             }
             Ast::Print { expr, t, pos } => {
-                let expr_t = match self.annotate_type(expr)?.0 {
-                    t @ AstType::Bool | t @ AstType::Float | t @ AstType::String => t,
+                let expr_t = match self.check_value(expr) {
+                    t @ AstType::Bool
+                    | t @ AstType::Float
+                    | t @ AstType::String
+                    | t @ AstType::Unknown => t,
                     t @ _ => {
-                        return Err(TypeError::Error(format!("cannot print type {:?}", t), *pos))
+                        return Err(TypeError::Labeled(
+                            ErrorCode::UnprintableType,
+                            format!("cannot print type {}", t),
+                            expr.span(),
+                            vec![("in this print statement".to_string(), *pos)],
+                        ))
                     }
                 };
                 t.replace(expr_t);
@@ -214,23 +560,24 @@ impl<'a> TypeChecker<'a> {
             Ast::Return { expr, t, pos } => {
                 if self.is_root {
                     return Err(TypeError::Error(
+                        ErrorCode::ReturnOutsideFunction,
                         "can't return from root function".to_string(),
                         *pos,
                     ));
                 }
                 let expr_t = if let Some(expr) = expr {
-                    self.annotate_type(expr)?.0
+                    self.check_value(expr)
                 } else {
                     AstType::Nil
                 };
                 t.replace(expr_t.clone());
-                self.return_values.push(expr_t);
+                self.return_values.push((expr_t, *pos));
                 (AstType::Nil, true)
             }
-            Ast::Declaration { name, expr, t, .. } => {
-                let expr_t = self.annotate_type(expr)?.0;
+            Ast::Declaration { name, expr, t, pos } => {
+                let expr_t = self.check_value(expr);
                 t.replace(expr_t.clone());
-                self.declare_variable(name, expr_t);
+                self.declare_variable(name, expr_t, *pos);
                 (AstType::Nil, false)
             }
             Ast::FuncDeclaration {
@@ -241,18 +588,26 @@ impl<'a> TypeChecker<'a> {
                 pos,
             } => {
                 if self.is_root && self.current_scope_depth == 0 {
+                    // Unresolved names are reported when checking `func`.
+                    for t in args_t.iter_mut().chain(std::iter::once(&mut *ret_t)) {
+                        if let AstType::Unresolved(name) = t {
+                            *t = self.resolve_type(name).unwrap_or(AstType::Unknown);
+                        }
+                    }
                     self.globals.insert(
                         name.clone(),
                         AstType::Function(args_t.clone(), Box::new(ret_t.clone())),
                     );
+                    self.global_spans.insert(name.clone(), *pos);
                 } else {
                     return Err(TypeError::Error(
+                        ErrorCode::NotTopLevel,
                         "global function declarations are only allowed at the top level"
                             .to_string(),
                         *pos,
                     ));
                 }
-                self.annotate_type(func)?.0;
+                self.check(func);
                 (AstType::Nil, false)
             }
             Ast::EnumDeclaration {
@@ -262,10 +617,34 @@ impl<'a> TypeChecker<'a> {
             } => {
                 if !(self.is_root && self.current_scope_depth == 0) {
                     return Err(TypeError::Error(
+                        ErrorCode::NotTopLevel,
                         "enum declarations are only allowed at the top level".to_string(),
                         *pos,
                     ));
                 }
+                if let Some(host) = self.externals.and_then(|ext| ext.lookup_enum(name)) {
+                    let matches = variants.len() == host.len()
+                        && variants
+                            .iter()
+                            .zip(host.iter())
+                            .all(|((variant, t), host)| {
+                                *t == AstType::Nil && variant.eq_ignore_ascii_case(host)
+                            });
+                    if !matches {
+                        self.errors.push(TypeError::Error(
+                            ErrorCode::HostEnumMismatch,
+                            format!(
+                                "enum {} has to declare the host's variants {}",
+                                name,
+                                host.join(" | ")
+                            ),
+                            *pos,
+                        ));
+                    }
+                }
+                for (_, t) in variants.iter_mut() {
+                    self.resolve_unresolved_type(t, *pos);
+                }
                 let max_size = variants.iter().map(|(_, t)| t.width()).max().unwrap_or(0);
                 for var in variants.iter() {
                     if self
@@ -281,6 +660,7 @@ impl<'a> TypeChecker<'a> {
                         .is_some()
                     {
                         return Err(TypeError::Error(
+                            ErrorCode::NameInUse,
                             format!("name {} already in use", var.0),
                             *pos,
                         ));
@@ -298,14 +678,23 @@ impl<'a> TypeChecker<'a> {
                     .is_some()
                 {
                     return Err(TypeError::Error(
+                        ErrorCode::NameInUse,
                         format!("name {} already in use", name),
                         *pos,
                     ));
                 }
+                self.enum_spans.insert(name.clone(), *pos);
                 (AstType::Nil, false)
             }
             Ast::Variable { name, t, pos } => {
+                self.qualify(name, *pos)?;
                 let v = self.resolve_variable(name);
+                if v.is_none() {
+                    self.check_granted(name, *pos)?;
+                }
+                if v.is_some() {
+                    self.mark_used(name);
+                }
                 (
                     match v {
                         Some(Variable::Local(local)) => {
@@ -335,19 +724,21 @@ impl<'a> TypeChecker<'a> {
                 move_to_heap,
                 pos,
             } => {
-                let v_t = match self.resolve_variable(name).ok_or(TypeError::Error(
-                    format!("variable {} is not defined", name),
-                    *pos,
-                ))? {
-                    Variable::Local(local) => local.t.clone(),
+                let expr_t = self.check_value(expr);
+                self.qualify(name, *pos)?;
+                let (v_t, declared) = match self
+                    .resolve_variable(name)
+                    .ok_or_else(|| self.undefined(name, *pos))?
+                {
+                    Variable::Local(local) => (local.t.clone(), local.pos),
                     Variable::Global(_) => {
                         return Err(TypeError::Error(
+                            ErrorCode::AssignToGlobal,
                             format!("can't assign to global variable {}", name),
                             *pos,
                         ))
                     }
                 };
-                let expr_t = self.annotate_type(expr)?.0;
                 match (&v_t, &expr_t) {
                     (a, b) if a == b => {
                         move_to_heap.replace(false);
@@ -355,13 +746,17 @@ impl<'a> TypeChecker<'a> {
                     (AstType::HeapAllocated(a), b) if **a == *b => {
                         move_to_heap.replace(true);
                     }
+                    (AstType::Unknown, _) | (_, AstType::Unknown) => {}
+                    (AstType::HeapAllocated(a), _) if **a == AstType::Unknown => {}
                     _ => {
-                        return Err(TypeError::Error(
+                        return Err(TypeError::Labeled(
+                            ErrorCode::MismatchedTypes,
                             format!(
-                                "cannot assign value of type {:?} to variable with type {:?}",
+                                "cannot assign value of type {} to variable with type {}",
                                 expr_t, v_t
                             ),
                             *pos,
+                            vec![(format!("{} declared here", name), declared)],
                         ));
                     }
                 }
@@ -374,30 +769,35 @@ impl<'a> TypeChecker<'a> {
                 default,
                 pos,
             } => {
-                let switch_t = self.annotate_type(head)?.0;
+                let mut switch_t = self.check_value(head);
                 if switch_t == AstType::Nil {
-                    return Err(TypeError::Error(
+                    self.errors.push(TypeError::Labeled(
+                        ErrorCode::NilSwitch,
                         "switch value cannot be nil".to_string(),
-                        *pos,
+                        head.span(),
+                        vec![("in this switch".to_string(), *pos)],
                     ));
+                    switch_t = AstType::Unknown;
                 }
                 let mut diverges = if let Some(default) = default {
-                    self.annotate_type(default)?.1
+                    self.check(default).1
                 } else {
                     false
                 };
                 for (case, body) in cases.iter_mut() {
-                    let t = self.annotate_type(case)?.0;
-                    if t != switch_t {
-                        return Err(TypeError::Error(
+                    let t = self.check_value(case);
+                    if t != switch_t && t != AstType::Unknown && switch_t != AstType::Unknown {
+                        self.errors.push(TypeError::Labeled(
+                            ErrorCode::MismatchedTypes,
                             format!(
-                                "expected switch case to be type {:?}, but found type {:?}",
+                                "expected switch case to be type {}, but found type {}",
                                 switch_t, t
                             ),
-                            *pos,
+                            case.span(),
+                            vec![(format!("switch value is {}", switch_t), head.span())],
                         ));
                     }
-                    diverges = self.annotate_type(body)?.1 && diverges;
+                    diverges = self.check(body).1 && diverges;
                 }
                 (AstType::Nil, diverges)
             }
@@ -405,38 +805,26 @@ impl<'a> TypeChecker<'a> {
                 condition,
                 body,
                 else_body,
-                pos,
+                ..
             } => {
-                if self.annotate_type(condition)?.0 != AstType::Bool {
-                    return Err(TypeError::Error(
-                        "condition must be a bool".to_string(),
-                        *pos,
-                    ));
-                }
-                let mut diverges = self.annotate_type(body)?.1;
+                self.check_condition(condition);
+                let mut diverges = self.check(body).1;
                 if let Some(else_body) = else_body {
-                    diverges = self.annotate_type(else_body)?.1 && diverges;
+                    diverges = self.check(else_body).1 && diverges;
                 } else {
                     diverges = false;
                 }
                 (AstType::Nil, diverges)
             }
             Ast::While {
-                condition,
-                body,
-                pos,
+                condition, body, ..
             } => {
-                if self.annotate_type(condition)?.0 != AstType::Bool {
-                    return Err(TypeError::Error(
-                        "condition must be a bool".to_string(),
-                        *pos,
-                    ));
-                }
-                let diverges = self.annotate_type(body)?.1;
+                self.check_condition(condition);
+                let diverges = self.check(body).1;
                 (AstType::Nil, diverges)
             }
             Ast::ExprStatement { expr, t, .. } => {
-                let expr_t = self.annotate_type(expr)?.0;
+                let expr_t = self.check_value(expr);
                 t.replace(expr_t);
                 (AstType::Nil, false)
             }
@@ -447,65 +835,87 @@ impl<'a> TypeChecker<'a> {
                 ret_t,
                 pos,
             } => {
-                captured
-                    .iter_mut()
-                    .map(|(name, var_t)| match self.resolve_variable(name) {
-                        Some(Variable::Local(LocalVariable { t, .. }))
-                        | Some(Variable::Global(t)) => {
+                for (name, var_t) in captured.iter_mut() {
+                    match self.resolve_variable(name) {
+                        Some(Variable::Local(LocalVariable { t, .. })) => {
+                            self.mark_used(name);
+                            // captured again by a nested closure, the value is copied
+                            var_t.replace(match t {
+                                AstType::HeapAllocated(t) => *t,
+                                t => t,
+                            });
+                        }
+                        Some(Variable::Global(t)) => {
+                            self.mark_used(name);
+                            self.errors.push(TypeError::Error(
+                                ErrorCode::CapturedGlobal,
+                                format!("{} is global and can be used without capturing it", name),
+                                *pos,
+                            ));
                             var_t.replace(t);
-                            Ok(())
                         }
-                        None => Err(TypeError::Error(
-                            format!("variable {} is not defined", name),
-                            *pos,
-                        )),
-                    })
-                    .collect::<Result<Vec<_>, TypeError>>()?;
+                        None => {
+                            let error = self.undefined(name, *pos);
+                            self.errors.push(error);
+                            var_t.replace(AstType::Unknown);
+                        }
+                    }
+                }
 
-                for (_, arg_t) in args.iter_mut() {
-                    self.resolve_unresolved_type(arg_t);
+                for (_, arg_t, arg_pos) in args.iter_mut() {
+                    self.resolve_unresolved_type(arg_t, *arg_pos);
                 }
-                self.resolve_unresolved_type(ret_t);
+                self.resolve_unresolved_type(ret_t, *pos);
 
-                let old_variables = mem::replace(&mut self.variables, vec![]);
-                let old_return_values = mem::replace(&mut self.return_values, vec![]);
+                let old_variables = mem::take(&mut self.variables);
+                let old_return_values = mem::take(&mut self.return_values);
                 let old_depth = mem::replace(&mut self.current_scope_depth, 0);
                 let old_is_root = mem::replace(&mut self.is_root, false);
 
                 for arg in args.iter() {
-                    self.declare_variable(&arg.0, arg.1.clone());
+                    self.declare_variable(&arg.0, arg.1.clone(), arg.2);
                 }
                 for var in captured.iter() {
                     self.declare_variable(
                         &var.0,
                         AstType::HeapAllocated(Box::new(var.1.clone().unwrap())),
+                        *pos,
                     );
+                    // Unused captures are not reported, they have no span of their own.
+                    self.mark_used(&var.0);
                 }
 
-                let result = self.annotate_type(body);
+                let diverges = self.check(body).1;
 
-                mem::replace(&mut self.variables, old_variables);
+                for local in mem::replace(&mut self.variables, old_variables).into_iter() {
+                    self.warn_unused(local);
+                }
                 let return_values = mem::replace(&mut self.return_values, old_return_values);
-                mem::replace(&mut self.current_scope_depth, old_depth);
-                mem::replace(&mut self.is_root, old_is_root);
+                self.current_scope_depth = old_depth;
+                self.is_root = old_is_root;
 
-                let diverges = result?.1;
-
-                if let Some(t) = return_values.iter().filter(|t| *t != ret_t).next() {
-                    return Err(TypeError::Error(
-                        format!("return type {:?} doesn't match signature {:?}", t, ret_t),
-                        *pos,
+                if let Some((t, return_pos)) = return_values
+                    .iter()
+                    .find(|(t, _)| t != ret_t && *t != AstType::Unknown)
+                {
+                    self.errors.push(TypeError::Labeled(
+                        ErrorCode::ReturnMismatch,
+                        format!("return type {} doesn't match signature {}", t, ret_t),
+                        *return_pos,
+                        vec![("function declared here".to_string(), *pos)],
                     ));
                 }
-                if return_values.len() > 0 && !diverges {
-                    return Err(TypeError::Error(
-                        format!("all possible brances of function body needs to return",),
+                if !return_values.is_empty() && !diverges {
+                    self.errors.push(TypeError::Error(
+                        ErrorCode::MissingReturn,
+                        "all possible brances of function body needs to return".to_string(),
                         *pos,
                     ));
-                } else if return_values.len() == 0 && *ret_t != AstType::Nil {
-                    return Err(TypeError::Error(
+                } else if return_values.is_empty() && *ret_t != AstType::Nil {
+                    self.errors.push(TypeError::Error(
+                        ErrorCode::MissingReturn,
                         format!(
-                            "function with return type {:?} needs explicit return statement",
+                            "function with return type {} needs explicit return statement",
                             ret_t
                         ),
                         *pos,
@@ -513,7 +923,7 @@ impl<'a> TypeChecker<'a> {
                 }
 
                 (
-                    if captured.len() == 0 {
+                    if captured.is_empty() {
                         AstType::Function(
                             args.iter().map(|t| t.1.clone()).collect(),
                             Box::new(ret_t.clone()),
@@ -534,10 +944,14 @@ impl<'a> TypeChecker<'a> {
                 call_t,
                 pos,
             } => {
-                let ident_t = self.annotate_type(ident)?.0;
+                let ident_t = self.check(ident).0;
+                let declared = match &**ident {
+                    Ast::Variable { name, .. } => self.declaration_span(name),
+                    _ => None,
+                };
                 let mut args_t = vec![];
                 for arg in args.iter_mut() {
-                    args_t.push(self.annotate_type(arg)?.0);
+                    args_t.push(self.check_value(arg));
                 }
                 let (func_args_t, ret_t) = match ident_t {
                     AstType::Closure(a, r) => {
@@ -558,6 +972,7 @@ impl<'a> TypeChecker<'a> {
                             max_size,
                             wraps,
                         }) => {
+                            self.used_globals.insert(enum_type.clone());
                             call_t.replace(CallType::Enum);
                             let wraps = match wraps {
                                 AstType::Nil => Vec::new(),
@@ -571,19 +986,43 @@ impl<'a> TypeChecker<'a> {
                                 }),
                             )
                         }
-                        _ => return Err(TypeError::Error(format!("unresolved {:?}", name), *pos)),
+                        _ => return Err(self.undefined(&name, *pos)),
                     },
+                    AstType::Unknown => return Ok((AstType::Unknown, false)),
                     t @ _ => {
-                        return Err(TypeError::Error(format!("cannot call type {:?}", t), *pos))
+                        return Err(TypeError::Error(
+                            ErrorCode::NotCallable,
+                            format!("cannot call type {}", t),
+                            *pos,
+                        ))
                     }
                 };
-                if args_t != func_args_t {
-                    return Err(TypeError::Error(
+                let matches = |(t, expected): (&AstType, &AstType)| {
+                    t == expected || *t == AstType::Unknown || *expected == AstType::Unknown
+                };
+                if args_t.len() != func_args_t.len()
+                    || !args_t.iter().zip(func_args_t.iter()).all(matches)
+                {
+                    let mut labels: Vec<(String, Span)> = args
+                        .iter()
+                        .zip(args_t.iter().zip(func_args_t.iter()))
+                        .filter(|(_, types)| !matches(*types))
+                        .map(|(arg, (t, expected))| {
+                            (format!("expected {}, found {}", expected, t), arg.span())
+                        })
+                        .collect();
+                    if let Some(declared) = declared {
+                        labels.push(("declared here".to_string(), declared));
+                    }
+                    self.errors.push(TypeError::Labeled(
+                        ErrorCode::ArgumentMismatch,
                         format!(
-                            "arguments doesn't match, requires {:?}, got {:?}",
-                            func_args_t, args_t
+                            "arguments doesn't match, requires ({}), got ({})",
+                            type_list(&func_args_t),
+                            type_list(&args_t)
                         ),
                         *pos,
+                        labels,
                     ));
                 }
                 args_width.replace(args_t.iter().map(|t| t.width()).sum::<usize>() as u8);
@@ -593,24 +1032,28 @@ impl<'a> TypeChecker<'a> {
             Ast::Bool(_, _) => (AstType::Bool, false),
             Ast::String(_, _) => (AstType::String, false),
             Ast::Negate(a, pos) => {
-                let t = self.annotate_type(a)?.0;
+                let t = self.check_value(a);
                 match t {
-                    AstType::Float => {}
+                    AstType::Float | AstType::Unknown => {}
                     _ => {
-                        return Err(TypeError::Error(
-                            format!("operation can't be preformed on type {:?}", t),
+                        return Err(TypeError::Labeled(
+                            ErrorCode::InvalidOperand,
+                            format!("operation can't be preformed on type {}", t),
                             *pos,
+                            vec![(format!("this is {}", t), a.span())],
                         ));
                     }
                 }
                 (t, false)
             }
             Ast::Not(a, pos) => {
-                let t = self.annotate_type(a)?.0;
-                if t != AstType::Bool {
-                    return Err(TypeError::Error(
+                let t = self.check_value(a);
+                if t != AstType::Bool && t != AstType::Unknown {
+                    return Err(TypeError::Labeled(
+                        ErrorCode::InvalidOperand,
                         "not (!) operation requires a bool".to_string(),
                         *pos,
+                        vec![(format!("this is {}", t), a.span())],
                     ));
                 }
                 (AstType::Bool, false)
@@ -619,22 +1062,28 @@ impl<'a> TypeChecker<'a> {
             | Ast::Divide(l, r, t, pos)
             | Ast::Add(l, r, t, pos)
             | Ast::Sub(l, r, t, pos) => {
-                let t_l = self.annotate_type(l)?.0;
-                let t_r = self.annotate_type(r)?.0;
+                let t_l = self.check_value(l);
+                let t_r = self.check_value(r);
+                if t_l == AstType::Unknown || t_r == AstType::Unknown {
+                    return Ok((AstType::Unknown, false));
+                }
                 if t_l != t_r {
-                    return Err(TypeError::Error(
+                    return Err(TypeError::Labeled(
+                        ErrorCode::MismatchedTypes,
                         format!(
-                            "type of left operand ({:?}) doesn't match type of right ({:?})",
+                            "type of left operand ({}) doesn't match type of right ({})",
                             t_l, t_r
                         ),
                         *pos,
+                        operand_labels(l, &t_l, r, &t_r),
                     ));
                 }
                 match t_l {
                     AstType::Float => {}
                     _ => {
                         return Err(TypeError::Error(
-                            format!("operation can't be preformed on type {:?}", t_l),
+                            ErrorCode::InvalidOperand,
+                            format!("operation can't be preformed on type {}", t_l),
                             *pos,
                         ));
                     }
@@ -642,23 +1091,108 @@ impl<'a> TypeChecker<'a> {
                 t.replace(t_r.clone());
                 (t_r, false)
             }
+            Ast::Import { module, pos } => {
+                if !(self.is_root && self.current_scope_depth == 0) {
+                    return Err(TypeError::Error(
+                        ErrorCode::NotTopLevel,
+                        "imports are only allowed at the top level".to_string(),
+                        *pos,
+                    ));
+                }
+                if !self.externals.is_some_and(|ext| ext.has_module(module)) {
+                    return Err(TypeError::Error(
+                        ErrorCode::UndefinedName,
+                        format!("module {} is not defined", module),
+                        *pos,
+                    ));
+                }
+                if !self.imports.contains(module) {
+                    self.imports.push(module.clone());
+                }
+                (AstType::Nil, false)
+            }
+            Ast::Test { name, body, pos } => {
+                if !(self.is_root && self.current_scope_depth == 0) {
+                    return Err(TypeError::Error(
+                        ErrorCode::NotTopLevel,
+                        "tests are only allowed at the top level".to_string(),
+                        *pos,
+                    ));
+                }
+                if let Some(declared) = self.test_spans.insert(name.clone(), *pos) {
+                    self.errors.push(TypeError::Labeled(
+                        ErrorCode::NameInUse,
+                        format!("test \"{}\" is already defined", name),
+                        *pos,
+                        vec![("first defined here".to_string(), declared)],
+                    ));
+                }
+                // Tests run on their own, without the root variables.
+                let old_variables = mem::take(&mut self.variables);
+                self.check(body);
+                self.variables = old_variables;
+                (AstType::Nil, false)
+            }
+            Ast::Assert { condition, .. } => {
+                self.check_condition(condition);
+                (AstType::Nil, false)
+            }
+            Ast::AssertEq { lhs, rhs, t, pos } => {
+                let t_l = self.check_value(lhs);
+                let t_r = self.check_value(rhs);
+                if t_l == AstType::Unknown || t_r == AstType::Unknown {
+                    return Ok((AstType::Nil, false));
+                }
+                if t_l != t_r {
+                    return Err(TypeError::Labeled(
+                        ErrorCode::MismatchedTypes,
+                        format!(
+                            "values compared by assert_eq have different types, {} and {}",
+                            t_l, t_r
+                        ),
+                        *pos,
+                        operand_labels(lhs, &t_l, rhs, &t_r),
+                    ));
+                }
+                match t_l {
+                    AstType::EnumVariant { .. }
+                    | AstType::Bool
+                    | AstType::Float
+                    | AstType::String => {}
+                    _ => {
+                        return Err(TypeError::Error(
+                            ErrorCode::InvalidOperand,
+                            format!("values of type {} can't be compared", t_l),
+                            *pos,
+                        ));
+                    }
+                }
+                t.replace(t_l);
+                (AstType::Nil, false)
+            }
             Ast::Equal(l, r, t, pos) | Ast::NotEqual(l, r, t, pos) => {
-                let t_l = self.annotate_type(l)?.0;
-                let t_r = self.annotate_type(r)?.0;
+                let t_l = self.check_value(l);
+                let t_r = self.check_value(r);
+                if t_l == AstType::Unknown || t_r == AstType::Unknown {
+                    return Ok((AstType::Bool, false));
+                }
                 if t_l != t_r {
-                    return Err(TypeError::Error(
+                    return Err(TypeError::Labeled(
+                        ErrorCode::MismatchedTypes,
                         format!(
-                            "type of left operand ({:?}) doesn't match type of right ({:?})",
+                            "type of left operand ({}) doesn't match type of right ({})",
                             t_l, t_r
                         ),
                         *pos,
+                        operand_labels(l, &t_l, r, &t_r),
                     ));
                 }
                 match t_l {
                     AstType::EnumVariant { .. } | AstType::Bool | AstType::Float => {}
                     _ => {
                         return Err(TypeError::Error(
-                            format!("operation can't be preformed on type {:?}", t_l),
+                            ErrorCode::InvalidOperand,
+                            format!("operation can't be preformed on type {}", t_l),
                             *pos,
                         ));
                     }
@@ -670,22 +1204,28 @@ impl<'a> TypeChecker<'a> {
             | Ast::GreaterEqual(l, r, t, pos)
             | Ast::Lesser(l, r, t, pos)
             | Ast::LesserEqual(l, r, t, pos) => {
-                let t_l = self.annotate_type(l)?.0;
-                let t_r = self.annotate_type(r)?.0;
+                let t_l = self.check_value(l);
+                let t_r = self.check_value(r);
+                if t_l == AstType::Unknown || t_r == AstType::Unknown {
+                    return Ok((AstType::Bool, false));
+                }
                 if t_l != t_r {
-                    return Err(TypeError::Error(
+                    return Err(TypeError::Labeled(
+                        ErrorCode::MismatchedTypes,
                         format!(
-                            "type of left operand ({:?}) doesn't match type of right ({:?})",
+                            "type of left operand ({}) doesn't match type of right ({})",
                             t_l, t_r
                         ),
                         *pos,
+                        operand_labels(l, &t_l, r, &t_r),
                     ));
                 }
                 match t_l {
                     AstType::Float => {}
                     _ => {
                         return Err(TypeError::Error(
-                            format!("operation can't be preformed on type {:?}", t_l),
+                            ErrorCode::InvalidOperand,
+                            format!("operation can't be preformed on type {}", t_l),
                             *pos,
                         ));
                     }
@@ -694,12 +1234,15 @@ impl<'a> TypeChecker<'a> {
                 (AstType::Bool, false)
             }
             Ast::And(l, r, pos) | Ast::Or(l, r, pos) => {
-                let t_l = self.annotate_type(l)?.0;
-                let t_r = self.annotate_type(r)?.0;
-                if t_l != AstType::Bool || t_r != AstType::Bool {
-                    return Err(TypeError::Error(
+                let t_l = self.check_value(l);
+                let t_r = self.check_value(r);
+                let is_bool = |t: &AstType| *t == AstType::Bool || *t == AstType::Unknown;
+                if !is_bool(&t_l) || !is_bool(&t_r) {
+                    return Err(TypeError::Labeled(
+                        ErrorCode::InvalidOperand,
                         "operation requires both operands to be bool".to_string(),
                         *pos,
+                        operand_labels(l, &t_l, r, &t_r),
                     ));
                 }
                 (AstType::Bool, false)
@@ -707,4 +1250,21 @@ impl<'a> TypeChecker<'a> {
         };
         Ok((t, diverges))
     }
+    fn check_condition(&mut self, condition: &mut Ast) {
+        let condition_t = self.check_value(condition);
+        if condition_t != AstType::Bool && condition_t != AstType::Unknown {
+            self.errors.push(TypeError::Error(
+                ErrorCode::NonBoolCondition,
+                format!("condition must be a bool, found {}", condition_t),
+                condition.span(),
+            ));
+        }
+    }
+}
+
+fn operand_labels(l: &Ast, t_l: &AstType, r: &Ast, t_r: &AstType) -> Vec<(String, Span)> {
+    vec![
+        (format!("this is {}", t_l), l.span()),
+        (format!("this is {}", t_r), r.span()),
+    ]
 }
@@ -16,6 +16,15 @@ macro_rules! binary_op_f64{
 
 pub type ChunkAdr = u16;
 
+/// Error that stops a script, like a failed assertion.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub message: String,
+    pub span: Span,
+    /// Calls of the functions the error happened in, innermost first.
+    pub trace: Vec<Span>,
+}
+
 struct CallFrame {
     parent_ip: CodeAdr,
     parent_chunk: ChunkAdr,
@@ -23,32 +32,178 @@ struct CallFrame {
     args_width: u8,
 }
 
+/// Error of a call from the host into a script function.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CallError {
+    /// No top level function has this name.
+    Undefined(String),
+    /// The arguments or return value don't match the function's signature,
+    /// which is given.
+    MismatchedTypes(AstType),
+    Runtime(RuntimeError),
+}
+
 pub struct VM<'a> {
     stack: Stack,
     heap: Heap,
     chunks: Vec<Chunk>,
-    call_frames: Vec<CallFrame>,
     externals: Option<&'a Externals>,
 }
 
+/// Runs the code of the chunks on a stack and heap. An external calling
+/// back into the script runs a nested machine on the same stack and heap.
+struct Machine<'m> {
+    stack: &'m mut Stack,
+    heap: &'m mut Heap,
+    chunks: &'m [Chunk],
+    externals: Option<&'m Externals>,
+    call_frames: Vec<CallFrame>,
+}
+
 impl<'a> VM<'a> {
     pub fn new(chunks: Vec<Chunk>, externals: Option<&'a Externals>) -> VM {
         VM {
             stack: Stack::new(),
             heap: Heap::new(),
             chunks,
-            call_frames: vec![],
             externals,
         }
     }
-    #[allow(dead_code)]
     pub fn heap_ptr(&self) -> &Heap {
         &self.heap
     }
-    pub fn run(&mut self, out: &mut dyn Write) {
+    /// Appends chunks compiled after the vm was created, see
+    /// `Compiler::take_chunks`.
+    pub fn add_chunks(&mut self, chunks: Vec<Chunk>) {
+        self.chunks.extend(chunks);
+    }
+    pub fn run(&mut self, out: &mut dyn Write) -> Result<(), RuntimeError> {
+        self.run_chunk(0, out)
+    }
+    /// Runs `entry` as the root function. The stack and heap are left as
+    /// they were after the previous run, so root variables stay alive. On an
+    /// error the stack is reset to where it was before the run.
+    pub fn run_chunk(&mut self, entry: ChunkAdr, out: &mut dyn Write) -> Result<(), RuntimeError> {
+        self.machine().execute(entry, 0, false, out)
+    }
+
+    /// The top level function `name` of the programs run so far, with the
+    /// address of its chunk.
+    pub fn lookup_function(&self, name: &str) -> Option<(ChunkAdr, &ScriptFunction)> {
+        self.chunks
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(i, chunk)| match chunk.function() {
+                Some(function) if function.name == name => Some((i as ChunkAdr, function)),
+                _ => None,
+            })
+    }
+
+    /// Calls the top level function `name` with `args`, after the program
+    /// declaring it has run.
+    pub fn call<Args: ScriptArgs, R: ExternalValue>(
+        &mut self,
+        name: &str,
+        args: Args,
+        out: &mut dyn Write,
+    ) -> Result<R, CallError> {
+        let (chunk_i, function) = match self.lookup_function(name) {
+            Some(found) => found,
+            None => return Err(CallError::Undefined(name.to_string())),
+        };
+        if Args::ast_types() != function.args_t || R::ast_type() != function.ret_t {
+            return Err(CallError::MismatchedTypes(function.ast_type()));
+        }
+        let mut callbacks = VmCallbacks {
+            chunks: &self.chunks,
+            externals: self.externals,
+        };
+        ExternalContext::new(
+            &mut self.stack,
+            &mut self.heap,
+            self.externals,
+            &mut callbacks,
+            out,
+        )
+        .call_function(chunk_i, args)
+        .map_err(CallError::Runtime)
+    }
+
+    fn machine(&mut self) -> Machine<'_> {
+        Machine {
+            stack: &mut self.stack,
+            heap: &mut self.heap,
+            chunks: &self.chunks,
+            externals: self.externals,
+            call_frames: vec![],
+        }
+    }
+}
+
+/// Runs the callbacks of externals on a nested machine.
+struct VmCallbacks<'m> {
+    chunks: &'m [Chunk],
+    externals: Option<&'m Externals>,
+}
+
+impl<'m> Callbacks for VmCallbacks<'m> {
+    fn call(
+        &mut self,
+        callee: Callee,
+        args_t: &[AstType],
+        stack: &mut Stack,
+        heap: &mut Heap,
+        out: &mut dyn Write,
+    ) -> Result<(), RuntimeError> {
+        let args_width = args_t.iter().map(|t| t.width()).sum::<usize>() as StackAdr;
+        let frame_offset = stack.len() - args_width;
+        let chunk_i = match callee {
+            Callee::Function(chunk_i) => chunk_i,
+            Callee::Closure(adr) => {
+                let closure = match heap.get_closure_ref(adr) {
+                    Some(closure) => closure,
+                    None => panic!("called something that isn't a closure"),
+                };
+                let (chunk_i, captured) = (closure.function, closure.captured.clone());
+                // the captured variables are released by the closure when it returns
+                for var in captured {
+                    heap.increase_rc(var);
+                    stack.push(var);
+                }
+                chunk_i
+            }
+        };
+        let mut machine = Machine {
+            stack,
+            heap,
+            chunks: self.chunks,
+            externals: self.externals,
+            call_frames: vec![],
+        };
+        machine.execute(chunk_i, frame_offset, true, out)
+    }
+}
+
+impl<'m> Machine<'m> {
+    /// Runs `entry` with its frame at `frame_offset`. A root chunk leaves
+    /// its variables on the stack, a called function leaves its return
+    /// value in place of its arguments.
+    fn execute(
+        &mut self,
+        entry: ChunkAdr,
+        frame_offset: StackAdr,
+        is_call: bool,
+        out: &mut dyn Write,
+    ) -> Result<(), RuntimeError> {
+        let base = if is_call {
+            frame_offset
+        } else {
+            self.stack.len()
+        };
         let mut ip: CodeAdr = 0;
-        let mut current_chunk: ChunkAdr = 0;
-        let mut frame_offset: StackAdr = 0;
+        let mut current_chunk: ChunkAdr = entry;
+        let mut frame_offset = frame_offset;
         loop {
             let chunk = &self.chunks[current_chunk as usize];
             #[cfg(feature = "debug_runtime")]
@@ -65,23 +220,26 @@ impl<'a> VM<'a> {
             ip = ip + 1;
             match chunk.get_op(ip - 1) {
                 OpCode::Return { width } => {
-                    if self.call_frames.len() == 0 {
-                        return;
+                    if self.call_frames.len() == 0 && !is_call {
+                        return Ok(());
                     }
 
-                    let CallFrame {
-                        parent_ip,
-                        parent_chunk,
-                        parent_frame_offset,
-                        args_width,
-                    } = self.call_frames.pop().unwrap();
-
                     self.stack.0.copy_within(
                         self.stack.1 - width as usize..self.stack.1,
                         frame_offset as usize,
                     );
                     self.stack.truncate(frame_offset + width as StackAdr);
 
+                    let CallFrame {
+                        parent_ip,
+                        parent_chunk,
+                        parent_frame_offset,
+                        args_width,
+                    } = match self.call_frames.pop() {
+                        Some(frame) => frame,
+                        None => return Ok(()),
+                    };
+
                     ip = parent_ip;
                     current_chunk = parent_chunk;
                     frame_offset = parent_frame_offset;
@@ -151,6 +309,11 @@ impl<'a> VM<'a> {
                     self.stack.truncate(i1 as StackAdr);
                     self.stack.push(value);
                 }
+                OpCode::EqualF64 => {
+                    let r: f64 = self.stack.pop();
+                    let l: f64 = self.stack.pop();
+                    self.stack.push(l == r);
+                }
                 OpCode::GreaterF64 => {
                     let r: f64 = self.stack.pop();
                     let l: f64 = self.stack.pop();
@@ -161,6 +324,16 @@ impl<'a> VM<'a> {
                     let l: f64 = self.stack.pop();
                     self.stack.push(l < r);
                 }
+                OpCode::GreaterEqualF64 => {
+                    let r: f64 = self.stack.pop();
+                    let l: f64 = self.stack.pop();
+                    self.stack.push(l >= r);
+                }
+                OpCode::LesserEqualF64 => {
+                    let r: f64 = self.stack.pop();
+                    let l: f64 = self.stack.pop();
+                    self.stack.push(l <= r);
+                }
                 OpCode::Variable { stack_i, width } => {
                     let top = self.stack.1;
                     let i = stack_i as usize + frame_offset as usize;
@@ -186,8 +359,6 @@ impl<'a> VM<'a> {
                     let adr: HeapAdr = self.stack.get(stack_i + frame_offset);
                     let bytes = self.heap.get_value(adr).unwrap();
                     bytes.copy_from_slice(&self.stack.0[self.stack.1 - bytes.len()..self.stack.1]);
-                    self.stack
-                        .truncate((self.stack.1 - bytes.len()) as StackAdr);
                 }
                 OpCode::JumpIfFalse { ip: jmp_ip } => {
                     let top = self.stack.len() - 1;
@@ -237,16 +408,36 @@ impl<'a> VM<'a> {
                     ip = 0;
                     frame_offset = self.stack.len() - args_width as StackAdr;
 
+                    // the captures have to be retained before the closure is
+                    // released, a temporary closure would free them otherwise
                     let captured = closure.captured.clone();
-                    self.heap.decrease_rc(closure_adr);
                     for var in captured.iter() {
                         self.heap.increase_rc(*var);
                     }
+                    self.heap.decrease_rc(closure_adr);
                 }
                 OpCode::CallExternal { args_width } => {
                     let func_i: ExternalAdr = self.stack.pop();
 
-                    self.externals.unwrap().dispatch(func_i, &mut self.stack);
+                    let externals = self.externals.unwrap();
+                    let mut callbacks = VmCallbacks {
+                        chunks: self.chunks,
+                        externals: self.externals,
+                    };
+                    let mut context = ExternalContext::new(
+                        self.stack,
+                        self.heap,
+                        self.externals,
+                        &mut callbacks,
+                        out,
+                    );
+                    let result = externals.dispatch(func_i, &mut context);
+                    if let Err(error) = result {
+                        let span = chunk.get_call_span(ip - 1).unwrap();
+                        let call =
+                            self.fail(base, (current_chunk, ip, frame_offset), String::new(), span);
+                        return Err(error.at_call(call));
+                    }
                 }
                 OpCode::IncreaseRC => {
                     let top = self.stack.len() - HeapAdr::width() as u16;
@@ -293,7 +484,123 @@ impl<'a> VM<'a> {
                     self.stack.0[self.stack.1..self.stack.1 + bytes.len()].copy_from_slice(bytes);
                     self.stack.1 += bytes.len();
                 }
+                OpCode::Assert { assertion_i } => {
+                    let holds: bool = self.stack.pop();
+                    if !holds {
+                        let assertion = chunk.get_assertion(assertion_i).clone();
+                        return Err(self.fail(
+                            base,
+                            (current_chunk, ip, frame_offset),
+                            assertion.message,
+                            assertion.span,
+                        ));
+                    }
+                }
+                OpCode::AssertEqF64 { assertion_i } => {
+                    let r: f64 = self.stack.pop();
+                    let l: f64 = self.stack.pop();
+                    if l != r {
+                        let assertion = chunk.get_assertion(assertion_i);
+                        let message =
+                            format!("{}\n  left: {:?}\n right: {:?}", assertion.message, l, r);
+                        return Err(self.fail(
+                            base,
+                            (current_chunk, ip, frame_offset),
+                            message,
+                            assertion.span,
+                        ));
+                    }
+                }
+                OpCode::AssertEqBool { assertion_i } => {
+                    let r: bool = self.stack.pop();
+                    let l: bool = self.stack.pop();
+                    if l != r {
+                        let assertion = chunk.get_assertion(assertion_i);
+                        let message =
+                            format!("{}\n  left: {:?}\n right: {:?}", assertion.message, l, r);
+                        return Err(self.fail(
+                            base,
+                            (current_chunk, ip, frame_offset),
+                            message,
+                            assertion.span,
+                        ));
+                    }
+                }
+                OpCode::AssertEqString { assertion_i } => {
+                    let r: HeapAdr = self.stack.pop();
+                    let l: HeapAdr = self.stack.pop();
+                    let r_string = self.heap.get_string_ref(r).unwrap().clone();
+                    let l_string = self.heap.get_string_ref(l).unwrap().clone();
+                    self.heap.decrease_rc(r);
+                    self.heap.decrease_rc(l);
+                    if l_string != r_string {
+                        let assertion = chunk.get_assertion(assertion_i);
+                        let message = format!(
+                            "{}\n  left: {:?}\n right: {:?}",
+                            assertion.message, l_string, r_string
+                        );
+                        return Err(self.fail(
+                            base,
+                            (current_chunk, ip, frame_offset),
+                            message,
+                            assertion.span,
+                        ));
+                    }
+                }
+                OpCode::AssertEq { width, assertion_i } => {
+                    let i0 = self.stack.1 - width as usize;
+                    let i1 = self.stack.1 - 2 * width as usize;
+                    let equal = self.stack.0[i0..self.stack.1] == self.stack.0[i1..i0];
+                    self.stack.truncate(i1 as StackAdr);
+                    if !equal {
+                        let assertion = chunk.get_assertion(assertion_i).clone();
+                        return Err(self.fail(
+                            base,
+                            (current_chunk, ip, frame_offset),
+                            assertion.message,
+                            assertion.span,
+                        ));
+                    }
+                }
             }
         }
     }
+
+    /// Drops the call frames and everything pushed since the run started at
+    /// `base`, releasing the objects in them, and returns the error to report.
+    /// The failing op is the one before `ip`.
+    fn fail(
+        &mut self,
+        base: StackAdr,
+        (chunk_i, ip, frame_offset): (ChunkAdr, CodeAdr, StackAdr),
+        message: String,
+        span: Span,
+    ) -> RuntimeError {
+        let chunks = self.chunks;
+        let mut trace = vec![];
+        let mut frames = vec![(chunk_i, ip, frame_offset)];
+        for frame in self.call_frames.drain(..).rev() {
+            let parent = &chunks[frame.parent_chunk as usize];
+            trace.extend(parent.get_call_span(frame.parent_ip - 1));
+            frames.push((
+                frame.parent_chunk,
+                frame.parent_ip,
+                frame.parent_frame_offset,
+            ));
+        }
+        for (chunk_i, ip, frame_offset) in frames {
+            for slot in chunks[chunk_i as usize].get_live_objects(ip - 1) {
+                if frame_offset + slot >= base {
+                    let adr: HeapAdr = self.stack.get(frame_offset + slot);
+                    self.heap.decrease_rc(adr);
+                }
+            }
+        }
+        self.stack.truncate(base);
+        RuntimeError {
+            message,
+            span,
+            trace,
+        }
+    }
 }
@@ -0,0 +1,61 @@
+use std::process::Command;
+
+fn eval(code: &str, args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_imglang"))
+        .args(["eval", "--allow=env", "-e", code])
+        .args(args)
+        .env("IMGLANG_TEST_VALUE", "value")
+        .env("IMGLANG_TEST_EMPTY", "")
+        .env_remove("IMGLANG_TEST_UNSET")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn script_arguments() {
+    assert_eq!(
+        eval(
+            "print argc(); print arg(0); print arg(1); print argFloat(1); print argFloat(0);",
+            &["one", "2.5"]
+        ),
+        "2.0\none\n2.5\n2.5\nNaN\n"
+    );
+    assert_eq!(eval("print argc();", &[]), "0.0\n");
+}
+
+#[test]
+fn script_arguments_out_of_range() {
+    assert_eq!(
+        eval(
+            "print arg(1); print arg(-1); print arg(0.5); print argFloat(1); print argFloat(0.5);",
+            &["1"]
+        ),
+        "\n\n\nNaN\nNaN\n"
+    );
+}
+
+#[test]
+fn environment_variables() {
+    assert_eq!(
+        eval(
+            "print env(\"IMGLANG_TEST_VALUE\"); print hasEnv(\"IMGLANG_TEST_VALUE\");",
+            &[]
+        ),
+        "value\ntrue\n"
+    );
+    // empty and unset variables only differ in hasEnv
+    assert_eq!(
+        eval(
+            "print env(\"IMGLANG_TEST_EMPTY\"); print hasEnv(\"IMGLANG_TEST_EMPTY\");
+            print env(\"IMGLANG_TEST_UNSET\"); print hasEnv(\"IMGLANG_TEST_UNSET\");",
+            &[]
+        ),
+        "\ntrue\n\nfalse\n"
+    );
+}
@@ -0,0 +1,155 @@
+use imglang::*;
+use std::env;
+use std::fs;
+
+fn run(granted: &[Capability], input: &str) -> Result<String, String> {
+    let externals = Externals::with_capabilities(granted);
+    let mut lexer = Lexer::new(&input.to_string()).unwrap();
+    let mut ast = parse(&mut lexer).unwrap();
+    if let Err(error) = TypeChecker::annotate_types(&mut ast, Some(&externals)) {
+        let messages: Vec<String> = type_diagnostics(error)
+            .into_iter()
+            .map(|d| format!("{} {}", d.code.code(), d.message))
+            .collect();
+        return Err(messages.join("\n"));
+    }
+    let vm = execute_vm(&ast, Some(&externals));
+    assert_eq!(execute_ast(&ast, Some(&externals)), vm);
+    match vm.error {
+        Some(error) => Err(error.message),
+        None => Ok(vm.output),
+    }
+}
+
+#[test]
+fn capability_names() {
+    for capability in CAPABILITIES.iter() {
+        assert_eq!(Capability::from_name(capability.name()), Some(*capability));
+    }
+    assert_eq!(Capability::from_name("network"), None);
+}
+
+#[test]
+fn granted_capabilities() {
+    let output = run(
+        &[Capability::Math, Capability::String, Capability::Clock],
+        "import string;
+        print math.floor(math.PI * 100);
+        print upper(concat(\"ab\", \"c\"));
+        print len(repeat(\"ab\", 3));
+        print contains(\"imglang\", \"lang\");
+        print clock.elapsed() >= 0;",
+    );
+    assert_eq!(output.unwrap(), "314.0\nABC\n6.0\ntrue\ntrue\n");
+}
+
+#[test]
+fn repeat_is_bounded() {
+    let string = [Capability::String];
+    assert_eq!(
+        run(
+            &string,
+            "print string.repeat(\"ab\", 10000000000000000000);"
+        ),
+        Err(format!(
+            "repeating a string 10000000000000000000 times makes it longer than {} bytes",
+            MAX_REPEAT_LEN
+        ))
+    );
+    assert!(run(&string, "print string.repeat(\"ab\", 1000000000000);").is_err());
+    assert_eq!(
+        run(&string, "print string.repeat(\"ab\", -1);"),
+        Err("cannot repeat a string -1 times".to_string())
+    );
+    assert_eq!(
+        run(
+            &string,
+            "print string.len(string.repeat(\"\", 1000000000000));"
+        ),
+        Ok("0.0\n".to_string())
+    );
+}
+
+#[test]
+fn default_capabilities_have_no_side_effects() {
+    assert_eq!(DEFAULT_CAPABILITIES, [Capability::Math, Capability::String]);
+    assert_eq!(
+        run(
+            &DEFAULT_CAPABILITIES,
+            "print math.sqrt(string.len(\"four\"));"
+        ),
+        Ok("2.0\n".to_string())
+    );
+    for (input, group) in [
+        ("print fs.read(\"in\");", "fs-read"),
+        ("fs.write(\"out\", \"data\");", "fs-write"),
+        ("print clock.now();", "clock"),
+        ("print env(\"HOME\");", "env"),
+    ]
+    .iter()
+    {
+        let error = run(&DEFAULT_CAPABILITIES, input).unwrap_err();
+        assert!(
+            error.contains(&format!("needs the {} capability", group)),
+            "{}",
+            error
+        );
+    }
+}
+
+#[test]
+fn denied_capabilities_are_type_errors() {
+    assert_eq!(
+        run(&[Capability::Math], "print string.len(\"a\");"),
+        Err(
+            "E0020 string.len needs the string capability, which the script isn't granted"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        run(
+            &[Capability::FsRead],
+            "import fs; write(\"out\", \"data\");"
+        ),
+        Err(
+            "E0020 fs.write needs the fs-write capability, which the script isn't granted"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        run(&[], "print env(\"HOME\");"),
+        Err("E0020 env needs the env capability, which the script isn't granted".to_string())
+    );
+    // names that are in no group stay undefined
+    assert_eq!(
+        run(&[], "print fs.delete(\"out\");"),
+        Err("E0004 fs.delete is not defined".to_string())
+    );
+}
+
+#[test]
+fn filesystem_capabilities() {
+    let path = env::temp_dir().join(format!("imglang-capabilities-{}", std::process::id()));
+    let path = path.to_str().unwrap();
+    let script = format!(
+        "import fs;
+        print exists(\"{0}\");
+        write(\"{0}\", \"one\");
+        append(\"{0}\", \" two\");
+        print read(\"{0}\");
+        remove(\"{0}\");
+        print exists(\"{0}\");",
+        path
+    );
+    let all = [Capability::FsRead, Capability::FsWrite];
+    assert_eq!(run(&all, &script).unwrap(), "false\none two\nfalse\n");
+    assert!(fs::metadata(path).is_err());
+
+    let error = run(
+        &[Capability::FsRead],
+        &format!("print fs.read(\"{}\");", path),
+    );
+    assert!(error
+        .unwrap_err()
+        .starts_with(&format!("could not read {}", path)));
+}
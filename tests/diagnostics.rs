@@ -0,0 +1,204 @@
+use imglang::*;
+
+fn warnings(input: &'static str) -> Vec<Diagnostic> {
+    let source = input.to_string();
+    let mut lexer = Lexer::new(&source).unwrap();
+    let mut ast = parse(&mut lexer).unwrap();
+    let mut type_checker = TypeChecker::new(None);
+    type_checker.annotate(&mut ast).unwrap();
+    type_checker
+        .take_warnings()
+        .into_iter()
+        .flat_map(type_diagnostics)
+        .collect()
+}
+
+fn render_type_errors(input: &'static str) -> String {
+    let source = input.to_string();
+    let mut lexer = Lexer::new(&source).unwrap();
+    let mut ast = parse(&mut lexer).unwrap();
+    let error = TypeChecker::annotate_types(&mut ast, None).unwrap_err();
+
+    let mut output: Vec<u8> = vec![];
+    render_diagnostics("test", &source, &type_diagnostics(error), &mut output);
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn line_and_column() {
+    let lines = SourceLines::new("var a = 1;\n\nprint a;");
+    assert_eq!(lines.location(0), (0, 0));
+    assert_eq!(lines.location(4), (0, 4));
+    assert_eq!(lines.location(11), (1, 0));
+    assert_eq!(lines.location(18), (2, 6));
+}
+
+#[test]
+fn span_underlined_with_carets() {
+    let output = render_type_errors("var a = 1;\nprint a + true;");
+    assert_eq!(
+        output,
+        "error[E0008]: type of left operand (float) doesn't match type of right (bool)
+ --> test:2:7
+  |
+2 | print a + true;
+  |       ^^^^^^^^
+  |       - this is float
+  |           ---- this is bool
+
+"
+    );
+}
+
+#[test]
+fn declared_here_label() {
+    let output = render_type_errors("var a = 1;\n\n\na = false;");
+    assert_eq!(
+        output,
+        "error[E0008]: cannot assign value of type bool to variable with type float
+ --> test:4:1
+  |
+1 | var a = 1;
+  | ---------- a declared here
+ ...
+4 | a = false;
+  | ^^^^^^^^^
+
+"
+    );
+}
+
+#[test]
+fn parser_error_at_token() {
+    let source = "var a = ;".to_string();
+    let mut lexer = Lexer::new(&source).unwrap();
+    let error = parse(&mut lexer).unwrap_err();
+    let diagnostics = parser_diagnostics(error);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].span, Span::new(8, 9));
+}
+
+#[test]
+fn json_diagnostic() {
+    let source = "var a = 1;\nprint a + \"å\";".to_string();
+    let mut lexer = Lexer::new(&source).unwrap();
+    let mut ast = parse(&mut lexer).unwrap();
+    let error = TypeChecker::annotate_types(&mut ast, None).unwrap_err();
+
+    let mut output: Vec<u8> = vec![];
+    render_diagnostics_json("te\"st", &source, &type_diagnostics(error), &mut output);
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        r#"{"severity":"error","code":"E0008","message":"type of left operand (float) doesn't match type of right (str)","file":"te\"st","start":17,"end":25,"line":2,"column":7,"end_line":2,"end_column":14,"labels":[{"message":"this is float","start":17,"end":18,"line":2,"column":7,"end_line":2,"end_column":8},{"message":"this is str","start":21,"end":25,"line":2,"column":11,"end_line":2,"end_column":14}],"help":null}
+"#
+    );
+}
+
+#[test]
+fn types_displayed_as_source() {
+    let closure = AstType::Closure(vec![AstType::Float], Box::new(AstType::Float));
+    assert_eq!(closure.to_string(), "<float;float>*");
+    let function = AstType::Function(vec![AstType::Bool, AstType::String], Box::new(AstType::Nil));
+    assert_eq!(function.to_string(), "<bool, str>");
+    let variant = AstType::EnumVariant {
+        enum_type: "State".to_string(),
+        max_size: 0,
+    };
+    assert_eq!(variant.to_string(), "State");
+}
+
+#[test]
+fn error_codes_are_unique() {
+    let errors = ERROR_CODES.iter().filter(|code| !code.is_warning());
+    let warnings = ERROR_CODES.iter().filter(|code| code.is_warning());
+    for (i, code) in errors.enumerate() {
+        assert_eq!(code.code(), format!("E{:04}", i + 1));
+    }
+    for (i, code) in warnings.enumerate() {
+        assert_eq!(code.code(), format!("W{:04}", i + 1));
+    }
+    for code in ERROR_CODES.iter() {
+        assert_eq!(ErrorCode::from_code(code.code()), Some(*code));
+        assert!(!code.explanation().is_empty());
+    }
+}
+
+#[test]
+fn did_you_mean() {
+    let output = render_type_errors("var length = 1;\nprint lenght;");
+    assert_eq!(
+        output,
+        "error[E0004]: lenght is not defined
+ --> test:2:7
+  |
+2 | print lenght;
+  |       ^^^^^^
+  = help: did you mean `length`?
+
+"
+    );
+}
+
+#[test]
+fn did_you_mean_enum_variant() {
+    let output = render_type_errors("enum State = first | second;\nvar s = frist();");
+    assert!(output.contains("did you mean `first`?"));
+    let output = render_type_errors("var abc = 1;\nprint xyz;");
+    assert!(!output.contains("did you mean"));
+}
+
+#[test]
+fn captured_global() {
+    let output = render_type_errors(
+        "fun half(x float) float {\n  return x / 2;\n}\nvar f = fun[half]() float half(3);",
+    );
+    assert!(output.starts_with("error[E0023]: half is global and can be used without capturing it"));
+}
+
+#[test]
+fn unused_variables_and_parameters() {
+    let found = warnings("fun f(a float, b float, _c float) float {\n  var d = 1;\n  return b;\n}\nprint f(1, 2, 3);");
+    let messages: Vec<&str> = found.iter().map(|d| d.message.as_str()).collect();
+    assert_eq!(messages, vec!["unused variable a", "unused variable d"]);
+    assert_eq!(found[0].span, Span::new(6, 13));
+    assert!(found.iter().all(|d| d.code == ErrorCode::UnusedVariable));
+}
+
+#[test]
+fn unreachable_after_return() {
+    let found = warnings("fun f() float {\n  return 1;\n  print 2;\n  print 3;\n}\nprint f();");
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].code, ErrorCode::UnreachableCode);
+    assert_eq!(found[0].span, Span::new(30, 38));
+}
+
+#[test]
+fn shadowed_variable() {
+    let found = warnings("var x = 1;\n{\n  var x = 2;\n  print x;\n}\nprint x;");
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].code, ErrorCode::ShadowedVariable);
+    assert_eq!(found[0].severity(), "warning");
+}
+
+#[test]
+fn unused_declarations() {
+    let found = warnings("fun helper() {}\nfun used() {}\nenum E = a | b;\nenum F = c;\nused();\nvar e = a();\nprint e == b();");
+    let messages: Vec<&str> = found.iter().map(|d| d.message.as_str()).collect();
+    assert_eq!(
+        messages,
+        vec!["function helper is never used", "enum F is never used"]
+    );
+}
+
+#[test]
+fn errors_in_independent_expressions() {
+    let source = "fun add(a float, b float) float {\n  return a + b;\n}\nvar x = add(1, true) + lenght;\nprint x * 2;\nif (x) {}\nvar y = add(1, 2) + \"s\";\nprint y;\nfun g(s Foo) { print s; }\ng(1);".to_string();
+    let mut lexer = Lexer::new(&source).unwrap();
+    let mut ast = parse(&mut lexer).unwrap();
+    let error = TypeChecker::annotate_types(&mut ast, None).unwrap_err();
+    let codes: Vec<&str> = type_diagnostics(error)
+        .iter()
+        .map(|d| d.code.code())
+        .collect();
+    assert_eq!(codes, vec!["E0014", "E0004", "E0008", "E0004"]);
+}
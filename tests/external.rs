@@ -1,6 +1,10 @@
 use imglang::*;
+use std::cell::RefCell;
+use std::rc::Rc;
 
-fn test_script(externals: Externals, input: &'static str, expected: &'static str) {
+/// Returns the number of objects left on the heap, variables at the root of
+/// the script are never freed.
+fn test_script(externals: Externals, input: &'static str, expected: &'static str) -> HeapAdr {
     let mut lexer = Lexer::new(&input.to_string()).unwrap();
     let mut ast = parse(&mut lexer).unwrap();
 
@@ -10,11 +14,173 @@ fn test_script(externals: Externals, input: &'static str, expected: &'static str
     let mut output: Vec<u8> = vec![];
 
     let mut vm = VM::new(chunks, Some(&externals));
-    vm.run(&mut output);
+    vm.run(&mut output).unwrap();
 
     let output = String::from_utf8(output).unwrap();
 
     assert_eq!(expected.to_string(), output);
+    vm.heap_ptr().count_objects()
+}
+
+/// Runs `input` on the vm and the interpreter, which have to agree.
+fn run(externals: &Externals, input: &str) -> Execution {
+    let mut lexer = Lexer::new(&input.to_string()).unwrap();
+    let mut ast = parse(&mut lexer).unwrap();
+    TypeChecker::annotate_types(&mut ast, Some(externals)).unwrap();
+    let execution = execute_vm(&ast, Some(externals));
+    assert_eq!(execute_ast(&ast, Some(externals)), execution);
+    execution
+}
+
+/// Messages of the type errors in `input`, prefixed with their codes.
+fn type_error(externals: &Externals, input: &str) -> String {
+    let mut lexer = Lexer::new(&input.to_string()).unwrap();
+    let mut ast = parse(&mut lexer).unwrap();
+    let error = TypeChecker::annotate_types(&mut ast, Some(externals)).unwrap_err();
+    type_diagnostics(error)
+        .into_iter()
+        .map(|d| format!("{}: {}", d.code.code(), d.message))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Externals shared by the tests below, the widths of dropped images are
+/// pushed to `dropped`.
+fn host_externals(dropped: Rc<RefCell<Vec<f64>>>) -> Externals {
+    let mut externals = Externals::new();
+    externals.register("root", |x: f64| -> Result<f64, ExternalError> {
+        if x < 0.0 {
+            return Err(format!("cannot take the root of {}", x).into());
+        }
+        Ok(x.sqrt())
+    });
+
+    externals.add_foreign_type_with_drop("Image", move |image: Image| {
+        dropped.borrow_mut().push(image.width);
+    });
+    let image_t = externals.lookup_foreign_type(&"Image".to_string()).unwrap();
+    externals.add_function(
+        "image".to_string(),
+        ExternalFunction {
+            args_t: vec![AstType::Float, AstType::Float],
+            ret_t: image_t.clone(),
+            dispatch: Box::new(|context: &mut ExternalContext| {
+                let height: f64 = context.stack.pop();
+                let width: f64 = context.stack.pop();
+                context.push_foreign(Image { width, height });
+                Ok(())
+            }),
+        },
+    );
+    externals.add_function(
+        "width".to_string(),
+        ExternalFunction {
+            args_t: vec![image_t.clone()],
+            ret_t: AstType::Float,
+            dispatch: Box::new(|context: &mut ExternalContext| {
+                let adr: HeapAdr = context.stack.pop();
+                let width = context.foreign::<Image>(adr).unwrap().width;
+                context.release(adr);
+                context.stack.push(width);
+                Ok(())
+            }),
+        },
+    );
+    externals.add_function(
+        "grow".to_string(),
+        ExternalFunction {
+            args_t: vec![image_t.clone()],
+            ret_t: image_t,
+            dispatch: Box::new(|context: &mut ExternalContext| {
+                // the argument's reference is handed back to the script
+                let adr: HeapAdr = context.stack.pop();
+                let image = context.foreign_mut::<Image>(adr).unwrap();
+                image.width *= 2.0;
+                image.height *= 2.0;
+                context.stack.push(adr);
+                Ok(())
+            }),
+        },
+    );
+
+    externals.add_function(
+        "sum_map".to_string(),
+        ExternalFunction {
+            args_t: vec![
+                AstType::Float,
+                AstType::Closure(vec![AstType::Float], Box::new(AstType::Float)),
+            ],
+            ret_t: AstType::Float,
+            dispatch: Box::new(|context: &mut ExternalContext| {
+                let adr: HeapAdr = context.stack.pop();
+                let n: f64 = context.stack.pop();
+                let mut sum = 0.0;
+                for i in 0..n as usize {
+                    let value: f64 = context.call_closure(adr, (i as f64,))?;
+                    sum += value;
+                }
+                context.release(adr);
+                context.stack.push(sum);
+                Ok(())
+            }),
+        },
+    );
+    externals.add_function(
+        "twice".to_string(),
+        ExternalFunction {
+            args_t: vec![
+                AstType::Function(vec![AstType::String], Box::new(AstType::String)),
+                AstType::String,
+            ],
+            ret_t: AstType::String,
+            dispatch: Box::new(|context: &mut ExternalContext| {
+                let s = context.pop_string();
+                let function: ChunkAdr = context.stack.pop();
+                let once: String = context.call_function(function, (s,))?;
+                let twice: String = context.call_function(function, (once,))?;
+                context.push_string(twice);
+                Ok(())
+            }),
+        },
+    );
+    externals.add_function(
+        "apply".to_string(),
+        ExternalFunction {
+            args_t: vec![
+                AstType::Float,
+                AstType::Closure(vec![AstType::Float], Box::new(AstType::Float)),
+            ],
+            ret_t: AstType::Float,
+            dispatch: Box::new(|context: &mut ExternalContext| {
+                let adr: HeapAdr = context.stack.pop();
+                let x: f64 = context.stack.pop();
+                let result = context.call_closure(adr, (x,));
+                context.release(adr);
+                context.stack.push::<f64>(result?);
+                Ok(())
+            }),
+        },
+    );
+    externals
+        .module("math")
+        .register("sqrt", f64::sqrt)
+        .register("max", f64::max)
+        .register("floor", f64::floor)
+        .add_constant("PI", std::f64::consts::PI);
+    externals
+        .module("img")
+        .register("width", || -> f64 { 640.0 })
+        .register("max", || -> f64 { 255.0 });
+    externals.add_constant("WIDTH", 640.0);
+    externals.add_constant("DEBUG", false);
+    externals.add_constant("TITLE", "canvas");
+    externals.add_constant("START", Direction::Right);
+    externals
+}
+
+struct Image {
+    width: f64,
+    height: f64,
 }
 
 #[test]
@@ -25,9 +191,10 @@ fn external_function_call() {
         ExternalFunction {
             args_t: vec![],
             ret_t: AstType::Float,
-            dispatch: |stack: &mut Stack| {
-                stack.push(123.345);
-            },
+            dispatch: Box::new(|context: &mut ExternalContext| {
+                context.stack.push(123.345);
+                Ok(())
+            }),
         },
     );
 
@@ -42,10 +209,11 @@ fn external_function_with_args() {
         ExternalFunction {
             args_t: vec![AstType::Float],
             ret_t: AstType::Float,
-            dispatch: |stack: &mut Stack| {
-                let arg: f64 = stack.pop();
-                stack.push(arg);
-            },
+            dispatch: Box::new(|context: &mut ExternalContext| {
+                let arg: f64 = context.stack.pop();
+                context.stack.push(arg);
+                Ok(())
+            }),
         },
     );
 
@@ -58,3 +226,616 @@ fn external_function_with_args() {
         "12.34\n56.78\n",
     );
 }
+
+#[test]
+fn external_function_with_strings() {
+    let mut externals = Externals::new();
+    externals.add_function(
+        "externalFunction".to_string(),
+        ExternalFunction {
+            args_t: vec![AstType::String],
+            ret_t: AstType::String,
+            dispatch: Box::new(|context: &mut ExternalContext| {
+                let adr: HeapAdr = context.stack.pop();
+                let string = format!("{}!", context.heap.get_string_ref(adr).unwrap());
+                context.heap.decrease_rc(adr);
+                let adr = context.heap.add_object(Obj::String(string));
+                context.stack.push(adr);
+                Ok(())
+            }),
+        },
+    );
+
+    test_script(
+        externals,
+        "
+            var a = \"string\";
+            print externalFunction(a);
+            print externalFunction(\"literal\");
+        ",
+        "string!\nliteral!\n",
+    );
+}
+
+#[test]
+fn external_function_with_state() {
+    let mut externals = Externals::new();
+    let mut calls = 0.0;
+    externals.add_function(
+        "counter".to_string(),
+        ExternalFunction {
+            args_t: vec![],
+            ret_t: AstType::Float,
+            dispatch: Box::new(move |context: &mut ExternalContext| {
+                calls += 1.0;
+                context.stack.push(calls);
+                Ok(())
+            }),
+        },
+    );
+
+    test_script(
+        externals,
+        "
+            counter();
+            print counter();
+            print counter();
+        ",
+        "2.0\n3.0\n",
+    );
+}
+
+#[test]
+fn external_function_shares_state_with_host() {
+    let log = Rc::new(RefCell::new(vec![]));
+    let mut externals = Externals::new();
+    let script_log = log.clone();
+    externals.add_function(
+        "record".to_string(),
+        ExternalFunction {
+            args_t: vec![AstType::Float],
+            ret_t: AstType::Nil,
+            dispatch: Box::new(move |context: &mut ExternalContext| {
+                let value: f64 = context.stack.pop();
+                script_log.borrow_mut().push(value);
+                Ok(())
+            }),
+        },
+    );
+
+    test_script(externals, "record(1); record(2.5);", "");
+    assert_eq!(*log.borrow(), vec![1.0, 2.5]);
+}
+
+#[test]
+fn external_function_with_string_helpers() {
+    let mut externals = Externals::new();
+    externals.add_function(
+        "words".to_string(),
+        ExternalFunction {
+            args_t: vec![AstType::String],
+            ret_t: AstType::Float,
+            dispatch: Box::new(|context: &mut ExternalContext| {
+                let text = context.pop_string();
+                context.stack.push(text.split_whitespace().count() as f64);
+                Ok(())
+            }),
+        },
+    );
+    externals.add_function(
+        "word".to_string(),
+        ExternalFunction {
+            args_t: vec![AstType::String, AstType::Float],
+            ret_t: AstType::String,
+            dispatch: Box::new(|context: &mut ExternalContext| {
+                let i: f64 = context.stack.pop();
+                let text = context.pop_string();
+                let word = text.split_whitespace().nth(i as usize).unwrap_or("");
+                context.push_string(word.to_string());
+                Ok(())
+            }),
+        },
+    );
+
+    let leaked = test_script(
+        externals,
+        "
+            {
+                var text = \"a b  c\";
+                print words(text);
+                print word(text, 1);
+                print word(word(text, 2), 0);
+            }
+        ",
+        "3.0\nb\nc\n",
+    );
+    assert_eq!(leaked, 0);
+}
+
+#[test]
+fn external_function_keeping_strings() {
+    let kept: Rc<RefCell<Option<HeapAdr>>> = Rc::new(RefCell::new(None));
+    let mut externals = Externals::new();
+    let keep_slot = kept.clone();
+    externals.add_function(
+        "keep".to_string(),
+        ExternalFunction {
+            args_t: vec![AstType::String],
+            ret_t: AstType::Nil,
+            dispatch: Box::new(move |context: &mut ExternalContext| {
+                // the reference of the argument is kept instead of released
+                let adr: HeapAdr = context.stack.pop();
+                if let Some(old) = keep_slot.borrow_mut().replace(adr) {
+                    context.release(old);
+                }
+                Ok(())
+            }),
+        },
+    );
+    let kept_slot = kept.clone();
+    externals.add_function(
+        "kept".to_string(),
+        ExternalFunction {
+            args_t: vec![],
+            ret_t: AstType::String,
+            dispatch: Box::new(move |context: &mut ExternalContext| {
+                let adr = kept_slot.borrow().unwrap();
+                assert!(context.string(adr).is_some());
+                context.retain(adr);
+                context.stack.push(adr);
+                Ok(())
+            }),
+        },
+    );
+    let forget_slot = kept.clone();
+    externals.add_function(
+        "forget".to_string(),
+        ExternalFunction {
+            args_t: vec![],
+            ret_t: AstType::Nil,
+            dispatch: Box::new(move |context: &mut ExternalContext| {
+                let adr = forget_slot.borrow_mut().take().unwrap();
+                context.release(adr);
+                Ok(())
+            }),
+        },
+    );
+
+    let leaked = test_script(
+        externals,
+        "
+            {
+                var a = \"first\";
+                keep(a);
+            }
+            print kept();
+            keep(\"second\");
+            print kept();
+            forget();
+        ",
+        "first\nsecond\n",
+    );
+    assert_eq!(leaked, 0);
+}
+
+#[test]
+fn registered_function() {
+    let mut externals = Externals::new();
+    externals.register("between", |x: f64, low: f64, high: f64| -> bool {
+        low <= x && x <= high
+    });
+    externals.register("greet", |name: String, loud: bool| -> String {
+        let greeting = format!("hello {}", name);
+        if loud {
+            greeting.to_uppercase()
+        } else {
+            greeting
+        }
+    });
+    assert_eq!(
+        externals.lookup_type(&"between".to_string()),
+        Some(AstType::ExternalFunction(
+            vec![AstType::Float, AstType::Float, AstType::Float],
+            Box::new(AstType::Bool)
+        ))
+    );
+
+    test_script(
+        externals,
+        "
+            print between(2, 1, 3);
+            print between(4, 1, 3);
+            print greet(\"world\", false);
+            print greet(\"world\", true);
+        ",
+        "true\nfalse\nhello world\nHELLO WORLD\n",
+    );
+}
+
+#[test]
+fn registered_function_without_return_value() {
+    let total = Rc::new(RefCell::new(0.0));
+    let mut externals = Externals::new();
+    let script_total = total.clone();
+    externals.register("add", move |value: f64| {
+        *script_total.borrow_mut() += value;
+    });
+
+    test_script(externals, "add(1); add(2.5);", "");
+    assert_eq!(*total.borrow(), 3.5);
+}
+
+#[derive(Debug, PartialEq)]
+enum Direction {
+    Left,
+    Right,
+}
+
+external_enum!(Direction { Left, Right });
+
+#[test]
+fn registered_function_with_enums() {
+    let mut externals = Externals::new();
+    externals.register("flip", |direction: Direction| -> Direction {
+        match direction {
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+        }
+    });
+
+    test_script(
+        externals,
+        "
+            enum Direction = left | right;
+            print flip(left()) == right();
+            print flip(flip(left())) == left();
+        ",
+        "true\ntrue\n",
+    );
+
+    let mut externals = Externals::new();
+    externals.register("show", |direction: Direction| -> String {
+        format!("{:?}", direction)
+    });
+    for input in [
+        "enum Direction = Left | Right | Up; print show(Up());",
+        "enum Direction = Right | Left; print show(Left());",
+        "enum Direction = Left; print show(Left());",
+        "enum Direction = Left | Right float;",
+    ]
+    .iter()
+    {
+        assert_eq!(
+            type_error(&externals, input),
+            "E0021: enum Direction has to declare the host's variants Left | Right",
+            "{}",
+            input
+        );
+    }
+
+    assert_eq!(
+        type_error(
+            &host_externals(Rc::default()),
+            "enum Direction = right | left;"
+        ),
+        "E0021: enum Direction has to declare the host's variants Left | Right"
+    );
+}
+
+#[test]
+fn failing_external_function() {
+    let source = "fun side(area float) float {
+  return root(area);
+}
+print side(4);
+print side(-1);
+print \"not reached\";
+";
+    let execution = run(&host_externals(Rc::default()), source);
+    assert_eq!(execution.output, "2.0\n");
+    let error = execution.error.unwrap();
+    assert_eq!(error.message, "cannot take the root of -1");
+    assert_eq!(&source[error.span.start..error.span.end], "root(area)");
+    let trace: Vec<&str> = error
+        .trace
+        .iter()
+        .map(|span| &source[span.start..span.end])
+        .collect();
+    assert_eq!(trace, vec!["side(-1)"]);
+
+    let mut rendered = vec![];
+    render_runtime_error("test", source, &error, &mut rendered);
+    assert_eq!(
+        String::from_utf8(rendered).unwrap(),
+        "error: cannot take the root of -1
+ --> test:2:10
+  |
+2 |   return root(area);
+  |          ^^^^^^^^^^
+  = note: called from test:5:7
+
+"
+    );
+}
+
+#[test]
+fn foreign_objects() {
+    let input = "
+        fun area(image Image) float {
+            return width(image) * width(image);
+        }
+
+        {
+            var a = image(2, 3);
+            var b = a;
+            print area(b);
+            {
+                var c = image(4, 5);
+            }
+            print width(grow(a));
+        }
+    ";
+    let dropped = Rc::new(RefCell::new(vec![]));
+    let leaked = test_script(host_externals(dropped.clone()), input, "4.0\n4.0\n");
+    assert_eq!(leaked, 0);
+    assert_eq!(*dropped.borrow(), vec![4.0, 4.0]);
+    assert_eq!(run(&host_externals(dropped), input).output, "4.0\n4.0\n");
+
+    assert_eq!(
+        type_error(&host_externals(Rc::default()), "print width(2);"),
+        "E0014: arguments doesn't match, requires (Image), got (float)"
+    );
+}
+
+fn run_program<'a>(externals: Option<&'a Externals>, input: &str) -> VM<'a> {
+    let mut lexer = Lexer::new(&input.to_string()).unwrap();
+    let mut ast = parse(&mut lexer).unwrap();
+    TypeChecker::annotate_types(&mut ast, externals).unwrap();
+    let chunks = Compiler::compile(&ast, externals);
+    let mut vm = VM::new(chunks, externals);
+    vm.run(&mut vec![]).unwrap();
+    vm
+}
+
+#[test]
+fn calling_script_functions() {
+    let mut vm = run_program(
+        None,
+        "fun shade(x float, y float) float {
+            return x * 10 + y;
+        }
+        fun greet(name str) str {
+            print name;
+            return \"hello\";
+        }",
+    );
+    let mut output: Vec<u8> = vec![];
+    let mut pixels = vec![];
+    for y in 0..2 {
+        for x in 0..3 {
+            let shade: f64 = vm.call("shade", (x as f64, y as f64), &mut output).unwrap();
+            pixels.push(shade);
+        }
+    }
+    assert_eq!(pixels, vec![0.0, 10.0, 20.0, 1.0, 11.0, 21.0]);
+
+    let greeting: String = vm
+        .call("greet", ("world".to_string(),), &mut output)
+        .unwrap();
+    assert_eq!(greeting, "hello");
+    assert_eq!(String::from_utf8(output).unwrap(), "world\n");
+    assert_eq!(vm.heap_ptr().count_objects(), 0);
+
+    let missing: Result<f64, CallError> = vm.call("blur", (1.0,), &mut vec![]);
+    assert_eq!(missing, Err(CallError::Undefined("blur".to_string())));
+    let mismatched: Result<f64, CallError> = vm.call("shade", (1.0, true), &mut vec![]);
+    assert_eq!(
+        mismatched,
+        Err(CallError::MismatchedTypes(AstType::Function(
+            vec![AstType::Float, AstType::Float],
+            Box::new(AstType::Float)
+        )))
+    );
+    let mismatched: Result<String, CallError> = vm.call("shade", (1.0, 2.0), &mut vec![]);
+    assert!(mismatched.is_err());
+}
+
+#[test]
+fn failing_script_function() {
+    let mut vm = run_program(
+        None,
+        "fun check(x float) {
+            var label = \"checked\";
+            assert x > 0;
+        }",
+    );
+    let mut output: Vec<u8> = vec![];
+    assert_eq!(vm.call::<_, ()>("check", (1.0,), &mut output), Ok(()));
+    match vm.call::<_, ()>("check", (-1.0,), &mut output) {
+        Err(CallError::Runtime(error)) => assert!(error.message.contains("x > 0")),
+        result => panic!("{:?}", result),
+    }
+    assert_eq!(vm.heap_ptr().count_objects(), 0);
+    // the vm can still be used after an error
+    assert_eq!(vm.call::<_, ()>("check", (2.0,), &mut output), Ok(()));
+}
+
+#[test]
+fn externals_calling_back_into_the_script() {
+    let input = "fun echo(s str) str {
+            print s;
+            return \"again\";
+        }
+        {
+            var offset = 1;
+            print sum_map(3, fun[offset](x float) float {
+                return x * x + offset;
+            });
+            print twice(echo, \"hey\");
+        }";
+    let expected = "8.0\nhey\nagain\nagain\n";
+    assert_eq!(
+        test_script(host_externals(Rc::default()), input, expected),
+        0
+    );
+    assert_eq!(run(&host_externals(Rc::default()), input).output, expected);
+
+    let input = "{
+            var k = 10;
+            print apply(2, fun[k](x float) float {
+                return sum_map(x, fun[k](y float) float {
+                    return y + k;
+                });
+            });
+        }";
+    assert_eq!(
+        test_script(host_externals(Rc::default()), input, "21.0\n"),
+        0
+    );
+    assert_eq!(run(&host_externals(Rc::default()), input).output, "21.0\n");
+}
+
+#[test]
+fn external_called_from_its_own_callback() {
+    let source = "{
+        var k = 1;
+        print apply(2, fun[k](x float) float {
+            return apply(x, fun[k](y float) float {
+                return y + k;
+            });
+        });
+    }";
+    let error = run(&host_externals(Rc::default()), source).error.unwrap();
+    assert_eq!(
+        error.message,
+        "external called again from a callback while it's running"
+    );
+    let calls: Vec<&str> = std::iter::once(&error.span)
+        .chain(error.trace.iter())
+        .map(|span| &source[span.start..span.start + 8])
+        .collect();
+    assert_eq!(calls, vec!["apply(x,", "apply(2,"]);
+}
+
+#[test]
+fn failing_callback() {
+    let source = "{
+        var limit = 1;
+        print sum_map(3, fun[limit](x float) float {
+            assert x < limit;
+            return x;
+        });
+    }";
+    let error = run(&host_externals(Rc::default()), source).error.unwrap();
+    assert!(error.message.contains("x < limit"));
+    // the error is where the callback failed, called from the external
+    assert_eq!(
+        &source[error.span.start..error.span.end],
+        "assert x < limit;"
+    );
+    let trace: Vec<&str> = error
+        .trace
+        .iter()
+        .map(|span| &source[span.start..span.start + 10])
+        .collect();
+    assert_eq!(trace, vec!["sum_map(3,"]);
+}
+
+#[test]
+fn external_modules() {
+    let input = "print math.sqrt(16);
+        print img.width();
+        fun sqrt(x float) float {
+            return x;
+        }
+        print sqrt(16);";
+    test_script(host_externals(Rc::default()), input, "4.0\n640.0\n16.0\n");
+
+    let input = "import math;
+        print sqrt(16) + math.max(1, 2);
+        import img;
+        // names of the script and unqualified externals come first
+        print width(image(2, 3));
+        print img.width();";
+    test_script(host_externals(Rc::default()), input, "6.0\n2.0\n640.0\n");
+    assert_eq!(
+        run(&host_externals(Rc::default()), input).output,
+        "6.0\n2.0\n640.0\n"
+    );
+
+    let externals = host_externals(Rc::default());
+    assert_eq!(
+        type_error(&externals, "print math.root(2);"),
+        "E0004: math.root is not defined"
+    );
+    assert_eq!(
+        type_error(&externals, "print sqrt(2);"),
+        "E0004: sqrt is not defined"
+    );
+    assert_eq!(
+        type_error(&externals, "import color;"),
+        "E0004: module color is not defined"
+    );
+    assert_eq!(
+        type_error(&externals, "{ import math; }"),
+        "E0006: imports are only allowed at the top level"
+    );
+    assert_eq!(
+        type_error(&externals, "import math; import img; print max();"),
+        "E0024: max is ambiguous, it could be math.max or img.max"
+    );
+    for input in [
+        "var f = math.sqrt;",
+        "print math.sqrt;",
+        "fun apply(f <float;float>, x float) float { return f(x); }
+        print apply(math.sqrt, 4);",
+        "fun root() <float;float> { return math.sqrt; }",
+    ]
+    .iter()
+    {
+        assert_eq!(
+            type_error(&externals, input),
+            "E0019: external functions can only be called",
+            "{}",
+            input
+        );
+    }
+}
+
+#[test]
+fn external_constants() {
+    let input = "
+        enum Direction = left | right;
+        import math;
+        fun half() float {
+            return WIDTH / 2;
+        }
+        {
+            print half();
+            print DEBUG;
+            print TITLE;
+            print START == right();
+            print floor(PI * 100) == floor(math.PI * 100);
+        }
+    ";
+    let expected = "320.0\nfalse\ncanvas\ntrue\ntrue\n";
+    assert_eq!(
+        test_script(host_externals(Rc::default()), input, expected),
+        0
+    );
+    assert_eq!(run(&host_externals(Rc::default()), input).output, expected);
+
+    let externals = host_externals(Rc::default());
+    assert_eq!(
+        type_error(&externals, "print WIDTH + TITLE;"),
+        "E0008: type of left operand (float) doesn't match type of right (str)"
+    );
+    assert_eq!(
+        type_error(&externals, "WIDTH = 2;"),
+        "E0007: can't assign to global variable WIDTH"
+    );
+    assert_eq!(
+        type_error(&externals, "import math; math.PI = 3;"),
+        "E0007: can't assign to global variable math.PI"
+    );
+}
@@ -0,0 +1,163 @@
+use imglang::*;
+use std::fs;
+
+fn format(source: &str) -> String {
+    format_source(&source.to_string()).unwrap()
+}
+
+#[test]
+fn scripts_stay_formatted() {
+    for entry in fs::read_dir("tests/scripts").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "input") {
+            let source = String::from_utf8(fs::read(&path).unwrap()).unwrap();
+            // scripts testing parser errors can't be formatted
+            if let Ok(formatted) = format_source(&source) {
+                assert_eq!(format(&formatted), formatted, "{:?}", path);
+            }
+        }
+    }
+}
+
+#[test]
+fn spacing_and_indentation() {
+    assert_eq!(
+        format("fun  fib(n float)float{if(n<2){return n;}return fib(n-1)+fib(n-2);}"),
+        "fun fib(n float) float {
+  if (n < 2) {
+    return n;
+  }
+  return fib(n - 1) + fib(n - 2);
+}
+"
+    );
+}
+
+#[test]
+fn comments_and_blank_lines_kept() {
+    let source = "// header
+
+var a = 1;  // one
+
+
+{ // block
+  print a;
+  // end of block
+}
+";
+    assert_eq!(
+        format(source),
+        "// header
+
+var a = 1;  // one
+
+{  // block
+  print a;
+  // end of block
+}
+"
+    );
+}
+
+#[test]
+fn parentheses_only_where_needed() {
+    assert_eq!(
+        format("print ((a + b) * c) - (d - e) - -(f);"),
+        "print (a + b) * c - (d - e) - -f;\n"
+    );
+    assert_eq!(
+        format("var g = (fun(x float) float x)(3) | h;"),
+        "var g = (fun(x float) float x)(3) | h;\n"
+    );
+}
+
+#[test]
+fn imports_and_module_names() {
+    assert_eq!(
+        format("import  math ;print math . sqrt(2);"),
+        "import math;\nprint math.sqrt(2);\n"
+    );
+}
+
+#[test]
+fn long_calls_wrapped() {
+    assert_eq!(
+        format("someFunction(firstArgument, secondArgument, thirdArgument, fourthArgument, fifth);"),
+        "someFunction(
+  firstArgument,
+  secondArgument,
+  thirdArgument,
+  fourthArgument,
+  fifth,
+);
+"
+    );
+    assert_eq!(format("f(a, b);"), "f(a, b);\n");
+}
+
+#[test]
+fn comment_before_else() {
+    let source = "if (a) {
+  print 1;
+} // after if
+// before else
+else {
+  print 2;
+}
+";
+    assert_eq!(
+        format(source),
+        "if (a) {
+  print 1;
+}  // after if
+// before else
+else {
+  print 2;
+}
+"
+    );
+}
+
+#[test]
+fn comments_between_parameters() {
+    assert_eq!(
+        format("fun f(a float, // first\n  b float) {\n  print a;\n}\n"),
+        "fun f(
+  a float,  // first
+  b float,
+) {
+  print a;
+}
+"
+    );
+}
+
+#[test]
+fn comments_between_arguments() {
+    assert_eq!(
+        format("print f(1, // one\n  2);\nprint g(\n  // none\n);\n"),
+        "print f(
+  1,  // one
+  2,
+);
+print g(
+  // none
+);
+"
+    );
+}
+
+#[test]
+fn comment_inside_expression() {
+    // the comments would end up after the statement or inside the block
+    let sources = [
+        ("var a = 1 + // one\n  2;\n", 12),
+        ("if (a) // moves\n{\n  print a;\n}\n", 7),
+    ];
+    for (source, start) in sources.iter() {
+        match format_source(&source.to_string()) {
+            Err(FormatError::Comment(span)) => assert_eq!(span.start, *start),
+            result => panic!("{:?}", result),
+        }
+    }
+}
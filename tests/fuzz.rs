@@ -0,0 +1,90 @@
+use imglang::*;
+use std::env;
+use std::panic;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// Number of programs generated, override for longer runs.
+const SEEDS_VAR: &str = "IMGLANG_FUZZ_SEEDS";
+const DEFAULT_SEEDS: u64 = 500;
+/// Generated programs are small, anything slower is stuck in a loop.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Runs one generated program through the whole pipeline, returning what
+/// went wrong.
+fn check_program(source: &String) -> Result<(), String> {
+    let mut lexer = Lexer::new(source).map_err(|e| format!("lexer error: {:?}", e))?;
+    let mut ast = parse(&mut lexer).map_err(|e| format!("parser error: {:?}", e))?;
+    TypeChecker::annotate_types(&mut ast, None).map_err(|e| format!("type error: {:?}", e))?;
+
+    let mut vm = VM::new(Compiler::compile(&ast, None), None);
+    let mut output: Vec<u8> = vec![];
+    let error = vm.run(&mut output).err();
+    let leaked = vm.heap_ptr().count_objects();
+    if leaked > 0 {
+        return Err(format!("{} heap objects leaked", leaked));
+    }
+    let vm = Execution {
+        output: String::from_utf8(output).unwrap(),
+        error,
+    };
+    let interpreter = execute_ast(&ast, None);
+    if vm != interpreter {
+        return Err(format!(
+            "vm and interpreter differ\n--- vm\n{:?}\n--- interpreter\n{:?}",
+            vm, interpreter
+        ));
+    }
+    Ok(())
+}
+
+// A program that doesn't finish in time is reported, but its thread can't be
+// stopped and keeps running until the test binary exits.
+fn check_seed(seed: u64) -> Result<(), String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let source = generate_program(seed);
+        let result = panic::catch_unwind(|| check_program(&source));
+        let result = match result {
+            Ok(result) => result,
+            Err(payload) => Err(match payload.downcast_ref::<String>() {
+                Some(message) => format!("panicked: {}", message),
+                None => format!("panicked: {:?}", payload.downcast_ref::<&str>()),
+            }),
+        };
+        sender.send(result).unwrap();
+    });
+    match receiver.recv_timeout(TIMEOUT) {
+        Ok(result) => result,
+        Err(_) => Err(format!("did not finish within {:?}", TIMEOUT)),
+    }
+}
+
+#[test]
+fn generated_programs() {
+    let seeds = env::var(SEEDS_VAR)
+        .ok()
+        .and_then(|seeds| seeds.parse().ok())
+        .unwrap_or(DEFAULT_SEEDS);
+    let failures: Vec<String> = (0..seeds)
+        .filter_map(|seed| {
+            check_seed(seed)
+                .err()
+                .map(|error| format!("seed {}: {}", seed, error))
+        })
+        .collect();
+    assert!(
+        failures.is_empty(),
+        "{} of {} programs failed, `imglang generate <seed>` prints one\n{}",
+        failures.len(),
+        seeds,
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn same_seed_same_program() {
+    assert_eq!(generate_program(7), generate_program(7));
+    assert_ne!(generate_program(7), generate_program(8));
+}
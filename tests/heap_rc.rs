@@ -10,7 +10,7 @@ fn run_script(input: &'static str) -> VM {
     let mut output: Vec<u8> = vec![];
 
     let mut vm = VM::new(chunks, None);
-    vm.run(&mut output);
+    vm.run(&mut output).unwrap();
     vm
 }
 
@@ -171,3 +171,58 @@ fn closure_variables_freed_correctly() {
     );
     assert_eq!(vm.heap_ptr().count_objects(), 0);
 }
+
+fn run_failing_script(input: &'static str) -> VM<'static> {
+    let mut lexer = Lexer::new(&input.to_string()).unwrap();
+    let mut ast = parse(&mut lexer).unwrap();
+
+    TypeChecker::annotate_types(&mut ast, None).unwrap();
+    let chunks = Compiler::compile(&ast, None);
+
+    let mut output: Vec<u8> = vec![];
+
+    let mut vm = VM::new(chunks, None);
+    assert!(vm.run(&mut output).is_err());
+    vm
+}
+
+#[test]
+fn count_objects_failed_function() {
+    let vm = run_failing_script(
+        r#"
+        fun take(s str, n float) float {
+            return n;
+        }
+        fun check(s str) float {
+            var label = s;
+            assert false;
+            return 1;
+        }
+        {
+            print take("temporary", check("argument"));
+        }
+    "#,
+    );
+    assert_eq!(vm.heap_ptr().count_objects(), 0);
+}
+
+#[test]
+fn count_objects_failed_closure() {
+    let vm = run_failing_script(
+        r#"
+        fun take(s str, n float) float {
+            return n;
+        }
+        {
+            var t = "outer";
+            var k = 1;
+            var f = fun[k](x float) float {
+                assert x < k;
+                return x;
+            };
+            print take(t, f(2));
+        }
+    "#,
+    );
+    assert_eq!(vm.heap_ptr().count_objects(), 0);
+}
@@ -0,0 +1,83 @@
+use imglang::*;
+
+fn annotated(input: &'static str, externals: Option<&Externals>) -> Ast {
+    let mut lexer = Lexer::new(&input.to_string()).unwrap();
+    let mut ast = parse(&mut lexer).unwrap();
+    TypeChecker::annotate_types(&mut ast, externals).unwrap();
+    ast
+}
+
+#[test]
+fn same_results_as_the_vm() {
+    let ast = annotated(
+        "fun makeCounter(start float) <;float>* {
+  return fun[start]() float {
+    var current = start + 1;
+    start = current;
+    return current;
+  };
+}
+var counter = makeCounter(0);
+var alias = counter;
+counter();
+print alias();
+var other = makeCounter(10);
+print other();
+assert_eq(counter(), 4);",
+        None,
+    );
+    let vm = execute_vm(&ast, None);
+    assert_eq!(vm.output, "2.0\n11.0\n");
+    assert_eq!(
+        vm.error.as_ref().unwrap().message,
+        "assertion failed: counter() == 4\n  left: 3.0\n right: 4.0"
+    );
+    assert_eq!(execute_ast(&ast, None), vm);
+}
+
+#[test]
+fn switch_runs_the_matching_case() {
+    let ast = annotated(
+        "fun show(n float) {
+  switch (n) {
+    1 { print \"one\"; }
+    2 { print \"two\"; }
+    * { print \"many\"; }
+  }
+}
+show(2);
+show(1);
+show(7);",
+        None,
+    );
+    assert_eq!(execute_ast(&ast, None).output, "two\none\nmany\n");
+}
+
+#[test]
+fn externals_are_called() {
+    let mut externals = Externals::new();
+    externals.add_function(
+        "shout".to_string(),
+        ExternalFunction {
+            args_t: vec![AstType::String, AstType::Float],
+            ret_t: AstType::String,
+            dispatch: Box::new(|context: &mut ExternalContext| {
+                let times: f64 = context.stack.pop();
+                let adr: HeapAdr = context.stack.pop();
+                let shouted = context
+                    .heap
+                    .get_string_ref(adr)
+                    .unwrap()
+                    .repeat(times as usize);
+                context.heap.decrease_rc(adr);
+                let adr = context.heap.add_object(Obj::String(shouted));
+                context.stack.push(adr);
+                Ok(())
+            }),
+        },
+    );
+    let ast = annotated("print shout(\"hey\", 2);", Some(&externals));
+    let vm = execute_vm(&ast, Some(&externals));
+    assert_eq!(vm.output, "heyhey\n");
+    assert_eq!(execute_ast(&ast, Some(&externals)), vm);
+}
@@ -192,6 +192,24 @@ fn comments_ignored_to_newline() {
     }
 }
 
+#[test]
+fn comments_kept_as_trivia() {
+    let (_, comments) = scan_with_comments(&"// test\r\n. // test 123".to_string()).unwrap();
+    assert_eq!(
+        comments,
+        vec![
+            Comment {
+                text: "// test".to_string(),
+                span: Span::new(0, 7),
+            },
+            Comment {
+                text: "// test 123".to_string(),
+                span: Span::new(11, 22),
+            },
+        ]
+    );
+}
+
 #[test]
 fn literals() {
     let mut tokens = scan(&"identifier \"string\" 123.456".to_string())
@@ -343,3 +361,29 @@ fn identifier_with_keyword_prefix() {
         assert_eq!(token, tokens.next());
     }
 }
+
+#[test]
+fn identifier_with_underscores() {
+    let mut tokens = scan(&"_unused snake_case".to_string()).unwrap().into_iter();
+    let expected = vec![
+        Some(Token {
+            start: 0,
+            end: 7,
+            t: TokenType::Identifier("_unused".to_string()),
+        }),
+        Some(Token {
+            start: 8,
+            end: 18,
+            t: TokenType::Identifier("snake_case".to_string()),
+        }),
+        Some(Token {
+            start: 18,
+            end: 18,
+            t: TokenType::Eof,
+        }),
+        None,
+    ];
+    for token in expected.into_iter() {
+        assert_eq!(token, tokens.next());
+    }
+}
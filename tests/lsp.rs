@@ -0,0 +1,201 @@
+use imglang::*;
+use std::io::Cursor;
+
+const URI: &str = "file:///test.img";
+
+fn message(text: &str) -> Json {
+    Json::parse(text).unwrap()
+}
+
+fn open(server: &mut LanguageServer, text: &str) -> Json {
+    let open = Json::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("method", "textDocument/didOpen".into()),
+        (
+            "params",
+            Json::object(vec![(
+                "textDocument",
+                Json::object(vec![("uri", URI.into()), ("text", text.into())]),
+            )]),
+        ),
+    ]);
+    server.handle(&open).remove(0)
+}
+
+fn request(server: &mut LanguageServer, method: &str, line: usize, character: usize) -> Json {
+    let request = message(&format!(
+        r#"{{"jsonrpc":"2.0","id":1,"method":"{}","params":{{"textDocument":{{"uri":"{}"}},"position":{{"line":{},"character":{}}}}}}}"#,
+        method, URI, line, character
+    ));
+    server.handle(&request).remove(0).get("result").clone()
+}
+
+#[test]
+fn json_roundtrip() {
+    let text = r#"{"a":[1,2.5,-3],"b":"q\"\\\né😀","c":null,"d":true}"#;
+    let json = Json::parse(text).unwrap();
+    assert_eq!(json.get("b").as_str(), Some("q\"\\\né😀"));
+    assert_eq!(Json::parse(&json.to_string()), Some(json));
+    assert_eq!(Json::parse("{\"a\":}"), None);
+}
+
+#[test]
+fn diagnostics_published() {
+    let mut server = LanguageServer::new(None);
+    let published = open(&mut server, "var a = 1;\nvar b = a + \"x\";\n");
+    assert_eq!(
+        published.get("method").as_str(),
+        Some("textDocument/publishDiagnostics")
+    );
+    let diagnostics = published.get("params").get("diagnostics").as_array().unwrap();
+    assert_eq!(diagnostics.len(), 2);
+    let error = &diagnostics[1];
+    assert_eq!(error.get("code").as_str(), Some("E0008"));
+    assert_eq!(error.get("severity").as_f64(), Some(1.0));
+    assert_eq!(
+        error.get("range").to_string(),
+        r#"{"start":{"line":1,"character":8},"end":{"line":1,"character":15}}"#
+    );
+    assert_eq!(diagnostics[0].get("code").as_str(), Some("W0001"));
+}
+
+#[test]
+fn hover_shows_types() {
+    let mut server = LanguageServer::new(None);
+    open(
+        &mut server,
+        "fun half(x float) float {\n  return x / 2;\n}\nvar h = half(3) > 1;\nprint h;\n",
+    );
+    let hover = |server: &mut LanguageServer, line, character| {
+        let result = request(server, "textDocument/hover", line, character);
+        result.get("contents").get("value").as_str().map(|s| s.to_string())
+    };
+    assert_eq!(
+        hover(&mut server, 1, 9).as_deref(),
+        Some("```imglang\nx: float\n```")
+    );
+    assert_eq!(
+        hover(&mut server, 0, 5).as_deref(),
+        Some("```imglang\nfun half: <float;float>\n```")
+    );
+    assert_eq!(
+        hover(&mut server, 3, 16).as_deref(),
+        Some("```imglang\nbool\n```")
+    );
+    assert_eq!(
+        hover(&mut server, 4, 6).as_deref(),
+        Some("```imglang\nh: bool\n```")
+    );
+}
+
+#[test]
+fn go_to_definition() {
+    let mut server = LanguageServer::new(None);
+    open(
+        &mut server,
+        "enum State = on | off;\nfun f(s State) {}\nf(off());\nvar a = 1;\nprint a;\n",
+    );
+    let range = |result: Json| result.get("range").get("start").to_string();
+    assert_eq!(
+        range(request(&mut server, "textDocument/definition", 2, 0)),
+        r#"{"line":1,"character":4}"#
+    );
+    assert_eq!(
+        range(request(&mut server, "textDocument/definition", 2, 3)),
+        r#"{"line":0,"character":18}"#
+    );
+    assert_eq!(
+        range(request(&mut server, "textDocument/definition", 4, 6)),
+        r#"{"line":3,"character":4}"#
+    );
+}
+
+#[test]
+fn completion_of_names_in_scope() {
+    let mut externals = Externals::new();
+    externals.add_function(
+        "clock".to_string(),
+        ExternalFunction {
+            args_t: vec![],
+            ret_t: AstType::Float,
+            dispatch: Box::new(|context: &mut ExternalContext| {
+                context.stack.push(0.0);
+                Ok(())
+            }),
+        },
+    );
+    let mut server = LanguageServer::new(Some(&externals));
+    open(
+        &mut server,
+        "var a = 1;\nfun f(arg float) {\n  var inner = arg;\n  \n}\nvar b = 2;\n",
+    );
+    let result = request(&mut server, "textDocument/completion", 3, 2);
+    let labels: Vec<&str> = result
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|item| item.get("kind").as_f64() != Some(14.0))
+        .map(|item| item.get("label").as_str().unwrap())
+        .collect();
+    assert_eq!(labels, vec!["inner", "arg", "a", "f", "clock"]);
+}
+
+#[test]
+fn framed_session() {
+    let messages = [
+        r#"{"jsonrpc":"2.0","id":0,"method":"initialize","params":{}}"#,
+        r#"{"jsonrpc":"2.0","id":1,"method":"shutdown"}"#,
+        r#"{"jsonrpc":"2.0","method":"exit"}"#,
+    ];
+    let input: String = messages
+        .iter()
+        .map(|m| format!("Content-Length: {}\r\n\r\n{}", m.len(), m))
+        .collect();
+    let mut output = vec![];
+    let mut server = LanguageServer::new(None);
+    let code = server.run(&mut Cursor::new(input), &mut output);
+    assert_eq!(code, 0);
+
+    let mut output = Cursor::new(output);
+    let initialized = read_message(&mut output).unwrap().unwrap();
+    let capabilities = initialized.get("result").get("capabilities");
+    assert_eq!(capabilities.get("hoverProvider"), &Json::Bool(true));
+    let shutdown = read_message(&mut output).unwrap().unwrap();
+    assert_eq!(shutdown.get("id").as_f64(), Some(1.0));
+    assert_eq!(read_message(&mut output).unwrap(), None);
+}
+
+#[test]
+fn documents_that_dont_parse() {
+    let mut server = LanguageServer::new(None);
+    open(&mut server, "var count = 1;\nprint count;\n");
+    let published = open(&mut server, "var\nvar count = 1;\nprint count;\n");
+    let diagnostics = published.get("params").get("diagnostics").as_array().unwrap();
+    assert_eq!(diagnostics[0].get("code").as_str(), Some("E0003"));
+    // the last ast that parsed has the spans of the old text
+    assert_eq!(request(&mut server, "textDocument/hover", 2, 7), Json::Null);
+    assert_eq!(request(&mut server, "textDocument/definition", 2, 7), Json::Null);
+    let result = request(&mut server, "textDocument/completion", 2, 0);
+    assert!(result
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|item| item.get("label").as_str() == Some("count")));
+
+    for (text, code) in [
+        ("fun f() <", "E0003"),
+        ("enum Shape = none | circle loat;", "E0004"),
+    ]
+    .iter()
+    {
+        let published = open(&mut server, text);
+        let diagnostics = published.get("params").get("diagnostics").as_array().unwrap();
+        assert!(
+            diagnostics
+                .iter()
+                .any(|d| d.get("code").as_str() == Some(*code)),
+            "{}",
+            text
+        );
+    }
+}
@@ -1 +1,53 @@
 use imglang::*;
+
+fn parser_errors(input: &'static str) -> Vec<(String, Span)> {
+    let mut lexer = Lexer::new(&input.to_string()).unwrap();
+    let error = parse(&mut lexer).unwrap_err();
+    parser_diagnostics(error)
+        .into_iter()
+        .map(|d| (d.message, d.span))
+        .collect()
+}
+
+#[test]
+fn malformed_arguments_are_errors() {
+    let errors = parser_errors("fun f(a float, 1) { print a; }");
+    assert_eq!(
+        errors,
+        vec![(
+            "on token Float(1.0): expected argument name".to_string(),
+            Span::new(15, 16)
+        )]
+    );
+    let errors = parser_errors("var f = fun[1]() {};");
+    assert_eq!(
+        errors,
+        vec![(
+            "on token Float(1.0): expected name of captured variable".to_string(),
+            Span::new(12, 13)
+        )]
+    );
+}
+
+#[test]
+fn recovers_at_declarations() {
+    let errors = parser_errors(
+        "fun f() {\n  var a = ;\n  print a\n  var b = 2;\n}\nfun g() { return 1 }\nprint 1;\nprint",
+    );
+    let spans: Vec<Span> = errors.into_iter().map(|(_, span)| span).collect();
+    assert_eq!(
+        spans,
+        vec![
+            Span::new(20, 21),
+            Span::new(34, 37),
+            Span::new(66, 67),
+            Span::new(82, 82)
+        ]
+    );
+}
+
+#[test]
+fn skips_failed_blocks() {
+    let errors = parser_errors("if (true) { print 1 } else { print ; }\nwhile (true) {}");
+    assert_eq!(errors.len(), 1);
+}
@@ -0,0 +1,87 @@
+use imglang::*;
+
+fn eval_lines(repl: &mut Repl, lines: &[&'static str]) -> String {
+    let mut output: Vec<u8> = vec![];
+    for line in lines.iter() {
+        repl.eval(&line.to_string(), &mut output).unwrap();
+    }
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn definitions_persist_between_inputs() {
+    let mut repl = Repl::new(None);
+    let output = eval_lines(
+        &mut repl,
+        &[
+            "var a = 2;",
+            "fun double(x float) float { return x * 2; }",
+            "enum State = on | off;",
+            "var b = double(a);",
+            "print b;",
+            "print on() == off();",
+        ],
+    );
+    assert_eq!(output, "4.0\nfalse\n");
+}
+
+#[test]
+fn expression_results_are_printed() {
+    let mut repl = Repl::new(None);
+    let output = eval_lines(
+        &mut repl,
+        &["var a = 2;", "a * 3", "a == 2;", "\"string\"", "a = 5;"],
+    );
+    assert_eq!(output, "6.0\ntrue\nstring\n");
+}
+
+#[test]
+fn failed_input_is_discarded() {
+    let mut repl = Repl::new(None);
+    let mut output: Vec<u8> = vec![];
+    repl.eval(&"var a = 1;".to_string(), &mut output).unwrap();
+    assert!(repl
+        .eval(&"var b = 2; var c = b + true;".to_string(), &mut output)
+        .is_err());
+    assert!(repl.eval(&"print b;".to_string(), &mut output).is_err());
+    repl.eval(&"print a;".to_string(), &mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), "1.0\n");
+}
+
+#[test]
+fn objects_freed_between_inputs() {
+    let mut repl = Repl::new(None);
+    eval_lines(
+        &mut repl,
+        &["var a = \"kept\";", "{ var b = \"freed\"; }", "\"printed\""],
+    );
+    assert_eq!(repl.heap_ptr().count_objects(), 1);
+}
+
+#[test]
+fn unbalanced_braces_need_more_input() {
+    assert!(needs_more_input(&"fun a() {".to_string()));
+    assert!(needs_more_input(&"if (true) {\n  {\n  }\n".to_string()));
+    assert!(needs_more_input(&"print \"unterminated".to_string()));
+    assert!(!needs_more_input(&"fun a() {\n}".to_string()));
+    assert!(!needs_more_input(&"print 1;".to_string()));
+}
+
+#[test]
+fn failed_assertion_discards_input() {
+    let mut repl = Repl::new(None);
+    let mut output: Vec<u8> = vec![];
+    repl.eval(&"var a = 1;".to_string(), &mut output).unwrap();
+    assert!(matches!(
+        repl.eval(
+            &"var b = 2; var s = \"discarded\"; assert a == b;".to_string(),
+            &mut output
+        ),
+        Err(ReplError::Runtime(_))
+    ));
+    assert_eq!(repl.heap_ptr().count_objects(), 0);
+    assert!(repl.eval(&"print b;".to_string(), &mut output).is_err());
+    repl.eval(&"var c = 3; print a + c;".to_string(), &mut output)
+        .unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), "4.0\n");
+}
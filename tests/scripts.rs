@@ -1,72 +1,137 @@
 use imglang::*;
+use std::env;
 use std::fs;
-
-fn load_files(prefix: &'static str) -> (String, String) {
-    let input = String::from_utf8(fs::read(format!("{}.input", prefix)).unwrap()).unwrap();
-    let output = String::from_utf8(fs::read(format!("{}.output", prefix)).unwrap()).unwrap();
-    (input, output)
+use std::path::{Path, PathBuf};
+
+/// Set to overwrite the expected files with the actual results, after a
+/// change in behaviour was checked to be intended.
+const BLESS_VAR: &str = "IMGLANG_BLESS";
+
+/// What running a script produced: its stdout, and the rendered errors if
+/// it failed to compile or stopped with a runtime error. Scripts that
+/// compile are run on both the vm and the tree-walking interpreter.
+struct Outcome {
+    output: String,
+    errors: String,
+    executions: Option<(Execution, Execution)>,
 }
 
-fn test_script(prefix: &'static str) {
-    let (input, expected) = load_files(prefix);
-
-    let mut lexer = Lexer::new(&input).unwrap();
-    let mut ast = parse(&mut lexer).unwrap();
-
-    TypeChecker::annotate_types(&mut ast, None).unwrap();
-    let chunks = Compiler::compile(&ast, None);
-
-    let mut output: Vec<u8> = vec![];
-
-    let mut vm = VM::new(chunks, None);
-    vm.run(&mut output);
-
-    let output = String::from_utf8(output).unwrap();
-
-    assert_eq!(expected, output);
+fn run_script(name: &str, source: &String) -> Outcome {
+    let mut errors: Vec<u8> = vec![];
+    let mut lexer = match Lexer::new(source) {
+        Ok(lexer) => lexer,
+        Err(error) => {
+            render_diagnostics(name, source, &lexer_diagnostics(error), &mut errors);
+            return Outcome {
+                output: String::new(),
+                errors: String::from_utf8(errors).unwrap(),
+                executions: None,
+            };
+        }
+    };
+    let mut ast = match parse(&mut lexer) {
+        Ok(ast) => ast,
+        Err(error) => {
+            render_diagnostics(name, source, &parser_diagnostics(error), &mut errors);
+            return Outcome {
+                output: String::new(),
+                errors: String::from_utf8(errors).unwrap(),
+                executions: None,
+            };
+        }
+    };
+    if let Err(error) = TypeChecker::annotate_types(&mut ast, None) {
+        render_diagnostics(name, source, &type_diagnostics(error), &mut errors);
+        return Outcome {
+            output: String::new(),
+            errors: String::from_utf8(errors).unwrap(),
+            executions: None,
+        };
+    }
+
+    let vm = execute_vm(&ast, None);
+    if let Some(error) = &vm.error {
+        render_runtime_error(name, source, error, &mut errors);
+    }
+    Outcome {
+        output: vm.output.clone(),
+        errors: String::from_utf8(errors).unwrap(),
+        executions: Some((vm, execute_ast(&ast, None))),
+    }
 }
 
-#[test]
-fn variables() {
-    test_script("tests/scripts/variables");
-}
-
-#[test]
-fn if_else() {
-    test_script("tests/scripts/if_else");
-}
-
-#[test]
-fn test_while() {
-    test_script("tests/scripts/while");
-}
-
-#[test]
-fn functions() {
-    test_script("tests/scripts/functions");
-}
-
-#[test]
-fn fibonacci() {
-    test_script("tests/scripts/fibonacci");
-}
-
-#[test]
-fn nested_scopes() {
-    test_script("tests/scripts/nested_scopes");
-}
-
-#[test]
-fn closure_counter() {
-    test_script("tests/scripts/counter");
-}
-
-#[test]
-fn closure_alternate() {
-    test_script("tests/scripts/alternate");
+/// Compares `actual` with the contents of `path`, a missing file counts as
+/// empty. When blessing the file is written instead, or removed when
+/// `actual` is empty.
+fn check_expected(path: &Path, actual: &str, bless: bool, failures: &mut Vec<String>) {
+    if bless {
+        if actual.is_empty() {
+            if path.exists() {
+                fs::remove_file(path).unwrap();
+            }
+        } else {
+            fs::write(path, actual).unwrap();
+        }
+        return;
+    }
+    let expected = fs::read_to_string(path).unwrap_or_default();
+    if expected != actual {
+        failures.push(format!(
+            "{}\n--- expected\n{}\n--- actual\n{}",
+            path.display(),
+            expected,
+            actual
+        ));
+    }
 }
 
+/// Runs every `.input` script in `tests/scripts`, comparing its stdout with
+/// the `.output` file and its errors with the `.error` file next to it, and
+/// the vm with the tree-walking interpreter.
 #[test]
-fn test_enum() {
-    test_script("tests/scripts/enum");
+fn scripts() {
+    let bless = env::var_os(BLESS_VAR).is_some();
+    let mut inputs: Vec<PathBuf> = fs::read_dir("tests/scripts")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "input"))
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty());
+
+    let mut failures = vec![];
+    for input in inputs.iter() {
+        let source = fs::read_to_string(input).unwrap();
+        let name = input.file_name().unwrap().to_string_lossy();
+        let outcome = run_script(&name, &source);
+        if let Some((vm, interpreter)) = &outcome.executions {
+            if vm != interpreter {
+                failures.push(format!(
+                    "{} runs differently on the vm and the interpreter\n--- vm\n{:?}\n--- interpreter\n{:?}",
+                    input.display(),
+                    vm,
+                    interpreter
+                ));
+            }
+        }
+        check_expected(
+            &input.with_extension("output"),
+            &outcome.output,
+            bless,
+            &mut failures,
+        );
+        check_expected(
+            &input.with_extension("error"),
+            &outcome.errors,
+            bless,
+            &mut failures,
+        );
+    }
+    assert!(
+        failures.is_empty(),
+        "{} mismatched, rerun with {}=1 to update the expected files\n\n{}",
+        failures.len(),
+        BLESS_VAR,
+        failures.join("\n")
+    );
 }
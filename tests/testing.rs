@@ -0,0 +1,114 @@
+use imglang::*;
+
+fn test_results(input: &'static str) -> (String, Vec<TestResult>) {
+    let source = input.to_string();
+    let mut lexer = Lexer::new(&source).unwrap();
+    let mut ast = parse(&mut lexer).unwrap();
+    TypeChecker::annotate_types(&mut ast, None).unwrap();
+    (source, run_tests(&ast, None))
+}
+
+fn type_error_codes(input: &'static str) -> Vec<ErrorCode> {
+    let source = input.to_string();
+    let mut lexer = Lexer::new(&source).unwrap();
+    let mut ast = parse(&mut lexer).unwrap();
+    let error = TypeChecker::annotate_types(&mut ast, None).unwrap_err();
+    type_diagnostics(error)
+        .into_iter()
+        .map(|d| d.code)
+        .collect()
+}
+
+#[test]
+fn tests_run_in_isolation() {
+    let (_, results) = test_results(
+        "fun double(x float) float { return x * 2; }
+var unused = 1;
+test \"passes\" {
+  var a = double(2);
+  assert a == 4;
+  assert_eq(double(a), 8);
+}
+test \"fails\" {
+  print \"before\";
+  assert_eq(double(1), 3);
+  print \"after\";
+}
+print \"not run\";",
+    );
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].name, "passes");
+    assert!(results[0].passed());
+    assert_eq!(results[1].output, "before\n");
+    let error = results[1].error.as_ref().unwrap();
+    assert_eq!(
+        error.message,
+        "assertion failed: double(1) == 3\n  left: 2.0\n right: 3.0"
+    );
+}
+
+#[test]
+fn failures_point_at_the_assertion() {
+    let (source, results) = test_results(
+        "enum Light = on | off;
+test \"strings\" {
+  assert_eq(\"a\", \"a\");
+}
+test \"enums\" {
+  assert_eq(on(), off());
+}",
+    );
+    let mut output: Vec<u8> = vec![];
+    assert!(!report_tests("test", &source, &results, &mut output));
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "running 2 tests
+test strings ... ok
+test enums ... FAILED
+
+failures:
+
+---- enums ----
+error: assertion failed: on() == off()
+ --> test:6:3
+  |
+6 |   assert_eq(on(), off());
+  |   ^^^^^^^^^^^^^^^^^^^^^^^
+
+test result: FAILED. 1 passed; 1 failed
+"
+    );
+}
+
+#[test]
+fn tests_are_type_checked() {
+    assert_eq!(
+        type_error_codes("test \"a\" {} test \"a\" {}"),
+        vec![ErrorCode::NameInUse]
+    );
+    assert_eq!(
+        type_error_codes("var a = 1; test \"a\" { print a; }"),
+        vec![ErrorCode::UndefinedName]
+    );
+    assert_eq!(
+        type_error_codes("fun f() { test \"a\" {} }"),
+        vec![ErrorCode::NotTopLevel]
+    );
+    assert_eq!(
+        type_error_codes("test \"a\" { assert_eq(1, true); assert 1; }"),
+        vec![ErrorCode::MismatchedTypes, ErrorCode::NonBoolCondition]
+    );
+}
+
+#[test]
+fn failed_assertion_stops_the_script() {
+    let source = "var a = 1; assert a == 2; print a;".to_string();
+    let mut lexer = Lexer::new(&source).unwrap();
+    let mut ast = parse(&mut lexer).unwrap();
+    TypeChecker::annotate_types(&mut ast, None).unwrap();
+    let mut vm = VM::new(Compiler::compile(&ast, None), None);
+    let mut output: Vec<u8> = vec![];
+    let error = vm.run(&mut output).unwrap_err();
+    assert_eq!(error.span.start, 11);
+    assert!(output.is_empty());
+}